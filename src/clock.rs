@@ -0,0 +1,74 @@
+//! Clock used for timestamps.
+//!
+//! The Raspberry Pi has no real-time clock, so after a cold boot without internet its system
+//! time is wrong. Once a better time source is available, such as the GSM network time, the
+//! clock is synchronized with it, and the offset to the system time is applied to every
+//! timestamp from then on.
+
+use std::fmt;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+
+use time::{self, Timespec, Tm};
+
+/// Offset from the system time to the current time, in milliseconds.
+static OFFSET: AtomicI64 = AtomicI64::new(0);
+/// Current time source, as its index in `TimeSource::from_index()`.
+static SOURCE: AtomicUsize = AtomicUsize::new(0);
+
+/// Source of the current time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSource {
+    /// The system clock, unsynchronized.
+    System,
+    /// The time reported by the GSM network.
+    Network,
+}
+
+impl TimeSource {
+    /// Gets the time source stored with the given index.
+    fn from_index(index: usize) -> TimeSource {
+        match index {
+            1 => TimeSource::Network,
+            _ => TimeSource::System,
+        }
+    }
+
+    /// Gets the index used to store the time source.
+    fn index(&self) -> usize {
+        match *self {
+            TimeSource::System => 0,
+            TimeSource::Network => 1,
+        }
+    }
+}
+
+impl fmt::Display for TimeSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TimeSource::System => write!(f, "system clock"),
+            TimeSource::Network => write!(f, "GSM network"),
+        }
+    }
+}
+
+/// Gets the current time in UTC, from the current time source.
+pub fn now_utc() -> Tm {
+    let offset = time::Duration::milliseconds(OFFSET.load(Ordering::SeqCst));
+    time::at_utc(time::get_time() + offset)
+}
+
+/// Gets the current time source.
+pub fn source() -> TimeSource {
+    TimeSource::from_index(SOURCE.load(Ordering::SeqCst))
+}
+
+/// Synchronizes the clock with the given current time, taken from the given source.
+///
+/// Returns the correction applied to the previous time, in milliseconds.
+pub fn synchronize(now: Timespec, source: TimeSource) -> i64 {
+    let offset = (now - time::get_time()).num_milliseconds();
+    let previous = OFFSET.swap(offset, Ordering::SeqCst);
+    SOURCE.store(source.index(), Ordering::SeqCst);
+
+    offset - previous
+}
@@ -0,0 +1,774 @@
+//! Runtime configuration.
+//!
+//! The configuration is read at startup from a small subset of TOML: `[section]` headers and
+//! `key = value` lines, where values are double-quoted strings, integers or booleans, and `#`
+//! starts a comment. Every value is validated when loading, so that a bad configuration is
+//! detected before the flight instead of when the setting is first used.
+
+use std::{fs, io, fmt};
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use std::error::Error as StdError;
+
+use serial::{self, PortSettings};
+
+/// Configuration file.
+pub const CONFIG_FILE: &'static str = "config.toml";
+/// Serial devices that can be configured, in `[serial.<device>]` sections.
+const SERIAL_DEVICES: &'static [&'static str] = &["gsm", "gps", "radio"];
+/// Baud rates supported by the serial devices.
+const SERIAL_BAUD_RATES: &'static [usize] = &[1200, 2400, 4800, 9600, 19200, 38400, 57600,
+                                                115200];
+
+/// Error loading the configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The configuration file could not be read.
+    Io(io::Error),
+    /// A line of the configuration file is not valid.
+    Syntax { line: usize, message: String },
+    /// A setting has an invalid value, is unknown or is missing.
+    Invalid { key: String, message: String },
+}
+
+impl ConfigError {
+    /// Creates an invalid setting error.
+    fn invalid<K: Into<String>, M: Into<String>>(key: K, message: M) -> ConfigError {
+        ConfigError::Invalid {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> ConfigError {
+        ConfigError::Io(e)
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref e) => write!(f, "error reading the configuration: {}", e),
+            ConfigError::Syntax { line, ref message } => {
+                write!(f, "invalid configuration in line {}: {}", line, message)
+            }
+            ConfigError::Invalid { ref key, ref message } => {
+                write!(f, "invalid configuration for '{}': {}", key, message)
+            }
+        }
+    }
+}
+
+impl StdError for ConfigError {
+    fn description(&self) -> &str {
+        match *self {
+            ConfigError::Io(_) => "error reading the configuration",
+            ConfigError::Syntax { .. } => "invalid configuration syntax",
+            ConfigError::Invalid { .. } => "invalid configuration setting",
+        }
+    }
+}
+
+/// Value of a setting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+impl Value {
+    /// Parses a value from the right side of a `key = value` line.
+    fn parse(value: &str) -> Option<Value> {
+        if let Some(quoted) = value.strip_prefix('"') {
+            quoted.strip_suffix('"')
+                .filter(|s| !s.contains('"'))
+                .map(|s| Value::String(s.to_owned()))
+        } else if value == "true" || value == "false" {
+            Some(Value::Boolean(value == "true"))
+        } else {
+            value.parse().ok().map(Value::Integer)
+        }
+    }
+}
+
+/// Settings read from the configuration file, as `section.key` and value, in order.
+struct Settings {
+    values: Vec<(String, Value)>,
+}
+
+impl Settings {
+    /// Takes a string setting, if present.
+    fn take_string(&mut self, key: &str) -> Result<Option<String>, ConfigError> {
+        match self.take(key) {
+            Some(Value::String(s)) => Ok(Some(s)),
+            Some(_) => Err(ConfigError::invalid(key, "expected a string")),
+            None => Ok(None),
+        }
+    }
+
+    /// Takes an integer setting, if present.
+    fn take_integer(&mut self, key: &str) -> Result<Option<i64>, ConfigError> {
+        match self.take(key) {
+            Some(Value::Integer(i)) => Ok(Some(i)),
+            Some(_) => Err(ConfigError::invalid(key, "expected an integer")),
+            None => Ok(None),
+        }
+    }
+
+    /// Takes a boolean setting, if present.
+    fn take_boolean(&mut self, key: &str) -> Result<Option<bool>, ConfigError> {
+        match self.take(key) {
+            Some(Value::Boolean(b)) => Ok(Some(b)),
+            Some(_) => Err(ConfigError::invalid(key, "expected a boolean")),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes a setting, returning its value.
+    fn take(&mut self, key: &str) -> Option<Value> {
+        self.values.iter().position(|(k, _)| k == key).map(|i| self.values.remove(i).1)
+    }
+
+    /// Checks that all the settings were used, to detect misspelled keys.
+    fn finish(self) -> Result<(), ConfigError> {
+        match self.values.into_iter().next() {
+            Some((key, _)) => Err(ConfigError::invalid(key, "unknown setting")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for Settings {
+    type Err = ConfigError;
+    fn from_str(s: &str) -> Result<Settings, ConfigError> {
+        let mut section = String::new();
+        let mut values: Vec<(String, Value)> = Vec::new();
+
+        for (i, line) in s.lines().enumerate() {
+            let syntax_error = |message: &str| {
+                ConfigError::Syntax {
+                    line: i + 1,
+                    message: message.to_owned(),
+                }
+            };
+            // Comments can only start outside strings.
+            let line = match line.find('#') {
+                Some(pos) if line[..pos].matches('"').count() % 2 == 0 => &line[..pos],
+                _ => line,
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                section = try!(header.strip_suffix(']')
+                        .ok_or_else(|| syntax_error("unterminated section header")))
+                    .trim()
+                    .to_owned();
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if !key.trim().is_empty() => (key.trim(), value.trim()),
+                _ => return Err(syntax_error("expected 'key = value'")),
+            };
+            let key = if section.is_empty() {
+                key.to_owned()
+            } else {
+                format!("{}.{}", section, key)
+            };
+            let value = try!(Value::parse(value).ok_or_else(|| syntax_error("invalid value")));
+
+            if values.iter().any(|(k, _)| *k == key) {
+                return Err(syntax_error(&format!("duplicate setting '{}'", key)));
+            }
+            values.push((key, value));
+        }
+
+        Ok(Settings { values: values })
+    }
+}
+
+/// Serial port settings of a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerialConfig {
+    device: &'static str,
+    path: String,
+    settings: PortSettings,
+    timeout: u64,
+}
+
+impl SerialConfig {
+    /// Reads and validates the serial port settings of the given device, if it is configured.
+    fn from_settings(settings: &mut Settings,
+                     device: &'static str)
+                     -> Result<Option<SerialConfig>, ConfigError> {
+        let key = |name: &str| format!("serial.{}.{}", device, name);
+        let path = try!(settings.take_string(&key("path")));
+        let baud = try!(settings.take_integer(&key("baud")));
+        let data_bits = try!(settings.take_integer(&key("data_bits")));
+        let parity = try!(settings.take_string(&key("parity")));
+        let stop_bits = try!(settings.take_integer(&key("stop_bits")));
+        let flow_control = try!(settings.take_string(&key("flow_control")));
+        let timeout = try!(settings.take_integer(&key("timeout")));
+
+        let path = match path {
+            Some(path) => path,
+            None => {
+                return if baud.is_none() && data_bits.is_none() && parity.is_none() &&
+                          stop_bits.is_none() &&
+                          flow_control.is_none() &&
+                          timeout.is_none() {
+                    Ok(None)
+                } else {
+                    Err(ConfigError::invalid(key("path"), "missing setting"))
+                };
+            }
+        };
+        if !path.starts_with('/') {
+            return Err(ConfigError::invalid(key("path"), "must be an absolute path"));
+        }
+
+        let baud = baud.unwrap_or(9600);
+        if !SERIAL_BAUD_RATES.iter().any(|&b| b as i64 == baud) {
+            return Err(ConfigError::invalid(key("baud"),
+                                            format!("unsupported baud rate, must be one of {:?}",
+                                                    SERIAL_BAUD_RATES)));
+        }
+        let char_size = match data_bits.unwrap_or(8) {
+            5 => serial::Bits5,
+            6 => serial::Bits6,
+            7 => serial::Bits7,
+            8 => serial::Bits8,
+            _ => return Err(ConfigError::invalid(key("data_bits"), "must be between 5 and 8")),
+        };
+        let parity = match parity.as_deref().unwrap_or("none") {
+            "none" => serial::ParityNone,
+            "odd" => serial::ParityOdd,
+            "even" => serial::ParityEven,
+            _ => {
+                return Err(ConfigError::invalid(key("parity"),
+                                                "must be \"none\", \"odd\" or \"even\""))
+            }
+        };
+        let stop_bits = match stop_bits.unwrap_or(1) {
+            1 => serial::Stop1,
+            2 => serial::Stop2,
+            _ => return Err(ConfigError::invalid(key("stop_bits"), "must be 1 or 2")),
+        };
+        let flow_control = match flow_control.as_deref().unwrap_or("none") {
+            "none" => serial::FlowNone,
+            "software" => serial::FlowSoftware,
+            "hardware" => serial::FlowHardware,
+            _ => {
+                return Err(ConfigError::invalid(key("flow_control"),
+                                                "must be \"none\", \"software\" or \
+                                                 \"hardware\""))
+            }
+        };
+        let timeout = timeout.unwrap_or(100);
+        if !(1..=10_000).contains(&timeout) {
+            return Err(ConfigError::invalid(key("timeout"),
+                                            "must be between 1 and 10000 milliseconds"));
+        }
+
+        Ok(Some(SerialConfig {
+            device: device,
+            path: path,
+            settings: PortSettings {
+                baud_rate: serial::BaudRate::from_speed(baud as usize),
+                char_size: char_size,
+                parity: parity,
+                stop_bits: stop_bits,
+                flow_control: flow_control,
+            },
+            timeout: timeout as u64,
+        }))
+    }
+
+    /// Gets the name of the device, as in its configuration section.
+    pub fn get_device(&self) -> &str {
+        self.device
+    }
+
+    /// Gets the path of the serial port.
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    /// Gets the baud rate, data bits, parity, stop bits and flow control.
+    pub fn get_settings(&self) -> &PortSettings {
+        &self.settings
+    }
+
+    /// Gets the maximum time a read will block.
+    pub fn get_timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout)
+    }
+}
+
+/// GSM module settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GsmConfig {
+    power_retries: u8,
+    reset_pin: u8,
+    pin: Option<String>,
+    iccid: Option<String>,
+    reject_calls: bool,
+}
+
+impl GsmConfig {
+    /// Reads and validates the GSM module settings.
+    fn from_settings(settings: &mut Settings) -> Result<GsmConfig, ConfigError> {
+        let power_retries = try!(settings.take_integer("gsm.power_retries")).unwrap_or(3);
+        if !(1..=10).contains(&power_retries) {
+            return Err(ConfigError::invalid("gsm.power_retries", "must be between 1 and 10"));
+        }
+
+        // The power key and status lines use wiringPi pins 7 and 21.
+        let reset_pin = try!(settings.take_integer("gsm.reset_pin")).unwrap_or(22);
+        if !(0..=31).contains(&reset_pin) || reset_pin == 7 || reset_pin == 21 {
+            return Err(ConfigError::invalid("gsm.reset_pin",
+                                            "must be a wiringPi pin from 0 to 31, other than \
+                                             the power key (7) and status (21) pins"));
+        }
+
+        let pin = try!(settings.take_string("gsm.pin")).filter(|p| !p.is_empty());
+        if let Some(ref pin) = pin {
+            if pin.len() < 4 || pin.len() > 8 || !pin.chars().all(|c| c.is_ascii_digit()) {
+                return Err(ConfigError::invalid("gsm.pin", "must be 4 to 8 digits"));
+            }
+        }
+
+        let iccid = try!(settings.take_string("gsm.iccid")).filter(|i| !i.is_empty());
+        if let Some(ref iccid) = iccid {
+            // ICCIDs are 19 or 20 digits long, and some modems pad them with an 'F'.
+            if iccid.len() < 19 || iccid.len() > 20 ||
+               !iccid.chars().all(|c| c.is_ascii_digit() || c == 'F' || c == 'f') {
+                return Err(ConfigError::invalid("gsm.iccid", "must be 19 or 20 digits"));
+            }
+        }
+
+        let reject_calls = try!(settings.take_boolean("gsm.reject_calls")).unwrap_or(true);
+
+        Ok(GsmConfig {
+            power_retries: power_retries as u8,
+            reset_pin: reset_pin as u8,
+            pin: pin,
+            iccid: iccid,
+            reject_calls: reject_calls,
+        })
+    }
+
+    /// Gets the number of power key pulses tried before resetting the modem.
+    pub fn get_power_retries(&self) -> u8 {
+        self.power_retries
+    }
+
+    /// Gets the wiringPi pin connected to the reset line of the modem.
+    pub fn get_reset_pin(&self) -> u8 {
+        self.reset_pin
+    }
+
+    /// Gets the PIN of the SIM card, if it is locked.
+    pub fn get_pin(&self) -> Option<&str> {
+        self.pin.as_deref()
+    }
+
+    /// Gets the ICCID of the SIM card expected in the modem, if it should be checked.
+    pub fn get_iccid(&self) -> Option<&str> {
+        self.iccid.as_deref()
+    }
+
+    /// Checks if incoming calls should be rejected.
+    pub fn is_rejecting_calls(&self) -> bool {
+        self.reject_calls
+    }
+}
+
+/// GPRS bearer settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GprsConfig {
+    apn: String,
+    user: Option<String>,
+    password: Option<String>,
+    bearer_profile: u8,
+}
+
+impl GprsConfig {
+    /// Reads and validates the GPRS settings.
+    fn from_settings(settings: &mut Settings) -> Result<GprsConfig, ConfigError> {
+        let apn = match try!(settings.take_string("gprs.apn")) {
+            Some(apn) => apn,
+            None => return Err(ConfigError::invalid("gprs.apn", "missing setting")),
+        };
+        try!(validate_at_string("gprs.apn", &apn, 1, 64));
+
+        let user = try!(settings.take_string("gprs.user")).filter(|u| !u.is_empty());
+        if let Some(ref user) = user {
+            try!(validate_at_string("gprs.user", user, 1, 32));
+        }
+        let password = try!(settings.take_string("gprs.password")).filter(|p| !p.is_empty());
+        if let Some(ref password) = password {
+            try!(validate_at_string("gprs.password", password, 1, 32));
+        }
+
+        // The SIM800 supports bearer profiles 1 to 3.
+        let bearer_profile = try!(settings.take_integer("gprs.bearer_profile")).unwrap_or(1);
+        if !(1..=3).contains(&bearer_profile) {
+            return Err(ConfigError::invalid("gprs.bearer_profile", "must be between 1 and 3"));
+        }
+
+        Ok(GprsConfig {
+            apn: apn,
+            user: user,
+            password: password,
+            bearer_profile: bearer_profile as u8,
+        })
+    }
+
+    /// Gets the access point name.
+    pub fn get_apn(&self) -> &str {
+        &self.apn
+    }
+
+    /// Gets the user name for the access point, if any.
+    pub fn get_user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Gets the password for the access point, if any.
+    pub fn get_password(&self) -> Option<&str> {
+        self.password.as_deref()
+    }
+
+    /// Gets the bearer profile identifier.
+    pub fn get_bearer_profile(&self) -> u8 {
+        self.bearer_profile
+    }
+}
+
+/// Telemetry upload settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TelemetryConfig {
+    url: Option<String>,
+    interval: u64,
+}
+
+impl TelemetryConfig {
+    /// Reads and validates the telemetry settings.
+    fn from_settings(settings: &mut Settings) -> Result<TelemetryConfig, ConfigError> {
+        let url = try!(settings.take_string("telemetry.url")).filter(|u| !u.is_empty());
+        if let Some(ref url) = url {
+            try!(validate_at_string("telemetry.url", url, 1, 255));
+            // The HTTPS support of the SIM800 is not reliable enough to be used in flight.
+            if url.len() <= "http://".len() || !url.starts_with("http://") {
+                return Err(ConfigError::invalid("telemetry.url", "must be an http:// URL"));
+            }
+        }
+
+        let interval = try!(settings.take_integer("telemetry.interval")).unwrap_or(60);
+        if !(10..=3600).contains(&interval) {
+            return Err(ConfigError::invalid("telemetry.interval",
+                                            "must be between 10 and 3600 seconds"));
+        }
+
+        Ok(TelemetryConfig {
+            url: url,
+            interval: interval as u64,
+        })
+    }
+
+    /// Gets the URL of the ground server, if telemetry upload is enabled.
+    pub fn get_url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    /// Gets the time between telemetry uploads.
+    pub fn get_interval(&self) -> Duration {
+        Duration::from_secs(self.interval)
+    }
+}
+
+/// Ground command uplink settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UplinkConfig {
+    whitelist: Vec<String>,
+}
+
+impl UplinkConfig {
+    /// Reads and validates the uplink settings.
+    fn from_settings(settings: &mut Settings) -> Result<UplinkConfig, ConfigError> {
+        let whitelist = try!(settings.take_string("uplink.whitelist")).unwrap_or_default();
+        let whitelist = whitelist.split(',')
+            .map(|n| n.trim().to_owned())
+            .filter(|n| !n.is_empty())
+            .collect::<Vec<_>>();
+        if whitelist.is_empty() {
+            return Err(ConfigError::invalid("uplink.whitelist", "at least one number needed"));
+        }
+        for number in &whitelist {
+            // International format, as the numbers are reported by the modem.
+            let digits = number.strip_prefix('+').unwrap_or("");
+            if digits.len() < 6 || digits.len() > 15 ||
+               !digits.chars().all(|c| c.is_ascii_digit()) {
+                return Err(ConfigError::invalid("uplink.whitelist",
+                                                format!("invalid number '{}', must be '+' \
+                                                         followed by 6 to 15 digits",
+                                                        number)));
+            }
+        }
+
+        Ok(UplinkConfig { whitelist: whitelist })
+    }
+
+    /// Gets the phone numbers of the ground team, allowed to send commands to the probe.
+    pub fn get_whitelist(&self) -> &[String] {
+        &self.whitelist
+    }
+}
+
+/// OpenStratos configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    serial: Vec<SerialConfig>,
+    gsm: GsmConfig,
+    gprs: GprsConfig,
+    uplink: UplinkConfig,
+    telemetry: TelemetryConfig,
+}
+
+impl Config {
+    /// Loads and validates the configuration from the given file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let mut contents = String::new();
+        try!(try!(fs::File::open(path)).read_to_string(&mut contents));
+        contents.parse()
+    }
+
+    /// Gets the serial port settings of the given device, if it is configured.
+    pub fn get_serial(&self, device: &str) -> Option<&SerialConfig> {
+        self.serial.iter().find(|s| s.device == device)
+    }
+
+    /// Gets the serial port settings of the GSM module, which are always configured.
+    pub fn get_gsm_serial(&self) -> &SerialConfig {
+        self.get_serial("gsm").expect("GSM serial port checked when loading the configuration")
+    }
+
+    /// Gets the GSM module settings.
+    pub fn get_gsm(&self) -> &GsmConfig {
+        &self.gsm
+    }
+
+    /// Gets the GPRS settings.
+    pub fn get_gprs(&self) -> &GprsConfig {
+        &self.gprs
+    }
+
+    /// Gets the ground command uplink settings.
+    pub fn get_uplink(&self) -> &UplinkConfig {
+        &self.uplink
+    }
+
+    /// Gets the telemetry upload settings.
+    pub fn get_telemetry(&self) -> &TelemetryConfig {
+        &self.telemetry
+    }
+}
+
+impl FromStr for Config {
+    type Err = ConfigError;
+    fn from_str(s: &str) -> Result<Config, ConfigError> {
+        let mut settings = try!(s.parse::<Settings>());
+        let mut serial = Vec::new();
+        for device in SERIAL_DEVICES {
+            if let Some(config) = try!(SerialConfig::from_settings(&mut settings, device)) {
+                serial.push(config);
+            }
+        }
+        if !serial.iter().any(|s| s.device == "gsm") {
+            return Err(ConfigError::invalid("serial.gsm.path", "missing setting"));
+        }
+
+        let config = Config {
+            serial: serial,
+            gsm: try!(GsmConfig::from_settings(&mut settings)),
+            gprs: try!(GprsConfig::from_settings(&mut settings)),
+            uplink: try!(UplinkConfig::from_settings(&mut settings)),
+            telemetry: try!(TelemetryConfig::from_settings(&mut settings)),
+        };
+        try!(settings.finish());
+
+        Ok(config)
+    }
+}
+
+/// Checks that a string can be sent inside a quoted AT command parameter, and that its length
+/// is within the given limits.
+fn validate_at_string(key: &str, value: &str, min: usize, max: usize) -> Result<(), ConfigError> {
+    if value.len() < min || value.len() > max {
+        return Err(ConfigError::invalid(key,
+                                        format!("must be between {} and {} characters long",
+                                                min,
+                                                max)));
+    }
+    if !value.chars().all(|c| c.is_ascii_graphic() && c != '"' && c != '\\') {
+        return Err(ConfigError::invalid(key, "only printable ASCII without quotes allowed"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a minimal configuration with the given settings added.
+    fn config(settings: &str) -> Result<Config, ConfigError> {
+        config_with_gprs("apn = \"internet\"", settings)
+    }
+
+    /// Parses a minimal configuration with the given `[gprs]` section and settings added.
+    fn config_with_gprs(gprs: &str, settings: &str) -> Result<Config, ConfigError> {
+        format!("[serial.gsm]\npath = \"/dev/ttyAMA0\"\n[gprs]\n{}\n\
+                 [uplink]\nwhitelist = \"+34600000001\"\n{}",
+                gprs,
+                settings)
+            .parse()
+    }
+
+    /// Checks that the given settings are rejected because of the given key.
+    fn assert_invalid(settings: &str, invalid_key: &str) {
+        assert_rejected(config(settings), settings, invalid_key);
+    }
+
+    /// Checks that a configuration was rejected because of the given key.
+    fn assert_rejected(result: Result<Config, ConfigError>, settings: &str, invalid_key: &str) {
+        match result {
+            Err(ConfigError::Invalid { ref key, .. }) if key == invalid_key => {}
+            result => panic!("unexpected result for '{}': {:?}", settings, result.err()),
+        }
+    }
+
+    #[test]
+    fn example_file() {
+        let config = Config::load(CONFIG_FILE).unwrap();
+        assert_eq!(config.get_gsm().get_reset_pin(), 22);
+    }
+
+    #[test]
+    fn reset_pin() {
+        assert_eq!(config("").unwrap().get_gsm().get_reset_pin(), 22);
+        assert_eq!(config("[gsm]\nreset_pin = 0").unwrap().get_gsm().get_reset_pin(), 0);
+        assert_eq!(config("[gsm]\nreset_pin = 31").unwrap().get_gsm().get_reset_pin(), 31);
+
+        assert_invalid("[gsm]\nreset_pin = 7", "gsm.reset_pin");
+        assert_invalid("[gsm]\nreset_pin = 21", "gsm.reset_pin");
+        assert_invalid("[gsm]\nreset_pin = 32", "gsm.reset_pin");
+        assert_invalid("[gsm]\nreset_pin = -1", "gsm.reset_pin");
+        assert_invalid("[gsm]\nreset_pin = \"22\"", "gsm.reset_pin");
+    }
+
+    #[test]
+    fn gprs() {
+        let default = config("").unwrap();
+        let gprs = default.get_gprs();
+        assert_eq!(gprs.get_apn(), "internet");
+        assert_eq!((gprs.get_user(), gprs.get_password()), (None, None));
+        assert_eq!(gprs.get_bearer_profile(), 1);
+
+        let credentials = config("[gprs]\nuser = \"stratos\"\npassword = \"s3cr3t!\"\n\
+                                  bearer_profile = 3")
+            .unwrap();
+        let gprs = credentials.get_gprs();
+        assert_eq!((gprs.get_user(), gprs.get_password()), (Some("stratos"), Some("s3cr3t!")));
+        assert_eq!(gprs.get_bearer_profile(), 3);
+
+        // Empty credentials mean that they are not needed.
+        let empty = config("[gprs]\nuser = \"\"\npassword = \"\"").unwrap();
+        assert_eq!((empty.get_gprs().get_user(), empty.get_gprs().get_password()), (None, None));
+
+        assert_invalid("[gprs]\nuser = \"sky net\"", "gprs.user");
+        assert_invalid("[gprs]\nuser = 1234", "gprs.user");
+        assert_invalid("[gprs]\npassword = \"a\\\\b\"", "gprs.password");
+        assert_invalid(&format!("[gprs]\npassword = \"{}\"", "x".repeat(33)),
+                       "gprs.password");
+        assert_invalid("[gprs]\nbearer_profile = 0", "gprs.bearer_profile");
+        assert_invalid("[gprs]\nbearer_profile = 4", "gprs.bearer_profile");
+        assert_invalid("[gprs]\nproxy = \"10.0.0.1\"", "gprs.proxy");
+    }
+
+    #[test]
+    fn apn() {
+        for &apn in &["", "apn = \"\"", "apn = \"my apn\"", "apn = \"a\\\\b\"", "apn = 1"] {
+            assert_rejected(config_with_gprs(apn, ""), apn, "gprs.apn");
+        }
+        let apn = format!("apn = \"{}\"", "a".repeat(65));
+        assert_rejected(config_with_gprs(&apn, ""), &apn, "gprs.apn");
+
+        let apn = format!("apn = \"{}\"", "a".repeat(64));
+        assert_eq!(config_with_gprs(&apn, "").unwrap().get_gprs().get_apn().len(), 64);
+    }
+
+    #[test]
+    fn serial() {
+        let default = config("").unwrap();
+        let gsm = default.get_gsm_serial();
+        assert_eq!((gsm.get_device(), gsm.get_path()), ("gsm", "/dev/ttyAMA0"));
+        assert_eq!(*gsm.get_settings(),
+                   PortSettings {
+                       baud_rate: serial::Baud9600,
+                       char_size: serial::Bits8,
+                       parity: serial::ParityNone,
+                       stop_bits: serial::Stop1,
+                       flow_control: serial::FlowNone,
+                   });
+        assert_eq!(gsm.get_timeout(), Duration::from_millis(100));
+        assert!(default.get_serial("gps").is_none());
+
+        let gps = config("[serial.gps]\npath = \"/dev/ttyUSB0\"\nbaud = 115200\n\
+                          data_bits = 7\nparity = \"even\"\nstop_bits = 2\n\
+                          flow_control = \"hardware\"\ntimeout = 10000")
+            .unwrap();
+        let gps = gps.get_serial("gps").unwrap();
+        assert_eq!(gps.get_path(), "/dev/ttyUSB0");
+        assert_eq!(*gps.get_settings(),
+                   PortSettings {
+                       baud_rate: serial::Baud115200,
+                       char_size: serial::Bits7,
+                       parity: serial::ParityEven,
+                       stop_bits: serial::Stop2,
+                       flow_control: serial::FlowHardware,
+                   });
+        assert_eq!(gps.get_timeout(), Duration::from_secs(10));
+
+        assert_invalid("[serial.gsm]\nbaud = 14400", "serial.gsm.baud");
+        assert_invalid("[serial.gsm]\nbaud = \"9600\"", "serial.gsm.baud");
+        assert_invalid("[serial.gsm]\ndata_bits = 9", "serial.gsm.data_bits");
+        assert_invalid("[serial.gsm]\nparity = \"mark\"", "serial.gsm.parity");
+        assert_invalid("[serial.gsm]\nstop_bits = 0", "serial.gsm.stop_bits");
+        assert_invalid("[serial.gsm]\nflow_control = \"xon\"", "serial.gsm.flow_control");
+        assert_invalid("[serial.gsm]\ntimeout = 0", "serial.gsm.timeout");
+        assert_invalid("[serial.gsm]\ntimeout = 10001", "serial.gsm.timeout");
+        assert_invalid("[serial.gsm]\nrts = true", "serial.gsm.rts");
+        assert_invalid("[serial.radio]\npath = \"ttyUSB1\"", "serial.radio.path");
+        // The settings of a device need its path.
+        assert_invalid("[serial.gps]\nbaud = 4800", "serial.gps.path");
+        assert_invalid("[serial.modem]\npath = \"/dev/ttyUSB1\"", "serial.modem.path");
+    }
+
+    #[test]
+    fn missing_gsm_serial() {
+        let settings = "[gprs]\napn = \"internet\"\n[uplink]\nwhitelist = \"+34600000001\"\n\
+                        [serial.gps]\npath = \"/dev/ttyUSB0\"";
+        assert_rejected(settings.parse(), settings, "serial.gsm.path");
+    }
+}
@@ -0,0 +1,274 @@
+//! GPRS bearer sessions.
+//!
+//! Cell location, HTTP and any other feature needing a data connection run inside a bearer
+//! session. The session opens the bearer when created, and closes it when dropped, so that the
+//! bearer is never left open after an error.
+
+use std::ops::{Deref, DerefMut};
+
+use log::LogLevel::*;
+
+use Coordinates;
+use super::{Gsm, GsmError, Transport};
+use super::parse::CellLocation;
+
+/// Open GPRS bearer, closed when dropped.
+///
+/// The session gives access to the modem, so that commands needing the bearer can be sent while
+/// it is open.
+pub struct BearerSession<'a, T: Transport + 'a> {
+    gsm: &'a mut Gsm<T>,
+    open: bool,
+}
+
+impl<'a, T: Transport> BearerSession<'a, T> {
+    /// Gets the location of the serving cell from the cell location service.
+    pub fn locate(&mut self) -> Result<Coordinates, GsmError> {
+        let command = format!("AT+CIPGSMLOC=1,{}", self.gprs.get_bearer_profile());
+        let response = try!(self.send_command(&command));
+        let location = if response.is_ok() {
+            self.parse_information::<CellLocation>(&response, "+CIPGSMLOC:")
+        } else {
+            Err(response.to_error(&command))
+        };
+        let coordinates = match location {
+            Ok(ref l) if l.get_coordinates().is_some() => {
+                self.logger.log(&format!("Cell location found at {} UTC.",
+                                         l.get_date_time().unwrap_or("unknown time")),
+                                Info);
+                l.get_coordinates().unwrap()
+            }
+            _ => {
+                let error = match location {
+                    Ok(l) => {
+                        GsmError::unexpected(command.as_str(),
+                                             format!("location code {} ({})",
+                                                     l.get_code(),
+                                                     l.get_code_description()))
+                    }
+                    Err(e) => e,
+                };
+                self.logger.log(&format!("Bad response getting location on '{}' response: {}",
+                                         command,
+                                         error),
+                                Error);
+                return Err(error);
+            }
+        };
+
+        Ok(coordinates)
+    }
+
+    /// Closes the bearer, returning the error if it could not be closed.
+    pub fn close(mut self) -> Result<(), GsmError> {
+        self.teardown()
+    }
+
+    /// Closes the bearer if it might be open.
+    fn teardown(&mut self) -> Result<(), GsmError> {
+        if !self.open {
+            return Ok(());
+        }
+        self.open = false;
+
+        let command = format!("AT+SAPBR=0,{}", self.gsm.gprs.get_bearer_profile());
+        match self.gsm.send_command_ok(&command) {
+            Ok(_) => {
+                self.gsm.logger.log("GPRS bearer closed.", Info);
+                Ok(())
+            }
+            Err(e) => {
+                self.gsm.logger.log("Error turning GPRS down.", Error);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<'a, T: Transport> Deref for BearerSession<'a, T> {
+    type Target = Gsm<T>;
+
+    fn deref(&self) -> &Gsm<T> {
+        self.gsm
+    }
+}
+
+impl<'a, T: Transport> DerefMut for BearerSession<'a, T> {
+    fn deref_mut(&mut self) -> &mut Gsm<T> {
+        self.gsm
+    }
+}
+
+impl<'a, T: Transport> Drop for BearerSession<'a, T> {
+    fn drop(&mut self) {
+        // Errors are already logged, and there is nothing else to do with them here.
+        let _ = self.teardown();
+    }
+}
+
+impl<T: Transport> Gsm<T> {
+    /// Opens the GPRS bearer, attaching to the GPRS service first.
+    pub fn open_bearer<'a>(&'a mut self) -> Result<BearerSession<'a, T>, GsmError> {
+        if !self.is_on() {
+            error!("Trying to open the GPRS bearer, but GSM was off.");
+            return Err(GsmError::PowerOff);
+        }
+
+        let profile = self.gprs.get_bearer_profile();
+        self.logger.log(&format!("Opening GPRS bearer {} with APN {}…",
+                                 profile,
+                                 self.gprs.get_apn()),
+                        Info);
+        try!(self.send_command_ok("AT+CGATT=1"));
+        try!(self.send_command_ok(&format!("AT+SAPBR=3,{},\"CONTYPE\",\"GPRS\"", profile)));
+        let apn = self.gprs.get_apn().to_owned();
+        try!(self.send_command_ok(&format!("AT+SAPBR=3,{},\"APN\",\"{}\"", profile, apn)));
+        if let Some(user) = self.gprs.get_user().map(|u| u.to_owned()) {
+            try!(self.send_command_ok(&format!("AT+SAPBR=3,{},\"USER\",\"{}\"", profile, user)));
+        }
+        if let Some(password) = self.gprs.get_password().map(|p| p.to_owned()) {
+            // The password is kept out of the logs.
+            try!(self.send_command_ok_masked(&format!("AT+SAPBR=3,{},\"PWD\",\"{}\"",
+                                                      profile,
+                                                      password),
+                                             &format!("AT+SAPBR=3,{},\"PWD\",\"****\"",
+                                                      profile)));
+        }
+
+        // From now on the bearer might be open even if the command fails, so the session closes
+        // it when dropped.
+        let mut session = BearerSession {
+            gsm: self,
+            open: true,
+        };
+        try!(session.send_command_ok(&format!("AT+SAPBR=1,{}", profile)));
+        session.logger.log("GPRS bearer open.", Info);
+
+        Ok(session)
+    }
+
+    /// Checks that the GPRS bearer can be opened with the configured settings, closing it
+    /// afterwards.
+    pub fn check_bearer(&mut self) -> Result<(), GsmError> {
+        let session = try!(self.open_bearer());
+        session.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use std::io::Read;
+    use std::time::Duration;
+
+    use logger::Logger;
+    use super::super::{Gsm, GsmError, Transport};
+    use super::super::emulator::Emulator;
+    use super::super::transport::ScriptedTransport;
+
+    /// GPRS settings with credentials and a bearer profile other than the default one.
+    const CREDENTIALS: &'static str = "[gprs]\nuser = \"stratos\"\npassword = \"s3cr3t!\"\n\
+                                       bearer_profile = 2\n";
+
+    /// Checks if the emulated bearer 1 is open.
+    fn is_open<T: Transport>(gsm: &mut Gsm<T>) -> bool {
+        let response = gsm.send_command_ok("AT+SAPBR=2,1").unwrap();
+        response.information("+SAPBR:").unwrap().starts_with("+SAPBR: 1,1,")
+    }
+
+    #[test]
+    fn locate() {
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        {
+            let mut bearer = gsm.open_bearer().unwrap();
+            assert!(is_open(&mut bearer));
+            let coordinates = bearer.locate().unwrap();
+            assert_eq!(coordinates.get_latitude(), 40.4168);
+            assert_eq!(coordinates.get_longitude(), -3.7038);
+            bearer.close().unwrap();
+        }
+        assert!(!is_open(&mut gsm));
+    }
+
+    #[test]
+    fn closed_when_dropped() {
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        {
+            let mut bearer = gsm.open_bearer().unwrap();
+            assert!(is_open(&mut bearer));
+        }
+        assert!(!is_open(&mut gsm));
+    }
+
+    #[test]
+    fn closed_after_error() {
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CIPGSMLOC=1,1", &["+CIPGSMLOC: 601", "OK"]);
+        let mut gsm = Gsm::simulated(emulator, "");
+        let result = gsm.open_bearer().and_then(|mut bearer| bearer.locate());
+        match result {
+            Err(GsmError::UnexpectedResponse { ref response, .. }) => {
+                assert_eq!(response, "location code 601 (network error)")
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(!is_open(&mut gsm));
+    }
+
+    #[test]
+    fn open_without_network() {
+        let mut emulator = Emulator::new();
+        emulator.add_dropout(Duration::from_secs(0), Duration::from_secs(60));
+        let mut gsm = Gsm::simulated(emulator, "");
+        assert!(gsm.open_bearer().is_err());
+        assert!(!is_open(&mut gsm));
+    }
+
+    #[test]
+    fn open_power_off() {
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        gsm.simulated_on = false;
+        match gsm.open_bearer().err() {
+            Some(GsmError::PowerOff) => {}
+            error => panic!("unexpected error: {:?}", error),
+        };
+    }
+
+    #[test]
+    fn credentials() {
+        let mut transport = ScriptedTransport::new();
+        transport.expect("AT+CGATT=1", &["OK"])
+            .expect("AT+SAPBR=3,2,\"CONTYPE\",\"GPRS\"", &["OK"])
+            .expect("AT+SAPBR=3,2,\"APN\",\"internet\"", &["OK"])
+            .expect("AT+SAPBR=3,2,\"USER\",\"stratos\"", &["OK"])
+            .expect("AT+SAPBR=3,2,\"PWD\",\"s3cr3t!\"", &["OK"])
+            .expect("AT+SAPBR=1,2", &["OK"])
+            .expect("AT+SAPBR=0,2", &["OK"]);
+        let mut gsm = Gsm::simulated(transport, CREDENTIALS);
+        let dir = env::temp_dir().join("BearerCredentialsTest");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        gsm.command_logger = Logger::new(&dir.join("log"), "Commands", "GSMCommands").unwrap();
+
+        gsm.check_bearer().unwrap();
+        assert!(gsm.serial.is_finished());
+
+        let mut log = String::new();
+        for entry in fs::read_dir(&dir).unwrap() {
+            fs::File::open(entry.unwrap().path()).unwrap().read_to_string(&mut log).unwrap();
+        }
+        assert!(log.contains("AT+SAPBR=3,2,\"PWD\",\"****\""));
+        assert!(!log.contains("s3cr3t!"));
+    }
+
+    #[test]
+    fn check_bearer() {
+        let mut gsm = Gsm::simulated(Emulator::new(), CREDENTIALS);
+        gsm.check_bearer().unwrap();
+
+        let mut emulator = Emulator::new();
+        emulator.add_dropout(Duration::from_secs(0), Duration::from_secs(60));
+        let mut gsm = Gsm::simulated(emulator, CREDENTIALS);
+        assert!(gsm.check_bearer().is_err());
+    }
+}
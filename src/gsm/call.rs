@@ -0,0 +1,256 @@
+//! Voice calls.
+//!
+//! The probe carries no audio, so calls are only used as a beacon: a short call attempt often
+//! gets through where the coverage is too weak for an SMS, and a missed call tells the ground
+//! team that the probe is alive. Call results such as `BUSY` or `NO CARRIER` end the `ATD`
+//! response if the call cannot be placed, but afterwards they can arrive at any time, so they are
+//! received as URCs, and the call state is polled with `AT+CLCC` to know when it is ringing or
+//! answered. Incoming calls are never answered.
+
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use log::LogLevel::*;
+
+use super::{Gsm, GsmError, Transport, Urc};
+use super::parse::{CallState, CurrentCall};
+use super::response::FinalResult;
+
+/// Time between call state checks, in seconds.
+const CALL_POLL_INTERVAL: u64 = 1;
+
+/// Outcome of a call attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallOutcome {
+    /// The call was answered, and hung up right away.
+    Answered,
+    /// The phone rang until the ring time passed, and the call was hung up: a missed call.
+    Missed,
+    /// The number was busy, or rejected the call.
+    Busy,
+    /// The call was not answered, and never rang before the ring time passed.
+    NoAnswer,
+    /// The call could not be set up.
+    NoCarrier,
+    /// There was no network to place the call.
+    NoDialTone,
+}
+
+impl CallOutcome {
+    /// Gets the outcome of a call from its result code, such as `BUSY`.
+    fn from_result(result: &str) -> Option<CallOutcome> {
+        match result {
+            "BUSY" => Some(CallOutcome::Busy),
+            "NO ANSWER" => Some(CallOutcome::NoAnswer),
+            "NO CARRIER" => Some(CallOutcome::NoCarrier),
+            "NO DIALTONE" => Some(CallOutcome::NoDialTone),
+            _ => None,
+        }
+    }
+
+    /// Checks if the called phone rang, so that the call was noticed.
+    pub fn is_reached(&self) -> bool {
+        matches!(*self, CallOutcome::Answered | CallOutcome::Missed)
+    }
+}
+
+impl<T: Transport> Gsm<T> {
+    /// Calls the given number, letting it ring at most `ring_time` before hanging up.
+    ///
+    /// The call is hung up as soon as it is answered, since there is no audio.
+    pub fn dial(&mut self, number: &str, ring_time: Duration) -> Result<CallOutcome, GsmError> {
+        if !self.is_on() {
+            error!("Trying to place a call, but GSM was off.");
+            return Err(GsmError::PowerOff);
+        }
+        let digits = number.strip_prefix('+').unwrap_or(number);
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(GsmError::InvalidInput(format!("invalid phone number '{}'", number)));
+        }
+        self.logger.log(&format!("Calling {} for at most {} s…", number, ring_time.as_secs()),
+                        Info);
+
+        // Subscribing before dialing, so that no call result can be missed.
+        let urcs = self.subscribe_urcs();
+        let start = Instant::now();
+        let command = format!("ATD{};", number);
+        let outcome = match self.send_command(&command) {
+            Ok(ref response) if response.is_ok() => self.wait_for_call(&urcs, ring_time),
+            // Calls that fail right away answer with a call result instead of `OK`.
+            Ok(response) => {
+                match *response.result() {
+                    FinalResult::CallResult(ref result) => {
+                        CallOutcome::from_result(result)
+                            .ok_or_else(|| response.to_error(&command))
+                    }
+                    _ => Err(response.to_error(&command)),
+                }
+            }
+            Err(e) => Err(e),
+        };
+        if self.send_command_ok("ATH").is_err() {
+            self.logger.log("Error hanging up the call.", Error);
+        }
+
+        match outcome {
+            Ok(outcome) => {
+                self.logger.log(&format!("Call to {} finished after {} s: {:?}.",
+                                         number,
+                                         start.elapsed().as_secs(),
+                                         outcome),
+                                if outcome.is_reached() { Info } else { Warn });
+                Ok(outcome)
+            }
+            Err(e) => {
+                self.logger.log(&format!("Error calling {}: {}", number, e), Error);
+                Err(e)
+            }
+        }
+    }
+
+    /// Logs the incoming calls received since the last check, rejecting them if configured.
+    ///
+    /// Returns whether any call was received. URCs are only received while the modem is in use,
+    /// so this should be called after other commands or after polling for URCs.
+    pub fn handle_incoming_calls(&mut self) -> Result<bool, GsmError> {
+        let mut ringing = false;
+        let mut callers = Vec::new();
+        while let Ok(urc) = self.calls.try_recv() {
+            match urc {
+                Urc::Ring => ringing = true,
+                Urc::CallerId(caller) if !callers.contains(&caller) => callers.push(caller),
+                _ => {}
+            }
+        }
+        if !ringing {
+            return Ok(false);
+        }
+
+        let callers = if callers.is_empty() {
+            String::from("unknown caller")
+        } else {
+            callers.join(", ")
+        };
+        if self.config.is_rejecting_calls() {
+            self.logger.log(&format!("Rejecting incoming call from {}.", callers), Warn);
+            try!(self.send_command_ok("ATH"));
+        } else {
+            self.logger.log(&format!("Incoming call from {}.", callers), Warn);
+        }
+
+        Ok(true)
+    }
+
+    /// Waits for the result of a call, until it is answered or the ring time passes.
+    fn wait_for_call(&mut self,
+                     urcs: &Receiver<Urc>,
+                     ring_time: Duration)
+                     -> Result<CallOutcome, GsmError> {
+        let start = Instant::now();
+        let mut alerting = false;
+        loop {
+            while let Ok(urc) = urcs.try_recv() {
+                match urc {
+                    Urc::Busy => return Ok(CallOutcome::Busy),
+                    Urc::NoAnswer => return Ok(CallOutcome::NoAnswer),
+                    Urc::NoCarrier => return Ok(CallOutcome::NoCarrier),
+                    Urc::NoDialTone => return Ok(CallOutcome::NoDialTone),
+                    _ => {}
+                }
+            }
+            if start.elapsed() >= ring_time {
+                return Ok(if alerting { CallOutcome::Missed } else { CallOutcome::NoAnswer });
+            }
+
+            let response = try!(self.send_command_ok("AT+CLCC"));
+            for line in response.lines().iter().filter(|l| l.starts_with("+CLCC:")) {
+                let call: CurrentCall = try!(line.parse());
+                if call.is_outgoing() {
+                    match call.get_state() {
+                        CallState::Active => return Ok(CallOutcome::Answered),
+                        CallState::Alerting => alerting = true,
+                        _ => {}
+                    }
+                }
+            }
+            try!(self.poll_urcs(Duration::from_secs(CALL_POLL_INTERVAL)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::CallOutcome;
+    use super::super::{Gsm, GsmError};
+    use super::super::emulator::Emulator;
+
+    const NUMBER: &'static str = "+34600000001";
+
+    /// Calls the test number with the given emulator, checking that the call was hung up.
+    fn dial(emulator: Emulator, ring_time: u64) -> Result<CallOutcome, GsmError> {
+        let mut gsm = Gsm::simulated(emulator, "");
+        let outcome = gsm.dial(NUMBER, Duration::from_secs(ring_time));
+        let calls = gsm.send_command_ok("AT+CLCC").unwrap();
+        assert!(calls.lines().is_empty());
+        outcome
+    }
+
+    #[test]
+    fn missed() {
+        assert_eq!(dial(Emulator::new(), 2).unwrap(), CallOutcome::Missed);
+    }
+
+    #[test]
+    fn answered() {
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CLCC", &["+CLCC: 1,0,0,0,0,\"+34600000001\",145", "OK"]);
+        assert_eq!(dial(emulator, 10).unwrap(), CallOutcome::Answered);
+    }
+
+    #[test]
+    fn busy() {
+        let mut emulator = Emulator::new();
+        emulator.script(format!("ATD{};", NUMBER), &["BUSY"]);
+        assert_eq!(dial(emulator, 10).unwrap(), CallOutcome::Busy);
+    }
+
+    #[test]
+    fn rejected_while_ringing() {
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CLCC", &["+CLCC: 1,0,3,0,0,\"+34600000001\",145", "OK", "BUSY"]);
+        assert_eq!(dial(emulator, 10).unwrap(), CallOutcome::Busy);
+    }
+
+    #[test]
+    fn no_carrier() {
+        let mut emulator = Emulator::new();
+        emulator.script(format!("ATD{};", NUMBER), &["NO CARRIER"]);
+        assert_eq!(dial(emulator, 10).unwrap(), CallOutcome::NoCarrier);
+    }
+
+    #[test]
+    fn no_answer() {
+        let mut emulator = Emulator::new();
+        emulator.script(format!("ATD{};", NUMBER), &["OK"])
+            .script("AT+CLCC", &["OK"]);
+        assert_eq!(dial(emulator, 1).unwrap(), CallOutcome::NoAnswer);
+    }
+
+    #[test]
+    fn no_dial_tone() {
+        let mut emulator = Emulator::new();
+        emulator.add_dropout(Duration::from_secs(0), Duration::from_secs(60));
+        assert_eq!(dial(emulator, 10).unwrap(), CallOutcome::NoDialTone);
+    }
+
+    #[test]
+    fn invalid_number() {
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        match gsm.dial("600 000 001", Duration::from_secs(10)) {
+            Err(GsmError::InvalidInput(_)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
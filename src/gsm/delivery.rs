@@ -0,0 +1,267 @@
+//! SMS delivery tracking.
+//!
+//! `+CMGS` only means that the SMSC accepted the message. Outgoing messages request a status
+//! report, which the network sends in a `+CDS` URC once each segment is delivered or the SMSC
+//! gives up. Reports are matched with the recipients by message reference and number.
+
+use std::sync::mpsc::Receiver;
+
+use super::Urc;
+use super::pdu;
+use super::parse::{DeliveryStatus, ParseResponseError, StatusReport};
+use super::sms::SegmentResult;
+
+/// Delivery state of the last message sent to a recipient.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryState {
+    /// The SMSC accepted the message, and its status report has not arrived yet.
+    Sent,
+    /// All the segments of the message were delivered.
+    Delivered,
+    /// Some segment of the message could not be sent or delivered.
+    Failed,
+}
+
+/// Recipient of SMS, with the delivery state of the last message sent to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recipient {
+    number: String,
+    state: DeliveryState,
+    pending: Vec<u8>,
+}
+
+impl Recipient {
+    /// Gets the phone number of the recipient.
+    pub fn get_number(&self) -> &str {
+        &self.number
+    }
+
+    /// Gets the delivery state of the last message sent to the recipient.
+    pub fn get_state(&self) -> DeliveryState {
+        self.state
+    }
+}
+
+/// Tracker of the delivery of the SMS sent to each recipient.
+pub struct DeliveryTracker {
+    urcs: Receiver<Urc>,
+    recipients: Vec<Recipient>,
+}
+
+impl DeliveryTracker {
+    /// Creates a tracker receiving the status reports from the given URC subscription.
+    pub fn new(urcs: Receiver<Urc>) -> DeliveryTracker {
+        DeliveryTracker {
+            urcs: urcs,
+            recipients: Vec::new(),
+        }
+    }
+
+    /// Gets the recipient with the given number, if a message was sent to it.
+    pub fn get_recipient(&self, number: &str) -> Option<&Recipient> {
+        self.recipients.iter().find(|r| numbers_match(&r.number, number))
+    }
+
+    /// Starts tracking a message sent to the given number, replacing the previous one.
+    pub fn track(&mut self, number: &str, segments: &[SegmentResult]) {
+        let pending = segments.iter().filter_map(|s| s.as_ref().ok()).cloned().collect();
+        let state = if segments.iter().any(|s| s.is_err()) {
+            DeliveryState::Failed
+        } else {
+            DeliveryState::Sent
+        };
+
+        if let Some(recipient) = self.recipients
+            .iter_mut()
+            .find(|r| numbers_match(&r.number, number)) {
+            recipient.state = state;
+            recipient.pending = pending;
+        } else {
+            self.recipients.push(Recipient {
+                number: number.to_owned(),
+                state: state,
+                pending: pending,
+            });
+        }
+    }
+
+    /// Handles the status reports received since the last update, returning the recipients whose
+    /// state changed, or the error parsing each invalid report.
+    pub fn update(&mut self) -> Vec<Result<Recipient, ParseResponseError>> {
+        let mut updates = Vec::new();
+        while let Ok(urc) = self.urcs.try_recv() {
+            if let Urc::StatusReport(payload) = urc {
+                match parse_status_report(&payload) {
+                    Ok(report) => {
+                        if let Some(recipient) = self.handle_report(&report) {
+                            updates.push(Ok(recipient));
+                        }
+                    }
+                    Err(e) => updates.push(Err(e)),
+                }
+            }
+        }
+
+        updates
+    }
+
+    /// Updates the recipient of the message a status report is about, returning its new state.
+    fn handle_report(&mut self, report: &StatusReport) -> Option<Recipient> {
+        let reference = report.get_reference();
+        self.recipients
+            .iter_mut()
+            .find(|r| {
+                r.pending.contains(&reference) &&
+                (report.get_recipient().is_empty() ||
+                 numbers_match(&r.number, report.get_recipient()))
+            })
+            .and_then(|recipient| {
+                match report.get_delivery_status() {
+                    DeliveryStatus::Delivered => {
+                        recipient.pending.retain(|&r| r != reference);
+                        if recipient.pending.is_empty() && recipient.state == DeliveryState::Sent {
+                            recipient.state = DeliveryState::Delivered;
+                        }
+                    }
+                    DeliveryStatus::Pending => return None,
+                    DeliveryStatus::Failed => {
+                        recipient.pending.retain(|&r| r != reference);
+                        recipient.state = DeliveryState::Failed;
+                    }
+                }
+
+                Some(recipient.clone())
+            })
+    }
+}
+
+/// Parses the payload of a `+CDS` URC, either the text mode fields or the PDU in hexadecimal.
+fn parse_status_report(payload: &str) -> Result<StatusReport, ParseResponseError> {
+    if payload.contains(',') {
+        format!("+CDS: {}", payload).parse()
+    } else {
+        pdu::decode_status_report(payload).map_err(|e| {
+            ParseResponseError::InvalidField {
+                response: "+CDS:",
+                field: "pdu",
+                value: e.to_string(),
+            }
+        })
+    }
+}
+
+/// Checks if two phone numbers are the same, ignoring the international prefix if only one of
+/// them has it.
+pub fn numbers_match(a: &str, b: &str) -> bool {
+    let a = a.chars().filter(|c| c.is_ascii_digit()).collect::<String>();
+    let b = b.chars().filter(|c| c.is_ascii_digit()).collect::<String>();
+    let (shorter, longer) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+
+    !shorter.is_empty() && longer.ends_with(&shorter) && shorter.len() + 4 >= longer.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use super::super::GsmError;
+
+    /// Status report for reference 0xD6 to +31628870634, with the given status.
+    fn report_pdu(status: &str) -> String {
+        format!("0006D60B911326880736F41110117195514011101171957140{}", status)
+    }
+
+    /// Creates a tracker with a message to +31628870634 in the given segments.
+    fn tracker(segments: &[SegmentResult]) -> DeliveryTracker {
+        let (_, urcs) = mpsc::channel();
+        let mut tracker = DeliveryTracker::new(urcs);
+        tracker.track("+31628870634", segments);
+        tracker
+    }
+
+    #[test]
+    fn delivered_pdu_report() {
+        let mut tracker = tracker(&[Ok(0xD6)]);
+        let report = parse_status_report(&report_pdu("00")).unwrap();
+        assert_eq!(report, StatusReport::new(0xD6, "+31628870634", 0x00));
+
+        let recipient = tracker.handle_report(&report).unwrap();
+        assert_eq!(recipient.get_number(), "+31628870634");
+        assert_eq!(recipient.get_state(), DeliveryState::Delivered);
+    }
+
+    #[test]
+    fn pending_pdu_report() {
+        let mut tracker = tracker(&[Ok(0xD6)]);
+        let report = parse_status_report(&report_pdu("20")).unwrap();
+
+        assert_eq!(tracker.handle_report(&report), None);
+        assert_eq!(tracker.get_recipient("+31628870634").unwrap().get_state(),
+                   DeliveryState::Sent);
+    }
+
+    #[test]
+    fn failed_pdu_report() {
+        let mut tracker = tracker(&[Ok(0xD6)]);
+        let report = parse_status_report(&report_pdu("46")).unwrap();
+
+        let recipient = tracker.handle_report(&report).unwrap();
+        assert_eq!(recipient.get_state(), DeliveryState::Failed);
+    }
+
+    #[test]
+    fn concatenated_message_delivered_with_last_segment() {
+        let mut tracker = tracker(&[Ok(0xD5), Ok(0xD6)]);
+        let first = StatusReport::new(0xD5, "+31628870634", 0x00);
+        let second = parse_status_report(&report_pdu("00")).unwrap();
+
+        assert_eq!(tracker.handle_report(&first).unwrap().get_state(), DeliveryState::Sent);
+        assert_eq!(tracker.handle_report(&second).unwrap().get_state(),
+                   DeliveryState::Delivered);
+    }
+
+    #[test]
+    fn segment_not_sent_fails_message() {
+        let mut tracker = tracker(&[Ok(0xD6), Err(GsmError::PowerOff)]);
+        let report = parse_status_report(&report_pdu("00")).unwrap();
+
+        assert_eq!(tracker.handle_report(&report).unwrap().get_state(),
+                   DeliveryState::Failed);
+    }
+
+    #[test]
+    fn report_for_other_recipient_ignored() {
+        let mut tracker = tracker(&[Ok(0xD6)]);
+        let report = StatusReport::new(0xD6, "+34600000002", 0x00);
+
+        assert_eq!(tracker.handle_report(&report), None);
+        assert_eq!(tracker.get_recipient("0031628870634").unwrap().get_state(),
+                   DeliveryState::Sent);
+    }
+
+    #[test]
+    fn text_mode_report() {
+        let mut tracker = tracker(&[Ok(0xD6)]);
+        let report = parse_status_report("6,214,\"+31628870634\",145,\"11/01/17,15:55:04+04\",\
+                                          \"11/01/17,15:57:04+04\",0")
+            .unwrap();
+
+        assert_eq!(tracker.handle_report(&report).unwrap().get_state(),
+                   DeliveryState::Delivered);
+    }
+
+    #[test]
+    fn update_handles_received_urcs() {
+        let (sender, urcs) = mpsc::channel();
+        let mut tracker = DeliveryTracker::new(urcs);
+        tracker.track("+31628870634", &[Ok(0xD6)]);
+        sender.send(Urc::StatusReport(String::from("0006"))).unwrap();
+        sender.send(Urc::StatusReport(report_pdu("00"))).unwrap();
+
+        let updates = tracker.update();
+        assert_eq!(updates.len(), 2);
+        assert!(updates[0].is_err());
+        assert_eq!(updates[1].as_ref().unwrap().get_state(), DeliveryState::Delivered);
+    }
+}
@@ -0,0 +1,1038 @@
+//! SIM800 modem emulator.
+//!
+//! The emulator answers the AT commands used by the GSM module, so that the flight software can
+//! be run end to end without a modem attached. Responses can be scripted, and the network and
+//! battery state changes with time following the configured dropouts and battery drain, sending
+//! the under-voltage warning when the battery runs low.
+//!
+//! HTTP requests made through the modem HTTP stack are forwarded to the server in their URL, and
+//! the connections of the IP stack to their host and port, so that local stand-ins can receive
+//! them during simulations.
+
+use std::{io, mem};
+use std::io::{Read, Write};
+use std::collections::VecDeque;
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+#[cfg(any(test, feature = "real-sim"))]
+use std::thread;
+
+use time;
+
+use super::Transport;
+use super::pdu;
+
+/// GSM battery voltage below which the SIM800 warns about under-voltage, in millivolts.
+const UNDER_VOLTAGE_WARNING: f64 = 3500f64;
+/// Time the stand-ins wait for the real servers, in seconds.
+const SERVER_TIMEOUT: u64 = 10;
+/// Number of connection links of the emulated IP stack.
+const CONNECTION_LINKS: usize = 6;
+/// Number of SMS the emulated SIM card can store.
+const SMS_CAPACITY: usize = 30;
+
+/// Simulated network dropout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Dropout {
+    start: Duration,
+    duration: Duration,
+}
+
+/// Emulated HTTP service, between `AT+HTTPINIT` and `AT+HTTPTERM`.
+#[derive(Debug, Clone, Default)]
+struct HttpService {
+    url: Option<String>,
+    content_type: String,
+    data: Vec<u8>,
+    pending_data: usize,
+    response: Vec<u8>,
+}
+
+/// Connection of the emulated IP stack, forwarded to a real socket.
+#[derive(Debug)]
+enum Connection {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+/// Software SIM800 modem.
+pub struct Emulator {
+    start: Instant,
+    echo: bool,
+    text_mode: bool,
+    status_reports: bool,
+    bearer_open: bool,
+    attached: bool,
+    battery_mv: f64,
+    main_battery_mv: f64,
+    battery_drain: f64,
+    main_battery_drain: f64,
+    latitude: f64,
+    longitude: f64,
+    dropouts: Vec<Dropout>,
+    scripted: VecDeque<(String, Vec<String>)>,
+    sms_destination: Option<String>,
+    message_reference: u8,
+    inbox: Vec<(u32, bool, String, String)>,
+    http: Option<HttpService>,
+    ip_up: bool,
+    connections: Vec<Option<Connection>>,
+    pending_send: Option<(usize, usize)>,
+    send_data: Vec<u8>,
+    call: Option<String>,
+    under_voltage_warned: bool,
+    outgoing: Vec<u8>,
+    incoming: VecDeque<String>,
+    incoming_data: VecDeque<Vec<u8>>,
+}
+
+impl Emulator {
+    /// Creates a new emulator with full batteries and permanent network coverage.
+    pub fn new() -> Emulator {
+        Emulator {
+            start: Instant::now(),
+            echo: true,
+            text_mode: false,
+            status_reports: false,
+            bearer_open: false,
+            attached: false,
+            battery_mv: 4200f64,
+            main_battery_mv: 2200f64,
+            battery_drain: 0f64,
+            main_battery_drain: 0f64,
+            latitude: 40.4168,
+            longitude: -3.7038,
+            dropouts: Vec::new(),
+            scripted: VecDeque::new(),
+            sms_destination: None,
+            message_reference: 0,
+            inbox: Vec::new(),
+            http: None,
+            ip_up: false,
+            connections: (0..CONNECTION_LINKS).map(|_| None).collect(),
+            pending_send: None,
+            send_data: Vec::new(),
+            call: None,
+            under_voltage_warned: false,
+            outgoing: Vec::new(),
+            incoming: VecDeque::new(),
+            incoming_data: VecDeque::new(),
+        }
+    }
+
+    /// Creates an emulator following a typical flight: the network is lost during most of the
+    /// ascent and descent, and batteries drain steadily.
+    pub fn flight_profile() -> Emulator {
+        let mut emulator = Emulator::new();
+        emulator.set_battery_drain(60f64, 40f64)
+            .add_dropout(Duration::from_secs(15 * 60), Duration::from_secs(135 * 60));
+
+        emulator
+    }
+
+    /// Sets the voltages of the GSM battery and of the main battery ADC, in millivolts.
+    #[cfg(test)]
+    pub fn set_batteries(&mut self, gsm_mv: f64, main_mv: f64) -> &mut Self {
+        self.battery_mv = gsm_mv;
+        self.main_battery_mv = main_mv;
+        self
+    }
+
+    /// Sets how many millivolts per hour the GSM and main batteries lose.
+    pub fn set_battery_drain(&mut self,
+                             gsm_mv_per_hour: f64,
+                             main_mv_per_hour: f64)
+                             -> &mut Self {
+        self.battery_drain = gsm_mv_per_hour;
+        self.main_battery_drain = main_mv_per_hour;
+        self
+    }
+
+    /// Sets the location reported by the cell location service.
+    #[cfg(test)]
+    pub fn set_location(&mut self, latitude: f64, longitude: f64) -> &mut Self {
+        self.latitude = latitude;
+        self.longitude = longitude;
+        self
+    }
+
+    /// Adds a network dropout starting `start` after the emulator was created.
+    pub fn add_dropout(&mut self, start: Duration, duration: Duration) -> &mut Self {
+        self.dropouts.push(Dropout {
+            start: start,
+            duration: duration,
+        });
+        self
+    }
+
+    /// Scripts the response for the next time the given command is received.
+    ///
+    /// Scripted responses take precedence over the emulated ones, and are used only once.
+    #[cfg(test)]
+    pub fn script<S: Into<String>>(&mut self, command: S, response: &[&str]) -> &mut Self {
+        self.scripted
+            .push_back((command.into(), response.iter().map(|l| l.to_string()).collect()));
+        self
+    }
+
+    /// Receives an SMS from the given number, storing it and announcing it with `+CMTI`.
+    #[cfg(test)]
+    pub fn receive_sms<S: Into<String>, M: Into<String>>(&mut self,
+                                                         sender: S,
+                                                         text: M)
+                                                         -> &mut Self {
+        // Like the SIM storage, the lowest free index is used.
+        let index = (1..).find(|i| self.inbox.iter().all(|m| m.0 != *i)).unwrap();
+        self.inbox.push((index, false, sender.into(), text.into()));
+        self.incoming.push_back(format!("+CMTI: \"SM\",{}", index));
+        self
+    }
+
+    /// Serves the emulator over the given transport, for example the slave side of a
+    /// pseudo-terminal, in a new thread.
+    #[cfg(any(test, feature = "real-sim"))]
+    pub fn serve<P: Transport + Send + 'static>(mut self, mut port: P) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            loop {
+                buf.clear();
+                self.check_battery();
+                self.check_connections();
+                // Bytes are read as they come, since the data sent through connections is binary.
+                if let Err(e) = port.read_bytes(&mut buf, 256) {
+                    error!("Error reading from the emulated GSM port: {}", e);
+                    break;
+                }
+                if !buf.is_empty() {
+                    // Writing to the emulator cannot fail.
+                    let _ = self.write_bytes(&buf);
+                }
+
+                while let Some(line) = self.incoming.pop_front() {
+                    let mut bytes = if line == "> " {
+                        line.clone().into_bytes()
+                    } else {
+                        format!("{}\r\n", line).into_bytes()
+                    };
+                    if line.starts_with("+RECEIVE,") {
+                        bytes.extend(self.incoming_data.pop_front().unwrap_or_default());
+                    }
+                    if let Err(e) = port.write_bytes(&bytes) {
+                        error!("Error writing to the emulated GSM port: {}", e);
+                        return;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Checks whether the network is currently available.
+    fn has_network(&self) -> bool {
+        let elapsed = self.start.elapsed();
+        !self.dropouts.iter().any(|d| elapsed >= d.start && elapsed < d.start + d.duration)
+    }
+
+    /// Gets the current GSM and main battery voltages, in millivolts.
+    fn battery_voltages(&self) -> (f64, f64) {
+        let elapsed = self.start.elapsed();
+        let hours = (elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9) / 3600f64;
+
+        (self.battery_mv - self.battery_drain * hours,
+         self.main_battery_mv - self.main_battery_drain * hours)
+    }
+
+    /// Sends the under-voltage warning once the GSM battery gets low.
+    fn check_battery(&mut self) {
+        let (gsm_mv, _) = self.battery_voltages();
+        if !self.under_voltage_warned && gsm_mv < UNDER_VOLTAGE_WARNING {
+            self.under_voltage_warned = true;
+            self.incoming.push_back(String::from("UNDER-VOLTAGE WARNNING"));
+        }
+    }
+
+    /// Handles a complete line received from the GSM logic.
+    fn handle_line(&mut self, line: String) {
+        if self.sms_destination.is_some() {
+            self.handle_sms_body(line);
+            return;
+        }
+
+        let command = line.trim().to_owned();
+        if command.is_empty() {
+            return;
+        }
+        if self.echo {
+            self.incoming.push_back(command.clone());
+        }
+
+        let position = self.scripted.iter().position(|entry| entry.0 == command);
+        if let Some((_, response)) = position.and_then(|i| self.scripted.remove(i)) {
+            self.incoming.extend(response);
+            return;
+        }
+
+        let response = self.respond(&command);
+        self.incoming.extend(response);
+    }
+
+    /// Handles a line of an SMS body, sending the message once Ctrl+Z is received.
+    fn handle_sms_body(&mut self, line: String) {
+        if !line.contains('\u{1A}') {
+            return;
+        }
+        let destination = self.sms_destination.take().unwrap();
+
+        if self.has_network() {
+            self.message_reference = self.message_reference.wrapping_add(1);
+            info!("[Emulator] SMS sent to {}.", destination);
+            self.incoming.push_back(format!("+CMGS: {}", self.message_reference));
+            self.incoming.push_back(String::from("OK"));
+
+            if self.status_reports && !self.text_mode {
+                let body = line.trim_end_matches('\u{1A}').trim();
+                if let Some(report) = status_report(body, self.message_reference) {
+                    self.incoming.push_back(format!("+CDS: {}", report.len() / 2 - 1));
+                    self.incoming.push_back(report);
+                }
+            }
+        } else {
+            // 331: no network service.
+            self.incoming.push_back(String::from("+CMS ERROR: 331"));
+        }
+    }
+
+    /// Receives a byte of the body of an HTTP request, returning `false` if no body was expected.
+    fn receive_http_data(&mut self, byte: u8) -> bool {
+        match self.http {
+            Some(ref mut http) if http.pending_data > 0 => {
+                http.data.push(byte);
+                http.pending_data -= 1;
+                if http.pending_data == 0 {
+                    self.incoming.push_back(String::from("OK"));
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Receives a byte of the data sent through a connection, returning `false` if no data was
+    /// expected.
+    fn receive_connection_data(&mut self, byte: u8) -> bool {
+        let (link, length) = match self.pending_send {
+            Some(pending) => pending,
+            None => return false,
+        };
+        self.send_data.push(byte);
+        if self.send_data.len() < length {
+            return true;
+        }
+
+        self.pending_send = None;
+        let data = mem::take(&mut self.send_data);
+        let online = self.has_network();
+        let sent = match self.connections[link] {
+            Some(Connection::Tcp(ref mut stream)) if online => stream.write_all(&data).is_ok(),
+            Some(Connection::Udp(ref socket)) if online => socket.send(&data).is_ok(),
+            _ => false,
+        };
+        self.incoming.push_back(format!("{}, SEND {}", link, if sent { "OK" } else { "FAIL" }));
+        true
+    }
+
+    /// Delivers the data received by the open connections, and reports the closed ones.
+    fn check_connections(&mut self) {
+        let mut buf = [0u8; 1460];
+        for link in 0..CONNECTION_LINKS {
+            let received = match self.connections[link] {
+                Some(Connection::Tcp(ref mut stream)) => stream.read(&mut buf),
+                Some(Connection::Udp(ref socket)) => socket.recv(&mut buf),
+                None => continue,
+            };
+            match received {
+                Ok(0) if !matches!(self.connections[link], Some(Connection::Udp(_))) => {
+                    self.connections[link] = None;
+                    self.incoming.push_back(format!("{}, CLOSED", link));
+                }
+                Ok(read) => {
+                    self.incoming.push_back(format!("+RECEIVE,{},{}:", link, read));
+                    self.incoming_data.push_back(buf[..read].to_vec());
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => {
+                    error!("[Emulator] Connection {} failed: {}", link, e);
+                    self.connections[link] = None;
+                    self.incoming.push_back(format!("{}, CLOSED", link));
+                }
+            }
+        }
+    }
+
+    /// Runs the HTTP request set up in the HTTP service, returning the `+HTTPACTION` URC.
+    fn http_action(&mut self, method: &str) -> Option<String> {
+        let online = self.bearer_open && self.has_network();
+        let http = match self.http {
+            Some(ref mut http) if method == "1" && http.url.is_some() => http,
+            _ => return None,
+        };
+
+        // 601: network error.
+        let status = if online {
+            let url = http.url.clone().unwrap();
+            match post(&url, &http.content_type, &http.data) {
+                Ok((status, body)) => {
+                    info!("[Emulator] HTTP POST to {}: {}.", url, status);
+                    http.response = body;
+                    status
+                }
+                Err(e) => {
+                    error!("[Emulator] HTTP POST to {} failed: {}", url, e);
+                    http.response.clear();
+                    601
+                }
+            }
+        } else {
+            http.response.clear();
+            601
+        };
+
+        Some(format!("+HTTPACTION: 1,{},{}", status, http.response.len()))
+    }
+
+    /// Generates the emulated response to a command.
+    fn respond(&mut self, command: &str) -> Vec<String> {
+        let mut response = Vec::new();
+        match command {
+            "AT" => {}
+            "ATE0" => self.echo = false,
+            "ATE1" => self.echo = true,
+            _ if command.starts_with("AT+CMEE=") => {}
+            _ if command.starts_with("AT+IPR=") => {}
+            "AT+GSN" => response.push(String::from("869170031234567")),
+            "AT+CCID" => response.push(String::from("8934071100000000001F")),
+            "AT+CIMI" => response.push(String::from("214070000000001")),
+            "AT+CGMR" => response.push(String::from("Revision:1418B05SIM800L24")),
+            "AT+CNUM" => response.push(String::from("+CNUM: \"\",\"+34600000000\",145,7,4")),
+            _ if command.starts_with("AT+CLIP=") => {}
+            _ if command.starts_with("ATD") && command.ends_with(';') => {
+                if !self.has_network() {
+                    return vec![String::from("NO DIALTONE")];
+                }
+                // Emulated calls keep ringing until they are hung up.
+                self.call = Some(command[3..command.len() - 1].to_owned());
+            }
+            "AT+CLCC" => {
+                if let Some(ref number) = self.call {
+                    response.push(format!("+CLCC: 1,0,3,0,0,\"{}\",129", number));
+                }
+            }
+            "ATH" => self.call = None,
+            _ if command.starts_with("AT+CLTS=") => {}
+            "AT&W" => {}
+            "AT+CCLK?" => {
+                let now = if self.has_network() {
+                    time::now_utc().strftime("%y/%m/%d,%H:%M:%S+00").unwrap().to_string()
+                } else {
+                    // The default clock of the SIM800 when the network time was not received.
+                    String::from("04/01/01,00:00:00+00")
+                };
+                response.push(format!("+CCLK: \"{}\"", now));
+            }
+            "AT+CPIN?" => response.push(String::from("+CPIN: READY")),
+            // Entering a PIN is not allowed once the SIM card is ready.
+            _ if command.starts_with("AT+CPIN=") => return vec![String::from("+CME ERROR: 3")],
+            _ if command.starts_with("AT+CPMS=") => {
+                response.push(format!("+CPMS: {0},{1},{0},{1},{0},{1}",
+                                      self.inbox.len(),
+                                      SMS_CAPACITY))
+            }
+            _ if command.starts_with("AT+CSCS=") => {}
+            "AT+CREG?" => {
+                response.push(format!("+CREG: 0,{}", if self.has_network() { 1 } else { 2 }))
+            }
+            "AT+CGREG?" => {
+                response.push(format!("+CGREG: 0,{}",
+                                      if self.has_network() && self.attached { 1 } else { 2 }))
+            }
+            "AT+COPS?" => {
+                if self.has_network() {
+                    response.push(String::from("+COPS: 0,0,\"Emulated network\""));
+                } else {
+                    response.push(String::from("+COPS: 0"));
+                }
+            }
+            _ if command.starts_with("AT+COPS=") => {}
+            "AT+CSQ" => {
+                if self.has_network() {
+                    response.push(String::from("+CSQ: 18,0"));
+                } else {
+                    response.push(String::from("+CSQ: 99,99"));
+                }
+            }
+            _ if command.starts_with("AT+CENG=") => {}
+            "AT+CENG?" => {
+                response.push(String::from("+CENG: 1,1"));
+                if self.has_network() {
+                    response.push(String::from("+CENG: 0,\"0022,45,00,214,07,33,00c4,10,05,1a2b,\
+                                                0\""));
+                    response.push(String::from("+CENG: 1,\"0031,28,21,00c5,214,07,1a2b\""));
+                    response.push(String::from("+CENG: 2,\"0047,36,17,0d31,214,07,1a2c\""));
+                } else {
+                    response.push(String::from("+CENG: 0,\"0000,00,00,000,00,00,0000,00,00,0000,\
+                                                0\""));
+                    response.push(String::from("+CENG: 1,\"0000,00,00,0000,000,00,0000\""));
+                }
+            }
+            "AT+CBC" => {
+                let (gsm_mv, _) = self.battery_voltages();
+                let percent = ((gsm_mv - 3700f64) / 5f64).clamp(0f64, 100f64);
+                response.push(format!("+CBC: 0,{},{}", percent as u32, gsm_mv as u32));
+            }
+            "AT+CADC?" => {
+                let (_, main_mv) = self.battery_voltages();
+                response.push(format!("+CADC: 1,{}", main_mv as u32));
+            }
+            "AT+CMGF=0" => self.text_mode = false,
+            _ if command.starts_with("AT+CNMI=") => {
+                self.status_reports = command.split(',').nth(3) == Some("1");
+            }
+            "AT+CMGF=1" => self.text_mode = true,
+            "AT+CGATT=1" => {
+                if !self.has_network() {
+                    return vec![String::from("ERROR")];
+                }
+                self.attached = true;
+            }
+            "AT+CGATT?" => response.push(format!("+CGATT: {}", self.attached as u8)),
+            _ if command.starts_with("AT+SAPBR=1,") => {
+                if !self.attached || !self.has_network() {
+                    return vec![String::from("ERROR")];
+                }
+                self.bearer_open = true;
+            }
+            _ if command.starts_with("AT+SAPBR=0,") => {
+                if !self.bearer_open {
+                    return vec![String::from("ERROR")];
+                }
+                self.bearer_open = false;
+            }
+            _ if command.starts_with("AT+SAPBR=2,") => {
+                if self.bearer_open {
+                    response.push(String::from("+SAPBR: 1,1,\"10.0.0.2\""));
+                } else {
+                    response.push(String::from("+SAPBR: 1,3,\"0.0.0.0\""));
+                }
+            }
+            _ if command.starts_with("AT+CIPGSMLOC=1,") => {
+                if self.bearer_open && self.has_network() {
+                    let now = time::now_utc();
+                    response.push(format!("+CIPGSMLOC: 0,{:.6},{:.6},{},{}",
+                                          self.longitude,
+                                          self.latitude,
+                                          now.strftime("%Y/%m/%d").unwrap(),
+                                          now.strftime("%H:%M:%S").unwrap()));
+                } else {
+                    // 601: network error.
+                    response.push(String::from("+CIPGSMLOC: 601"));
+                }
+            }
+            "AT+CMGL=4" => {
+                if self.text_mode {
+                    return vec![String::from("ERROR")];
+                }
+                let now = time::now_utc();
+                let timestamp = [(now.tm_year % 100) as u8,
+                                 (now.tm_mon + 1) as u8,
+                                 now.tm_mday as u8,
+                                 now.tm_hour as u8,
+                                 now.tm_min as u8,
+                                 now.tm_sec as u8];
+                for &mut (index, ref mut read, ref sender, ref text) in &mut self.inbox {
+                    match pdu::encode_deliver(sender, text, timestamp) {
+                        Ok(pdu) => {
+                            // The length does not include the empty SMSC address.
+                            response.push(format!("+CMGL: {},{},,{}",
+                                                  index,
+                                                  if *read { 1 } else { 0 },
+                                                  pdu.len() / 2 - 1));
+                            response.push(pdu);
+                        }
+                        Err(e) => error!("[Emulator] Cannot list SMS {}: {}", index, e),
+                    }
+                    *read = true;
+                }
+            }
+            _ if command.starts_with("AT+CMGD=") => {
+                let index = command["AT+CMGD=".len()..].parse::<u32>().ok();
+                self.inbox.retain(|m| Some(m.0) != index);
+            }
+            _ if command.starts_with("AT+SAPBR=3,") => {}
+            "AT+HTTPINIT" => {
+                if self.http.is_some() || !self.bearer_open {
+                    return vec![String::from("ERROR")];
+                }
+                self.http = Some(HttpService::default());
+            }
+            "AT+HTTPTERM" => {
+                if self.http.take().is_none() {
+                    return vec![String::from("ERROR")];
+                }
+            }
+            _ if command.starts_with("AT+HTTPPARA=") => {
+                let mut parameter = command["AT+HTTPPARA=".len()..].splitn(2, ',');
+                let value = parameter.next()
+                    .and_then(|key| parameter.next().map(|value| (key, value.trim_matches('"'))));
+                match (self.http.as_mut(), value) {
+                    (Some(http), Some(("\"URL\"", url))) => http.url = Some(url.to_owned()),
+                    (Some(http), Some(("\"CONTENT\"", content_type))) => {
+                        http.content_type = content_type.to_owned()
+                    }
+                    (Some(_), Some(("\"CID\"", _))) => {}
+                    _ => return vec![String::from("ERROR")],
+                }
+            }
+            _ if command.starts_with("AT+HTTPDATA=") => {
+                let length = command["AT+HTTPDATA=".len()..]
+                    .split(',')
+                    .next()
+                    .and_then(|l| l.parse::<usize>().ok());
+                match (self.http.as_mut(), length) {
+                    (Some(http), Some(length)) if length > 0 => {
+                        http.data.clear();
+                        http.pending_data = length;
+                    }
+                    _ => return vec![String::from("ERROR")],
+                }
+                return vec![String::from("DOWNLOAD")];
+            }
+            _ if command.starts_with("AT+HTTPACTION=") => {
+                match self.http_action(&command["AT+HTTPACTION=".len()..]) {
+                    Some(urc) => return vec![String::from("OK"), urc],
+                    None => return vec![String::from("ERROR")],
+                }
+            }
+            "AT+CIPSHUT" => {
+                self.ip_up = false;
+                for connection in &mut self.connections {
+                    *connection = None;
+                }
+                return vec![String::from("SHUT OK")];
+            }
+            _ if command.starts_with("AT+CIPMUX=") => {
+                if self.ip_up {
+                    return vec![String::from("ERROR")];
+                }
+            }
+            _ if command.starts_with("AT+CSTT=") => {}
+            "AT+CIICR" => {
+                if !self.attached || !self.has_network() {
+                    return vec![String::from("ERROR")];
+                }
+                self.ip_up = true;
+            }
+            "AT+CIFSREX" => {
+                if !self.ip_up {
+                    return vec![String::from("ERROR")];
+                }
+                response.push(String::from("+CIFSREX: 10.0.0.3"));
+            }
+            _ if command.starts_with("AT+CIPSTART=") => {
+                let fields = command["AT+CIPSTART=".len()..]
+                    .split(',')
+                    .map(|f| f.trim_matches('"'))
+                    .collect::<Vec<_>>();
+                let link = match fields.first().and_then(|l| l.parse::<usize>().ok()) {
+                    Some(link) if link < CONNECTION_LINKS && self.ip_up && fields.len() == 4 &&
+                                  self.connections[link].is_none() => link,
+                    _ => return vec![String::from("ERROR")],
+                };
+
+                let result = match open_connection(fields[1], fields[2], fields[3]) {
+                    Ok(connection) => {
+                        info!("[Emulator] Connection {} open to {}:{}.",
+                              link,
+                              fields[2],
+                              fields[3]);
+                        self.connections[link] = Some(connection);
+                        "CONNECT OK"
+                    }
+                    Err(e) => {
+                        error!("[Emulator] Connection to {}:{} failed: {}",
+                               fields[2],
+                               fields[3],
+                               e);
+                        "CONNECT FAIL"
+                    }
+                };
+                return vec![String::from("OK"), format!("{}, {}", link, result)];
+            }
+            _ if command.starts_with("AT+CIPSEND=") => {
+                let mut fields = command["AT+CIPSEND=".len()..].split(',');
+                let (link, length) = match (fields.next().and_then(|l| l.parse::<usize>().ok()),
+                                            fields.next().and_then(|l| l.parse().ok())) {
+                    (Some(link), Some(length)) => (link, length),
+                    _ => return vec![String::from("ERROR")],
+                };
+                if length == 0 || self.connections.get(link).map_or(true, |c| c.is_none()) {
+                    return vec![String::from("ERROR")];
+                }
+                self.pending_send = Some((link, length));
+                return vec![String::from("> ")];
+            }
+            _ if command.starts_with("AT+CIPCLOSE=") => {
+                let link = command["AT+CIPCLOSE=".len()..]
+                    .split(',')
+                    .next()
+                    .and_then(|l| l.parse::<usize>().ok());
+                match link.and_then(|link| self.connections.get_mut(link)) {
+                    Some(connection) if connection.is_some() => *connection = None,
+                    _ => return vec![String::from("ERROR")],
+                }
+                return vec![format!("{}, CLOSE OK", link.unwrap())];
+            }
+            "AT+HTTPREAD" => {
+                match self.http {
+                    Some(ref http) => {
+                        response.push(format!("+HTTPREAD: {}", http.response.len()));
+                        response.push(String::from_utf8_lossy(&http.response).into_owned());
+                    }
+                    None => return vec![String::from("ERROR")],
+                }
+            }
+            _ if command.starts_with("AT+CMGS=") => {
+                let argument = &command["AT+CMGS=".len()..];
+                let destination = if self.text_mode {
+                    argument.trim_matches('"').to_owned()
+                } else {
+                    // In PDU mode only the TPDU length is given, the destination is in the PDU.
+                    match argument.parse::<u8>() {
+                        Ok(length) => format!("PDU of {} octets", length),
+                        Err(_) => return vec![String::from("ERROR")],
+                    }
+                };
+                self.sms_destination = Some(destination);
+                return vec![String::from("> ")];
+            }
+            _ => return vec![String::from("ERROR")],
+        }
+        response.push(String::from("OK"));
+
+        response
+    }
+}
+
+impl Transport for Emulator {
+    /// Pops the next queued line without blocking.
+    ///
+    /// Returns `Ok(0)` if nothing is queued: the response reader takes it as "nothing received
+    /// yet", not as the end of the stream, and keeps polling until the command deadline.
+    fn read_line(&mut self, buf: &mut String) -> Result<usize, io::Error> {
+        self.check_battery();
+        self.check_connections();
+        match self.incoming.pop_front() {
+            Some(line) => {
+                let len = buf.len();
+                buf.push_str(&line);
+                if line != "> " {
+                    buf.push_str("\r\n");
+                }
+                Ok(buf.len() - len)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn read_bytes(&mut self, buf: &mut Vec<u8>, len: usize) -> Result<usize, io::Error> {
+        match self.incoming_data.pop_front() {
+            Some(mut data) => {
+                if data.len() > len {
+                    self.incoming_data.push_front(data.split_off(len));
+                }
+                buf.extend_from_slice(&data);
+                Ok(data.len())
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        for &byte in bytes {
+            if self.receive_http_data(byte) || self.receive_connection_data(byte) {
+                continue;
+            }
+            self.outgoing.push(byte);
+            if byte == b'\r' || byte == b'\n' || byte == 0x1A {
+                let line = String::from_utf8_lossy(&self.outgoing).into_owned();
+                self.outgoing.clear();
+                self.handle_line(line);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, _timeout: Duration) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+/// Builds the status report of a delivered SMS-SUBMIT PDU requesting one, in hexadecimal.
+fn status_report(submit: &str, reference: u8) -> Option<String> {
+    // The SMSC address is empty, so the TPDU starts at the second octet.
+    let (first_octet, digits) = match (submit.get(2..4), submit.get(6..8)) {
+        (Some(first_octet), Some(digits)) => {
+            match (u8::from_str_radix(first_octet, 16), usize::from_str_radix(digits, 16)) {
+                (Ok(first_octet), Ok(digits)) => (first_octet, digits),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    if first_octet & 0x20 == 0 {
+        return None;
+    }
+
+    let now = time::now_utc();
+    let timestamp = [now.tm_year % 100,
+                     now.tm_mon + 1,
+                     now.tm_mday,
+                     now.tm_hour,
+                     now.tm_min,
+                     now.tm_sec,
+                     0]
+        .iter()
+        .map(|v| format!("{}{}", v % 10, v / 10))
+        .collect::<String>();
+
+    // Address length, type of address and digits, copied from the destination address.
+    submit.get(6..6 + 4 + (digits + 1) / 2 * 2).map(|address| {
+        format!("0006{:02X}{}{}{}00", reference, address, timestamp, timestamp)
+    })
+}
+
+/// Sends an HTTP POST request to a plain HTTP server, returning the status code and the body of
+/// the response.
+fn post(url: &str, content_type: &str, body: &[u8]) -> Result<(u16, Vec<u8>), io::Error> {
+    let location = try!(url.strip_prefix("http://").ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "only http:// URLs are supported")
+    }));
+    let (host, path) = match location.find('/') {
+        Some(i) => (&location[..i], &location[i..]),
+        None => (location, "/"),
+    };
+    let address = if host.contains(':') {
+        host.to_owned()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let mut stream = try!(TcpStream::connect(address.as_str()));
+    try!(stream.set_read_timeout(Some(Duration::from_secs(SERVER_TIMEOUT))));
+    try!(write!(stream,
+                "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n",
+                path,
+                host,
+                content_type,
+                body.len()));
+    try!(stream.write_all(body));
+
+    let mut response = Vec::new();
+    try!(stream.read_to_end(&mut response));
+    let status = String::from_utf8_lossy(&response)
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok());
+    let body = response.windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map_or(Vec::new(), |i| response[i + 4..].to_vec());
+
+    status.map(|status| (status, body))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP response"))
+}
+
+/// Opens the real connection of the emulated IP stack to the given host and port.
+fn open_connection(protocol: &str, host: &str, port: &str) -> Result<Connection, io::Error> {
+    let port = try!(port.parse::<u16>()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid port")));
+    let address = try!(try!((host, port).to_socket_addrs())
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host not found")));
+
+    let connection = match protocol {
+        "TCP" => {
+            Connection::Tcp(try!(TcpStream::connect_timeout(&address,
+                                                             Duration::from_secs(SERVER_TIMEOUT))))
+        }
+        "UDP" => {
+            let local = if address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+            let socket = try!(UdpSocket::bind(local));
+            try!(socket.connect(address));
+            Connection::Udp(socket)
+        }
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "unknown protocol")),
+    };
+    match connection {
+        Connection::Tcp(ref stream) => try!(stream.set_nonblocking(true)),
+        Connection::Udp(ref socket) => try!(socket.set_nonblocking(true)),
+    }
+
+    Ok(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{Emulator, pdu};
+    use gsm::transport::{PtyTransport, Transport};
+
+    /// Sends a command to the emulator and returns the queued lines, without line terminators.
+    fn command(emulator: &mut Emulator, command: &str) -> Vec<String> {
+        emulator.write_bytes(command.as_bytes()).unwrap();
+        emulator.write_bytes(b"\r").unwrap();
+        read_all(emulator)
+    }
+
+    /// Reads every line queued by the emulator.
+    fn read_all(emulator: &mut Emulator) -> Vec<String> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if emulator.read_line(&mut line).unwrap() == 0 {
+                return lines;
+            }
+            lines.push(line.trim_end_matches("\r\n").to_owned());
+        }
+    }
+
+    #[test]
+    fn it_echoes_until_disabled() {
+        let mut emulator = Emulator::new();
+        assert_eq!(command(&mut emulator, "AT"), vec!["AT", "OK"]);
+        assert_eq!(command(&mut emulator, "ATE0"), vec!["ATE0", "OK"]);
+        assert_eq!(command(&mut emulator, "AT"), vec!["OK"]);
+        assert_eq!(command(&mut emulator, "AT+FOO"), vec!["ERROR"]);
+    }
+
+    #[test]
+    fn it_loses_the_network_during_dropouts() {
+        let mut emulator = Emulator::new();
+        emulator.add_dropout(Duration::from_secs(0), Duration::from_secs(3600));
+        command(&mut emulator, "ATE0");
+
+        assert_eq!(command(&mut emulator, "AT+CREG?"), vec!["+CREG: 0,2", "OK"]);
+        assert_eq!(command(&mut emulator, "AT+CSQ"), vec!["+CSQ: 99,99", "OK"]);
+
+        command(&mut emulator, "AT+CMGF=1");
+        assert_eq!(command(&mut emulator, "AT+CMGS=\"+34600000001\""), vec!["> "]);
+        emulator.write_bytes(b"Test\x1A").unwrap();
+        assert_eq!(read_all(&mut emulator), vec!["+CMS ERROR: 331"]);
+    }
+
+    #[test]
+    fn it_recovers_the_network_after_a_dropout() {
+        let mut emulator = Emulator::new();
+        emulator.add_dropout(Duration::from_secs(0), Duration::from_millis(50));
+        command(&mut emulator, "ATE0");
+        assert_eq!(command(&mut emulator, "AT+CREG?"), vec!["+CREG: 0,2", "OK"]);
+
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(command(&mut emulator, "AT+CREG?"), vec!["+CREG: 0,1", "OK"]);
+
+        command(&mut emulator, "AT+CMGF=1");
+        command(&mut emulator, "AT+CMGS=\"+34600000001\"");
+        emulator.write_bytes(b"Test\x1A").unwrap();
+        assert_eq!(read_all(&mut emulator), vec!["+CMGS: 1", "OK"]);
+    }
+
+    #[test]
+    fn it_warns_once_of_battery_under_voltage() {
+        let mut emulator = Emulator::new();
+        // 1 mV per millisecond, so that it drops below 3.5 V after about 100 ms.
+        emulator.set_batteries(3600f64, 7400f64).set_battery_drain(3_600_000f64, 0f64);
+        assert_eq!(read_all(&mut emulator), Vec::<String>::new());
+
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(read_all(&mut emulator), vec!["UNDER-VOLTAGE WARNNING"]);
+        assert_eq!(read_all(&mut emulator), Vec::<String>::new());
+    }
+
+    #[test]
+    fn it_reports_the_flight_profile_batteries() {
+        let mut emulator = Emulator::flight_profile();
+        command(&mut emulator, "ATE0");
+
+        // The batteries start full, and lose 1 mV per minute.
+        let battery = command(&mut emulator, "AT+CBC");
+        assert!(battery[0] == "+CBC: 0,100,4200" || battery[0] == "+CBC: 0,99,4199");
+        assert_eq!(command(&mut emulator, "AT+CREG?"), vec!["+CREG: 0,1", "OK"]);
+    }
+
+    #[test]
+    fn it_locates_only_with_the_bearer_open() {
+        let mut emulator = Emulator::new();
+        emulator.set_location(40.416775, -3.703790);
+        command(&mut emulator, "ATE0");
+
+        assert_eq!(command(&mut emulator, "AT+CIPGSMLOC=1,1"), vec!["+CIPGSMLOC: 601", "OK"]);
+        assert_eq!(command(&mut emulator, "AT+CGATT=1"), vec!["OK"]);
+        assert_eq!(command(&mut emulator, "AT+SAPBR=1,1"), vec!["OK"]);
+        let location = command(&mut emulator, "AT+CIPGSMLOC=1,1");
+        assert!(location[0].starts_with("+CIPGSMLOC: 0,-3.703790,40.416775,"));
+        assert_eq!(location[1], "OK");
+    }
+
+    #[test]
+    fn it_serves_a_pseudo_terminal() {
+        let mut master = PtyTransport::open().unwrap();
+        let slave = master.open_slave().unwrap();
+        master.set_timeout(Duration::from_millis(500)).unwrap();
+        let _ = Emulator::new().serve(slave);
+
+        master.write_bytes(b"AT\r").unwrap();
+        master.flush().unwrap();
+        let mut lines = Vec::new();
+        for _ in 0..2 {
+            let mut line = String::new();
+            master.read_line(&mut line).unwrap();
+            lines.push(line);
+        }
+        assert_eq!(lines, ["AT\r\n", "OK\r\n"]);
+    }
+
+    #[test]
+    fn it_uses_scripted_responses_once() {
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CSQ", &["+CSQ: 5,0", "OK"]);
+        command(&mut emulator, "ATE0");
+
+        assert_eq!(command(&mut emulator, "AT+CSQ"), vec!["+CSQ: 5,0", "OK"]);
+        assert_eq!(command(&mut emulator, "AT+CSQ"), vec!["+CSQ: 18,0", "OK"]);
+    }
+
+    #[test]
+    fn it_notifies_received_sms() {
+        let mut emulator = Emulator::new();
+        command(&mut emulator, "ATE0");
+        command(&mut emulator, "AT+CMGF=0");
+
+        emulator.receive_sms("+34600000001", "STATUS");
+        emulator.receive_sms("+34600000002", "PING");
+        assert_eq!(read_all(&mut emulator), vec!["+CMTI: \"SM\",1", "+CMTI: \"SM\",2"]);
+
+        let listing = command(&mut emulator, "AT+CMGL=4");
+        assert_eq!(listing.len(), 5);
+        assert!(listing[2].starts_with("+CMGL: 2,0,,"));
+        let sms = pdu::decode_deliver(&listing[3]).unwrap();
+        assert_eq!(sms.get_sender(), "+34600000002");
+        assert_eq!(sms.get_text(), "PING");
+
+        // Listed messages are marked as read.
+        assert!(command(&mut emulator, "AT+CMGL=4")[0].starts_with("+CMGL: 1,1,,"));
+    }
+}
@@ -0,0 +1,301 @@
+//! GSM module errors.
+
+use std::{io, fmt};
+use std::error::Error as StdError;
+
+use super::parse::{ParseResponseError, RegistrationStatus, SimState};
+
+/// Equipment error codes (`+CME ERROR`) from 3GPP TS 27.007 and the SIM800 manual.
+const CME_ERRORS: &'static [(u16, &'static str)] =
+    &[(0, "phone failure"),
+      (1, "no connection to phone"),
+      (2, "phone-adaptor link reserved"),
+      (3, "operation not allowed"),
+      (4, "operation not supported"),
+      (5, "PH-SIM PIN required"),
+      (6, "PH-FSIM PIN required"),
+      (7, "PH-FSIM PUK required"),
+      (10, "SIM not inserted"),
+      (11, "SIM PIN required"),
+      (12, "SIM PUK required"),
+      (13, "SIM failure"),
+      (14, "SIM busy"),
+      (15, "SIM wrong"),
+      (16, "incorrect password"),
+      (17, "SIM PIN2 required"),
+      (18, "SIM PUK2 required"),
+      (20, "memory full"),
+      (21, "invalid index"),
+      (22, "not found"),
+      (23, "memory failure"),
+      (24, "text string too long"),
+      (25, "invalid characters in text string"),
+      (26, "dial string too long"),
+      (27, "invalid characters in dial string"),
+      (30, "no network service"),
+      (31, "network timeout"),
+      (32, "network not allowed - emergency calls only"),
+      (40, "network personalization PIN required"),
+      (41, "network personalization PUK required"),
+      (42, "network subset personalization PIN required"),
+      (43, "network subset personalization PUK required"),
+      (44, "service provider personalization PIN required"),
+      (45, "service provider personalization PUK required"),
+      (46, "corporate personalization PIN required"),
+      (47, "corporate personalization PUK required"),
+      (100, "unknown"),
+      (103, "illegal MS"),
+      (106, "illegal ME"),
+      (107, "GPRS services not allowed"),
+      (111, "PLMN not allowed"),
+      (112, "location area not allowed"),
+      (113, "roaming not allowed in this location area"),
+      (132, "service option not supported"),
+      (133, "requested service option not subscribed"),
+      (134, "service option temporarily out of order"),
+      (148, "unspecified GPRS error"),
+      (149, "PDP authentication failure"),
+      (150, "invalid mobile class")];
+
+/// Message service error codes (`+CMS ERROR`) from 3GPP TS 27.005 and TS 24.011.
+const CMS_ERRORS: &'static [(u16, &'static str)] =
+    &[(1, "unassigned (unallocated) number"),
+      (8, "operator determined barring"),
+      (10, "call barred"),
+      (21, "short message transfer rejected"),
+      (27, "destination out of service"),
+      (28, "unidentified subscriber"),
+      (29, "facility rejected"),
+      (30, "unknown subscriber"),
+      (38, "network out of order"),
+      (41, "temporary failure"),
+      (42, "congestion"),
+      (47, "resources unavailable, unspecified"),
+      (50, "requested facility not subscribed"),
+      (69, "requested facility not implemented"),
+      (81, "invalid short message transfer reference value"),
+      (95, "invalid message, unspecified"),
+      (96, "invalid mandatory information"),
+      (97, "message type non-existent or not implemented"),
+      (98, "message not compatible with short message protocol state"),
+      (99, "information element non-existent or not implemented"),
+      (111, "protocol error, unspecified"),
+      (127, "interworking, unspecified"),
+      (300, "ME failure"),
+      (301, "SMS service of ME reserved"),
+      (302, "operation not allowed"),
+      (303, "operation not supported"),
+      (304, "invalid PDU mode parameter"),
+      (305, "invalid text mode parameter"),
+      (310, "SIM not inserted"),
+      (311, "SIM PIN required"),
+      (312, "PH-SIM PIN required"),
+      (313, "SIM failure"),
+      (314, "SIM busy"),
+      (315, "SIM wrong"),
+      (316, "SIM PUK required"),
+      (317, "SIM PIN2 required"),
+      (318, "SIM PUK2 required"),
+      (320, "memory failure"),
+      (321, "invalid memory index"),
+      (322, "memory full"),
+      (330, "SMSC address unknown"),
+      (331, "no network service"),
+      (332, "network timeout"),
+      (340, "no +CNMA acknowledgement expected"),
+      (500, "unknown error")];
+
+/// Error reported by the modem with a numeric code, either `+CME ERROR` or `+CMS ERROR`.
+///
+/// The modem reports either the numeric code or its text, depending on the `AT+CMEE` setting,
+/// so both are decoded into the code and its meaning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModemError {
+    code: Option<u16>,
+    text: String,
+}
+
+impl ModemError {
+    /// Decodes a `+CME ERROR` value.
+    pub fn cme(value: &str) -> ModemError {
+        ModemError::decode(value, CME_ERRORS)
+    }
+
+    /// Decodes a `+CMS ERROR` value.
+    pub fn cms(value: &str) -> ModemError {
+        ModemError::decode(value, CMS_ERRORS)
+    }
+
+    /// Gets the numeric error code, if known.
+    pub fn get_code(&self) -> Option<u16> {
+        self.code
+    }
+
+    /// Gets the meaning of the error.
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+
+    /// Decodes a numeric or verbose error value using the given code table.
+    fn decode(value: &str, table: &'static [(u16, &'static str)]) -> ModemError {
+        let value = value.trim();
+        match value.parse::<u16>() {
+            Ok(code) => {
+                ModemError {
+                    code: Some(code),
+                    text: table.iter()
+                        .find(|&&(c, _)| c == code)
+                        .map_or("unknown error code", |&(_, text)| text)
+                        .to_owned(),
+                }
+            }
+            Err(_) => {
+                ModemError {
+                    code: table.iter()
+                        .find(|&&(_, text)| text.eq_ignore_ascii_case(value))
+                        .map(|&(code, _)| code),
+                    text: value.to_owned(),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for ModemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "{} ({})", self.text, code),
+            None => write!(f, "{}", self.text),
+        }
+    }
+}
+
+/// Error of a GSM operation.
+#[derive(Debug)]
+pub enum GsmError {
+    /// The modem is powered off.
+    PowerOff,
+    /// The modem did not finish its response to the command before the deadline.
+    Timeout(String),
+    /// The modem answered the command with something other than what was expected.
+    UnexpectedResponse {
+        command: String,
+        response: String,
+    },
+    /// An information response could not be parsed.
+    Parse(ParseResponseError),
+    /// The modem reported an equipment error (`+CME ERROR`).
+    Equipment(ModemError),
+    /// The modem reported a message service error (`+CMS ERROR`).
+    MessageService(ModemError),
+    /// The modem did not register in the network before the deadline, with the last status seen.
+    NotRegistered(RegistrationStatus),
+    /// The SIM card is not ready, and could not be unlocked automatically.
+    SimLocked(SimState),
+    /// The modem did not change its power state, and stayed on (`true`) or off (`false`).
+    PowerStuck(bool),
+    /// The connection in the given link of the IP stack is closed.
+    ConnectionClosed(u8),
+    /// The request was invalid, and was not sent to the modem.
+    InvalidInput(String),
+    /// Error in the serial communication with the modem.
+    Io(io::Error),
+}
+
+impl GsmError {
+    /// Creates an unexpected response error.
+    pub fn unexpected<C: Into<String>, R: Into<String>>(command: C, response: R) -> GsmError {
+        GsmError::UnexpectedResponse {
+            command: command.into(),
+            response: response.into(),
+        }
+    }
+
+    /// Checks if the error is probably transient, so that retrying the operation later makes
+    /// sense: timeouts and network-related modem errors.
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            GsmError::Timeout(_) => true,
+            GsmError::NotRegistered(status) => status != RegistrationStatus::Denied,
+            GsmError::Equipment(ref e) => {
+                matches!(e.get_code(), Some(14) | Some(30) | Some(31) | Some(134) | Some(148))
+            }
+            GsmError::MessageService(ref e) => {
+                matches!(e.get_code(),
+                         Some(38) | Some(41) | Some(42) | Some(47) | Some(314) | Some(331) |
+                         Some(332))
+            }
+            GsmError::Io(ref e) => e.kind() == io::ErrorKind::TimedOut,
+            _ => false,
+        }
+    }
+
+    /// Checks if the error is known to be permanent, so that retrying the operation is useless:
+    /// invalid requests, and message service errors about the destination or the message itself.
+    pub fn is_permanent(&self) -> bool {
+        match *self {
+            GsmError::InvalidInput(_) => true,
+            GsmError::MessageService(ref e) => {
+                matches!(e.get_code(),
+                         Some(1) | Some(21) | Some(28) | Some(30) | Some(95) | Some(96) |
+                         Some(304))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<io::Error> for GsmError {
+    fn from(e: io::Error) -> GsmError {
+        GsmError::Io(e)
+    }
+}
+
+impl From<ParseResponseError> for GsmError {
+    fn from(e: ParseResponseError) -> GsmError {
+        GsmError::Parse(e)
+    }
+}
+
+impl fmt::Display for GsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GsmError::PowerOff => write!(f, "GSM is off"),
+            GsmError::Timeout(ref command) => write!(f, "timeout waiting for '{}'", command),
+            GsmError::UnexpectedResponse { ref command, ref response } => {
+                write!(f, "unexpected response to '{}': '{}'", command, response)
+            }
+            GsmError::Parse(ref e) => write!(f, "{}", e),
+            GsmError::Equipment(ref e) => write!(f, "equipment error: {}", e),
+            GsmError::MessageService(ref e) => write!(f, "message service error: {}", e),
+            GsmError::NotRegistered(status) => {
+                write!(f, "not registered in the network: {:?}", status)
+            }
+            GsmError::SimLocked(state) => write!(f, "SIM card not ready: {:?}", state),
+            GsmError::PowerStuck(true) => write!(f, "GSM did not turn off"),
+            GsmError::PowerStuck(false) => write!(f, "GSM did not turn on"),
+            GsmError::ConnectionClosed(link) => write!(f, "connection {} closed", link),
+            GsmError::InvalidInput(ref description) => write!(f, "{}", description),
+            GsmError::Io(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl StdError for GsmError {
+    fn description(&self) -> &str {
+        match *self {
+            GsmError::PowerOff => "GSM is off",
+            GsmError::Timeout(_) => "timeout waiting for the GSM response",
+            GsmError::UnexpectedResponse { .. } => "unexpected GSM response",
+            GsmError::Parse(_) => "invalid GSM response",
+            GsmError::Equipment(_) => "GSM equipment error",
+            GsmError::MessageService(_) => "GSM message service error",
+            GsmError::NotRegistered(_) => "not registered in the network",
+            GsmError::SimLocked(_) => "SIM card not ready",
+            GsmError::PowerStuck(_) => "GSM did not change its power state",
+            GsmError::ConnectionClosed(_) => "connection closed",
+            GsmError::InvalidInput(ref description) => description,
+            GsmError::Io(_) => "GSM serial port error",
+        }
+    }
+}
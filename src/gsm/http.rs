@@ -0,0 +1,215 @@
+//! HTTP requests through the SIM800 HTTP stack.
+//!
+//! The modem runs HTTP on top of an open GPRS bearer, so requests are only available inside a
+//! bearer session. The request is set up with `AT+HTTPPARA` and its body uploaded with
+//! `AT+HTTPDATA`. `AT+HTTPACTION` answers `OK` right away, and the HTTP status arrives later in a
+//! `+HTTPACTION` URC.
+
+use std::time::{Duration, Instant};
+
+use log::LogLevel::*;
+
+use super::{GsmError, Transport, Urc, command_timeout};
+use super::bearer::BearerSession;
+
+/// `AT+HTTPACTION` method code for POST requests.
+const HTTP_POST: u8 = 1;
+/// Time the modem waits for the request body after `AT+HTTPDATA`, in milliseconds.
+const HTTP_DATA_TIMEOUT: u64 = 10_000;
+/// Deadline for the `+HTTPACTION` URC after starting the request, in seconds.
+const HTTP_ACTION_TIMEOUT: u64 = 130;
+
+/// Response to an HTTP request.
+///
+/// Status codes from 600 on are errors of the modem HTTP stack, such as 601 for network errors
+/// or 603 for DNS errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpResponse {
+    status: u16,
+    length: usize,
+}
+
+impl HttpResponse {
+    /// Gets the HTTP status code.
+    pub fn get_status(&self) -> u16 {
+        self.status
+    }
+
+    /// Gets the length of the response body, in bytes.
+    pub fn get_length(&self) -> usize {
+        self.length
+    }
+
+    /// Checks if the server accepted the request, with a 2xx status code.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+impl<'a, T: Transport> BearerSession<'a, T> {
+    /// Sends an HTTP POST request with the given body and content type.
+    ///
+    /// The HTTP service of the modem is terminated afterwards, even if the request failed.
+    pub fn http_post(&mut self,
+                     url: &str,
+                     content_type: &str,
+                     body: &[u8])
+                     -> Result<HttpResponse, GsmError> {
+        // Both go inside quoted `AT+HTTPPARA` parameters.
+        if url.is_empty() || !url.chars().all(|c| c.is_ascii_graphic() && c != '"') {
+            return Err(GsmError::InvalidInput(format!("invalid URL '{}'", url)));
+        }
+        if content_type.is_empty() ||
+           !content_type.chars().all(|c| c == ' ' || (c.is_ascii_graphic() && c != '"')) {
+            return Err(GsmError::InvalidInput(format!("invalid content type '{}'", content_type)));
+        }
+        self.logger.log(&format!("Sending HTTP POST to {} ({} bytes)…", url, body.len()),
+                        Info);
+        try!(self.send_command_ok("AT+HTTPINIT"));
+        let result = self.http_request(url, content_type, body);
+        if self.send_command_ok("AT+HTTPTERM").is_err() {
+            self.logger.log("Error terminating the HTTP service.", Error);
+        }
+
+        if let Ok(ref response) = result {
+            self.logger.log(&format!("HTTP POST to {} finished with status {}.",
+                                     url,
+                                     response.get_status()),
+                            if response.is_success() { Info } else { Warn });
+        }
+        result
+    }
+
+    /// Sets up and runs a POST request, once the HTTP service is initialized.
+    fn http_request(&mut self,
+                    url: &str,
+                    content_type: &str,
+                    body: &[u8])
+                    -> Result<HttpResponse, GsmError> {
+        let profile = self.gprs.get_bearer_profile();
+        try!(self.send_command_ok(&format!("AT+HTTPPARA=\"CID\",{}", profile)));
+        try!(self.send_command_ok(&format!("AT+HTTPPARA=\"URL\",\"{}\"", url)));
+        try!(self.send_command_ok(&format!("AT+HTTPPARA=\"CONTENT\",\"{}\"", content_type)));
+        try!(self.upload_http_data(body));
+
+        // Subscribing before starting the request, so that the URC cannot be missed.
+        let urcs = self.subscribe_urcs();
+        try!(self.send_command_ok(&format!("AT+HTTPACTION={}", HTTP_POST)));
+
+        let start = Instant::now();
+        loop {
+            while let Ok(urc) = urcs.try_recv() {
+                if let Urc::HttpAction { method, status, length } = urc {
+                    if method == HTTP_POST {
+                        return Ok(HttpResponse {
+                            status: status,
+                            length: length,
+                        });
+                    }
+                }
+            }
+
+            if start.elapsed() >= Duration::from_secs(HTTP_ACTION_TIMEOUT) {
+                self.logger.log("No '+HTTPACTION' received after the HTTP request.", Error);
+                return Err(GsmError::Timeout(format!("AT+HTTPACTION={}", HTTP_POST)));
+            }
+            try!(self.poll_urcs(Duration::from_secs(1)));
+        }
+    }
+
+    /// Uploads the body of the HTTP request.
+    fn upload_http_data(&mut self, body: &[u8]) -> Result<(), GsmError> {
+        let command = format!("AT+HTTPDATA={},{}", body.len(), HTTP_DATA_TIMEOUT);
+        let response = try!(self.send_command(&command));
+        if !response.is_prompt() {
+            self.logger.log(&format!("No 'DOWNLOAD' received on 'AT+HTTPDATA' response: {}",
+                                     response.result()),
+                            Error);
+            return Err(response.to_error(&command));
+        }
+
+        let gsm = &mut **self;
+        try!(gsm.serial.write_bytes(body));
+        try!(gsm.serial.flush());
+        gsm.command_logger.log(&format!("Sent: {} bytes of HTTP data", body.len()), Info);
+
+        let response = try!(gsm.reader.read(&mut gsm.serial,
+                                            None,
+                                            command_timeout(&command),
+                                            &mut gsm.command_logger));
+        if response.is_ok() {
+            Ok(())
+        } else {
+            gsm.logger.log(&format!("Error uploading HTTP data: {}", response.result()), Error);
+            Err(response.to_error(&command))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::super::Gsm;
+    use super::super::emulator::Emulator;
+
+    /// Telemetry document sent in the tests.
+    const DOCUMENT: &'static str = "{\"state\":\"GoingUp\",\"latitude\":40.416800}";
+
+    #[test]
+    fn post() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/telemetry", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 512];
+            while !request.ends_with(DOCUMENT.as_bytes()) {
+                let read = stream.read(&mut buf).unwrap();
+                assert!(read > 0, "request ended early");
+                request.extend_from_slice(&buf[..read]);
+            }
+            stream.write_all(b"HTTP/1.0 201 Created\r\nContent-Length: 7\r\n\r\nstored\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+
+        let response = {
+            let mut bearer = gsm.open_bearer().unwrap();
+            let response = bearer.http_post(&url, "application/json", DOCUMENT.as_bytes())
+                .unwrap();
+            bearer.close().unwrap();
+            response
+        };
+        assert_eq!(response.get_status(), 201);
+        assert_eq!(response.get_length(), 7);
+        assert!(response.is_success());
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /telemetry HTTP/1.0\r\n"));
+        assert!(request.contains("Content-Type: application/json\r\n"));
+        assert!(request.ends_with(&format!("\r\n\r\n{}", DOCUMENT)));
+    }
+
+    #[test]
+    fn post_network_error() {
+        // Nothing listens on the port once the listener is dropped.
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/telemetry", listener.local_addr().unwrap())
+        };
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+
+        let mut bearer = gsm.open_bearer().unwrap();
+        let response = bearer.http_post(&url, "application/json", DOCUMENT.as_bytes()).unwrap();
+        assert_eq!(response.get_status(), 601);
+        assert_eq!(response.get_length(), 0);
+        assert!(!response.is_success());
+
+        // The HTTP service was terminated, so it can be used again.
+        assert!(bearer.http_post("http://", "application/json", b"{}").is_ok());
+    }
+}
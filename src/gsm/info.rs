@@ -0,0 +1,170 @@
+//! Modem and SIM card identity.
+//!
+//! Several payloads fly with the same software, so the identity of the modem and its SIM card is
+//! read at start-up, to tell which hardware produced each log.
+
+use std::fmt;
+
+use log::LogLevel::*;
+
+use super::{Gsm, GsmError, Transport};
+use super::parse::SubscriberNumber;
+
+/// Identity of the modem and its SIM card.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModemInfo {
+    imei: String,
+    iccid: String,
+    imsi: String,
+    revision: String,
+    number: Option<String>,
+}
+
+impl ModemInfo {
+    /// Gets the IMEI of the modem.
+    pub fn get_imei(&self) -> &str {
+        &self.imei
+    }
+
+    /// Gets the ICCID of the SIM card.
+    pub fn get_iccid(&self) -> &str {
+        &self.iccid
+    }
+
+    /// Gets the IMSI of the SIM card.
+    pub fn get_imsi(&self) -> &str {
+        &self.imsi
+    }
+
+    /// Gets the firmware revision of the modem.
+    pub fn get_revision(&self) -> &str {
+        &self.revision
+    }
+
+    /// Gets the phone number of the SIM card, if it is stored in it.
+    pub fn get_number(&self) -> Option<&str> {
+        self.number.as_deref()
+    }
+}
+
+impl fmt::Display for ModemInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "IMEI: {}, ICCID: {}, IMSI: {}, firmware: {}, number: {}",
+               self.imei,
+               self.iccid,
+               self.imsi,
+               self.revision,
+               self.number.as_deref().unwrap_or("unknown"))
+    }
+}
+
+impl<T: Transport> Gsm<T> {
+    /// Reads the identity of the modem and its SIM card.
+    ///
+    /// Many SIM cards do not store their own number, so it is only reported if available.
+    pub fn get_modem_info(&mut self) -> Result<ModemInfo, GsmError> {
+        if !self.is_on() {
+            error!("Trying to read the modem identity, but GSM was off.");
+            return Err(GsmError::PowerOff);
+        }
+
+        let imei = try!(self.read_identity("AT+GSN", ""));
+        let iccid = try!(self.read_identity("AT+CCID", ""));
+        let imsi = try!(self.read_identity("AT+CIMI", ""));
+        let revision = try!(self.read_identity("AT+CGMR", "Revision:"));
+        let number = match self.send_command_ok("AT+CNUM") {
+            Ok(ref response) if response.information("+CNUM:").is_some() => {
+                self.parse_information::<SubscriberNumber>(response, "+CNUM:")
+                    .ok()
+                    .map(|n| n.get_number().to_owned())
+                    .filter(|n| !n.is_empty())
+            }
+            _ => None,
+        };
+
+        let info = ModemInfo {
+            imei: imei,
+            iccid: iccid,
+            imsi: imsi,
+            revision: revision,
+            number: number,
+        };
+        self.logger.log(&format!("Modem identity: {}", info), Info);
+        Ok(info)
+    }
+
+    /// Reads an identity value, answered without a prefix or with the given one.
+    fn read_identity(&mut self, command: &str, prefix: &str) -> Result<String, GsmError> {
+        let response = try!(self.send_command_ok(command));
+        let value = response.lines()
+            .iter()
+            .map(|line| line.strip_prefix(prefix).unwrap_or(line).trim())
+            .find(|value| !value.is_empty());
+
+        match value {
+            Some(value) => Ok(value.to_owned()),
+            None => {
+                self.logger.log(&format!("Empty '{}' response.", command), Error);
+                Err(GsmError::unexpected(command, "empty response"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Gsm, GsmError};
+    use super::super::emulator::Emulator;
+
+    #[test]
+    fn modem_info() {
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        let info = gsm.get_modem_info().unwrap();
+        assert_eq!(info.get_imei(), "869170031234567");
+        assert_eq!(info.get_iccid(), "8934071100000000001F");
+        assert_eq!(info.get_imsi(), "214070000000001");
+        assert_eq!(info.get_revision(), "1418B05SIM800L24");
+        assert_eq!(info.get_number(), Some("+34600000000"));
+    }
+
+    #[test]
+    fn number_not_stored() {
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CNUM", &["OK"]);
+        let mut gsm = Gsm::simulated(emulator, "");
+        assert_eq!(gsm.get_modem_info().unwrap().get_number(), None);
+
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CNUM", &["+CNUM: \"\",\"\",129", "OK"]);
+        let mut gsm = Gsm::simulated(emulator, "");
+        assert_eq!(gsm.get_modem_info().unwrap().get_number(), None);
+
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CNUM", &["+CME ERROR: 10"]);
+        let mut gsm = Gsm::simulated(emulator, "");
+        assert_eq!(gsm.get_modem_info().unwrap().get_number(), None);
+    }
+
+    #[test]
+    fn empty_identity() {
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CCID", &["", "OK"]);
+        let mut gsm = Gsm::simulated(emulator, "");
+        match gsm.get_modem_info() {
+            Err(GsmError::UnexpectedResponse { ref command, .. }) => assert_eq!(command, "AT+CCID"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn sim_not_inserted() {
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CCID", &["+CME ERROR: 10"]);
+        let mut gsm = Gsm::simulated(emulator, "");
+        match gsm.get_modem_info() {
+            Err(GsmError::Equipment(ref e)) => assert_eq!(e.get_code(), Some(10)),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
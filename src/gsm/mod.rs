@@ -0,0 +1,613 @@
+pub mod transport;
+pub mod response;
+pub mod parse;
+pub mod error;
+pub mod urc;
+pub mod sms;
+pub mod pdu;
+pub mod outbox;
+pub mod delivery;
+pub mod network;
+pub mod bearer;
+pub mod http;
+pub mod socket;
+pub mod power;
+pub mod setup;
+pub mod sim;
+pub mod info;
+pub mod call;
+#[cfg(any(test, feature = "sim", feature = "real-sim"))]
+pub mod emulator;
+
+pub use self::transport::Transport;
+pub use self::error::GsmError;
+pub use self::urc::Urc;
+pub use self::sms::ReceivedSms;
+
+use std::io;
+use std::str::FromStr;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use wiringpi;
+use log::LogLevel::*;
+use Coordinates;
+
+use logger::Logger;
+use config::{SerialConfig, GsmConfig, GprsConfig};
+use self::response::{Response, ResponseReader};
+use self::delivery::DeliveryTracker;
+use self::socket::SocketTable;
+use self::parse::{ParseResponseError, Registration, BatteryCharge, AdcReading, SignalQuality};
+#[cfg(any(feature = "sim", feature = "real-sim"))]
+use self::emulator::Emulator;
+
+use wiringpi::pin::{InputPin, OutputPin, Value};
+
+const GSM_MAX_BAT: f64 = 4.2;
+const GSM_MIN_BAT: f64 = 3.7;
+const MAIN_MAX_BAT: f64 = 8.4 * 2660f64 / (2660 + 7420) as f64; // Measured Ohms in voltage divider
+const MAIN_MIN_BAT: f64 = 7.4 * MAIN_MAX_BAT / 8.4;
+/// Deadline for commands not listed in `GSM_COMMAND_TIMEOUTS`, in seconds.
+const GSM_DEFAULT_TIMEOUT: u64 = 5;
+/// Deadlines for slow commands, in seconds, by command prefix.
+const GSM_COMMAND_TIMEOUTS: &'static [(&'static str, u64)] = &[("AT+CMGS", 60),
+                                                                ("AT+CGATT", 10),
+                                                                ("AT+SAPBR=0", 65),
+                                                                ("AT+SAPBR=1", 85),
+                                                                ("AT+CIPGSMLOC", 60),
+                                                                ("AT+COPS=", 120),
+                                                                ("AT+HTTPDATA", 15),
+                                                                ("AT+CIPSHUT", 65),
+                                                                ("AT+CIICR", 85),
+                                                                ("ATD", 20)];
+
+/// Transport used to talk to the modem: the real serial port, or the emulator in simulations.
+#[cfg(not(any(feature = "sim", feature = "real-sim")))]
+pub type SystemTransport = transport::TtyTransport;
+/// Transport used to talk to the modem: the real serial port, or the emulator in simulations.
+#[cfg(feature = "sim")]
+pub type SystemTransport = Emulator;
+/// Transport used to talk to the modem: the real serial port, or the emulator in simulations.
+#[cfg(all(feature = "real-sim", not(feature = "sim")))]
+pub type SystemTransport = transport::PtyTransport;
+
+/// Opens the transport to the modem, on the configured serial port.
+#[cfg(not(any(feature = "sim", feature = "real-sim")))]
+pub fn open_transport(serial: &SerialConfig) -> Result<SystemTransport, io::Error> {
+    let mut transport = try!(transport::TtyTransport::open(serial.get_path()));
+    try!(transport.configure(serial.get_settings()));
+
+    Ok(transport)
+}
+
+/// Opens the transport to the modem.
+///
+/// In simulations the emulator is used directly as the transport.
+#[cfg(feature = "sim")]
+pub fn open_transport(_serial: &SerialConfig) -> Result<SystemTransport, io::Error> {
+    Ok(Emulator::flight_profile())
+}
+
+/// Opens the transport to the modem.
+///
+/// In realistic simulations the emulator is served on the slave side of a pseudo-terminal, so
+/// that the modem is accessed through a real serial port.
+#[cfg(all(feature = "real-sim", not(feature = "sim")))]
+pub fn open_transport(_serial: &SerialConfig) -> Result<SystemTransport, io::Error> {
+    let pty = try!(transport::PtyTransport::open());
+    Emulator::flight_profile().serve(try!(pty.open_slave()));
+    info!("GSM emulator serving on {}.", pty.slave_path().display());
+
+    Ok(pty)
+}
+
+pub struct Gsm<T: Transport> {
+    serial: T,
+    reader: ResponseReader,
+    logger: Logger,
+    command_logger: Logger,
+    power_pin: OutputPin<wiringpi::pin::WiringPi>,
+    status_pin: InputPin<wiringpi::pin::WiringPi>,
+    reset_pin: OutputPin<wiringpi::pin::WiringPi>,
+    simulated_on: bool,
+    simulated_ignored_pulses: u8,
+    pin_rejected: bool,
+    concatenated_reference: u8,
+    deliveries: DeliveryTracker,
+    sockets: SocketTable,
+    calls: Receiver<Urc>,
+    baud_rate: usize,
+    config: GsmConfig,
+    gprs: GprsConfig,
+}
+
+impl<T: Transport> Gsm<T> {
+    pub fn initialize(mut transport: T,
+                      wiring_pi: &wiringpi::WiringPi<wiringpi::pin::WiringPi>,
+                      serial: &SerialConfig,
+                      config: GsmConfig,
+                      gprs: GprsConfig)
+                      -> Result<Gsm<T>, io::Error> {
+        try!(transport.set_timeout(serial.get_timeout()));
+        let mut reader = ResponseReader::new();
+        let deliveries = DeliveryTracker::new(reader.subscribe());
+        let sockets = SocketTable::new();
+        let calls = reader.subscribe();
+        // The reset line is active low.
+        let reset_pin = wiring_pi.output_pin(config.get_reset_pin() as u16);
+        reset_pin.digital_write(Value::High);
+
+        Ok(Gsm {
+            serial: transport,
+            reader: reader,
+            logger: try!(Logger::new("data/logs/GSM", "GSM", "GSM")),
+            command_logger: try!(Logger::new("data/logs/GSMCommands",
+                                             "GSMCommands",
+                                             "GSMCommands")),
+            power_pin: wiring_pi.output_pin(7),
+            status_pin: wiring_pi.input_pin(21),
+            reset_pin: reset_pin,
+            simulated_on: false,
+            simulated_ignored_pulses: 0,
+            pin_rejected: false,
+            concatenated_reference: 0,
+            deliveries: deliveries,
+            sockets: sockets,
+            calls: calls,
+            baud_rate: serial.get_settings().baud_rate.speed(),
+            config: config,
+            gprs: gprs,
+        })
+    }
+
+    pub fn is_on(&self) -> bool {
+        if cfg!(any(test, feature = "sim", feature = "real-sim")) {
+            self.simulated_on
+        } else {
+            self.status_pin.digital_read() == Value::High
+        }
+    }
+
+    /// Opens a logger writing to the GSM log with the given prefix, for the modules using the
+    /// modem.
+    pub fn open_logger(&self, prefix: &'static str) -> Result<Logger, io::Error> {
+        self.logger.try_clone(prefix)
+    }
+
+    /// Subscribes to the unsolicited result codes received from the modem from now on.
+    pub fn subscribe_urcs(&mut self) -> Receiver<Urc> {
+        self.reader.subscribe()
+    }
+
+    /// Listens to the modem for the given time, dispatching any unsolicited result codes.
+    ///
+    /// URCs received while a command is running are dispatched automatically, so this is only
+    /// needed while the modem is idle.
+    pub fn poll_urcs(&mut self, timeout: Duration) -> Result<(), GsmError> {
+        if self.is_on() {
+            self.reader.poll(&mut self.serial, timeout, &mut self.command_logger)
+        } else {
+            Err(GsmError::PowerOff)
+        }
+    }
+
+    pub fn has_connectivity(&mut self) -> Result<bool, GsmError> {
+        if self.is_on() {
+            let response = try!(self.send_command_ok("AT+CREG?"));
+            let registration: Registration = try!(self.parse_information(&response, "+CREG:"));
+            Ok(registration.get_status().is_registered())
+        } else {
+            error!("Trying to check GSM connectivity, but GSM was off.");
+            Err(GsmError::PowerOff)
+        }
+    }
+
+    /// Gets the received signal strength and bit error rate.
+    pub fn signal_quality(&mut self) -> Result<SignalQuality, GsmError> {
+        if self.is_on() {
+            let response = try!(self.send_command_ok("AT+CSQ"));
+            self.parse_information(&response, "+CSQ:")
+        } else {
+            error!("Trying to check GSM signal quality, but GSM was off.");
+            Err(GsmError::PowerOff)
+        }
+    }
+
+    /// Gets the charge of the GSM and main batteries, in that order, as a fraction between their
+    /// minimum and maximum voltages.
+    pub fn get_battery_status(&mut self) -> Result<(f64, f64), GsmError> {
+        self.logger.log("Checking Battery status…", Info);
+        if self.is_on() {
+            let gsm_response = try!(self.send_command_ok("AT+CBC"));
+            let adc_response = try!(self.send_command_ok("AT+CADC?"));
+
+            let charge: BatteryCharge = try!(self.parse_information(&gsm_response, "+CBC:"));
+            let adc: AdcReading = try!(self.parse_information(&adc_response, "+CADC:"));
+            if !adc.is_success() {
+                self.logger.log("The ADC could not be read when checking the main battery.",
+                                Error);
+                return Err(GsmError::unexpected("AT+CADC?", "ADC read failed"));
+            }
+
+            self.logger.log(&format!("GSM battery: {} mV, {}% according to the modem, {:?}.",
+                                     charge.get_voltage(),
+                                     charge.get_percent(),
+                                     charge.get_status()),
+                            Info);
+            let gsm_voltage = charge.get_voltage() as f64;
+            let adc_voltage = adc.get_value() as f64;
+
+            Ok(((gsm_voltage / 1000.0 - GSM_MIN_BAT) / (GSM_MAX_BAT - GSM_MIN_BAT),
+                (adc_voltage / 1000.0 - MAIN_MIN_BAT) / (MAIN_MAX_BAT - MAIN_MIN_BAT)))
+        } else {
+            error!("Trying to check batteries, but GSM was off.");
+            Err(GsmError::PowerOff)
+        }
+    }
+
+    pub fn get_coordinates(&mut self) -> Result<Coordinates, GsmError> {
+        self.logger.log("Getting GSM location…", Info);
+        if self.is_on() {
+            let response = try!(self.send_command("AT+CMGF=1"));
+            if !response.is_ok() {
+                self.logger.log("No OK received getting location on 'AT+CMGF=1' response.",
+                                Error);
+                return Err(response.to_error("AT+CMGF=1"));
+            }
+
+            let mut bearer = try!(self.open_bearer());
+            let coordinates = try!(bearer.locate());
+
+            if bearer.close().is_err() {
+                self.logger.log("Error turning GPRS down after reading location.", Error);
+            }
+
+            Ok(coordinates)
+        } else {
+            error!("Trying to get GSM location, but GSM was off.");
+            Err(GsmError::PowerOff)
+        }
+    }
+
+    /// Parses the information line with the given prefix in a response.
+    fn parse_information<R>(&mut self, response: &Response, prefix: &str) -> Result<R, GsmError>
+        where R: FromStr<Err = ParseResponseError>
+    {
+        let result = match response.information(prefix) {
+            Some(line) => line.parse(),
+            None => {
+                Err(ParseResponseError::Prefix {
+                    expected: "information",
+                    line: response.lines().join(" | "),
+                })
+            }
+        };
+
+        result.map_err(|e| {
+            self.logger.log(&format!("Invalid '{}' response: {}", prefix, e), Error);
+            GsmError::Parse(e)
+        })
+    }
+
+    /// Sends a command and reads its response, waiting at most the default command deadline.
+    fn send_command(&mut self, command: &str) -> Result<Response, GsmError> {
+        let timeout = command_timeout(command);
+        self.send_command_timeout(command, timeout)
+    }
+
+    /// Sends a command and reads its response, waiting at most the given time for it.
+    fn send_command_timeout(&mut self,
+                            command: &str,
+                            timeout: Duration)
+                            -> Result<Response, GsmError> {
+        try!(self.serial.write_bytes(format!("{}\r", command).as_bytes()));
+        try!(self.serial.flush());
+        self.command_logger.log(&format!("Sent: '{}'", command), Info);
+
+        self.reader.read(&mut self.serial, Some(command), timeout, &mut self.command_logger)
+    }
+
+    /// Sends a command and checks that the modem answered with `OK`.
+    fn send_command_ok(&mut self, command: &str) -> Result<Response, GsmError> {
+        let response = try!(self.send_command(command));
+        if response.is_ok() {
+            Ok(response)
+        } else {
+            let error = response.to_error(command);
+            self.logger.log(&format!("Error on '{}' response: {}", command, error), Error);
+            Err(error)
+        }
+    }
+
+    /// Sends a command containing a secret, such as a PIN or a password, and reads its response.
+    ///
+    /// The `masked` version of the command, with the secret hidden, is the one written to the
+    /// logs and to the returned errors.
+    fn send_command_masked(&mut self, command: &str, masked: &str) -> Result<Response, GsmError> {
+        try!(self.serial.write_bytes(format!("{}\r", command).as_bytes()));
+        try!(self.serial.flush());
+        self.command_logger.log(&format!("Sent: '{}'", masked), Info);
+
+        match self.reader.read(&mut self.serial,
+                               Some(command),
+                               command_timeout(command),
+                               &mut self.command_logger) {
+            Err(GsmError::Timeout(_)) => Err(GsmError::Timeout(masked.to_owned())),
+            result => result,
+        }
+    }
+
+    /// Sends a command containing a secret and checks that the modem answered with `OK`, logging
+    /// only the `masked` version of the command.
+    fn send_command_ok_masked(&mut self,
+                              command: &str,
+                              masked: &str)
+                              -> Result<Response, GsmError> {
+        let response = try!(self.send_command_masked(command, masked));
+        if response.is_ok() {
+            Ok(response)
+        } else {
+            let error = response.to_error(masked);
+            self.logger.log(&format!("Error on '{}' response: {}", masked, error), Error);
+            Err(error)
+        }
+    }
+}
+
+/// Gets the deadline for the given command.
+fn command_timeout(command: &str) -> Duration {
+    let secs = GSM_COMMAND_TIMEOUTS.iter()
+        .find(|&&(prefix, _)| command.starts_with(prefix))
+        .map_or(GSM_DEFAULT_TIMEOUT, |&(_, secs)| secs);
+
+    Duration::from_secs(secs)
+}
+
+#[cfg(test)]
+impl<T: Transport> Gsm<T> {
+    /// Creates a GSM module that is on, talking to the given transport, for tests.
+    ///
+    /// The given configuration is added to a minimal one, so that the `[gsm]` section can be set.
+    pub fn simulated(transport: T, config: &str) -> Gsm<T> {
+        let config = format!("[serial.gsm]\npath = \"/dev/null\"\n\
+                              [gprs]\napn = \"internet\"\n\
+                              [uplink]\nwhitelist = \"+34600000001\"\n{}",
+                             config)
+            .parse::<::config::Config>()
+            .unwrap();
+        let mut reader = ResponseReader::new();
+        let deliveries = DeliveryTracker::new(reader.subscribe());
+        let sockets = SocketTable::new();
+        let calls = reader.subscribe();
+        let logs = ::std::env::temp_dir();
+
+        Gsm {
+            serial: transport,
+            reader: reader,
+            logger: Logger::new(&logs.join("GSM"), "GSMTest", "GSM").unwrap(),
+            command_logger: Logger::new(&logs.join("GSMCommands"),
+                                        "GSMCommandsTest",
+                                        "GSMCommands")
+                .unwrap(),
+            power_pin: OutputPin::new(7),
+            status_pin: InputPin::new(21),
+            reset_pin: OutputPin::new(config.get_gsm().get_reset_pin() as i32),
+            simulated_on: true,
+            simulated_ignored_pulses: 0,
+            pin_rejected: false,
+            concatenated_reference: 0,
+            deliveries: deliveries,
+            sockets: sockets,
+            calls: calls,
+            baud_rate: config.get_gsm_serial().get_settings().baud_rate.speed(),
+            config: config.get_gsm().clone(),
+            gprs: config.get_gprs().clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::transport::ScriptedTransport;
+    use super::emulator::Emulator;
+    #[cfg(feature = "sms")]
+    use super::delivery::DeliveryState;
+
+    /// Creates a GSM module that is on, talking to the given scripted modem.
+    fn gsm(transport: ScriptedTransport) -> Gsm<ScriptedTransport> {
+        Gsm::simulated(transport, "")
+    }
+
+    #[test]
+    fn battery_status() {
+        let mut transport = ScriptedTransport::new();
+        transport.expect("AT+CBC", &["+CBC: 0,50,3950", "", "OK"])
+            .expect("AT+CADC?", &["+CADC: 1,2217", "", "OK"]);
+        let mut gsm = gsm(transport);
+
+        // The GSM battery comes first, then the main battery.
+        let (gsm_battery, main_battery) = gsm.get_battery_status().unwrap();
+        assert!((gsm_battery - 0.5).abs() < 1e-9, "{}", gsm_battery);
+        assert!((main_battery - 1.0).abs() < 0.01, "{}", main_battery);
+        assert_eq!(gsm.serial.written(), &["AT+CBC", "AT+CADC?"]);
+        assert!(gsm.serial.is_finished());
+    }
+
+    #[test]
+    fn battery_status_adc_failure() {
+        let mut transport = ScriptedTransport::new();
+        transport.expect("AT+CBC", &["+CBC: 0,50,3950", "OK"])
+            .expect("AT+CADC?", &["+CADC: 0,0", "OK"]);
+        let mut gsm = gsm(transport);
+
+        match gsm.get_battery_status() {
+            Err(GsmError::UnexpectedResponse { ref command, .. }) => {
+                assert_eq!(command, "AT+CADC?")
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn battery_status_error() {
+        let mut transport = ScriptedTransport::new();
+        transport.expect("AT+CBC", &["+CME ERROR: 100"]);
+        let mut gsm = gsm(transport);
+
+        match gsm.get_battery_status() {
+            Err(GsmError::Equipment(ref e)) => assert_eq!(e.get_code(), Some(100)),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(gsm.serial.written(), &["AT+CBC"]);
+    }
+
+    #[test]
+    fn battery_status_power_off() {
+        let mut gsm = gsm(ScriptedTransport::new());
+        gsm.simulated_on = false;
+
+        match gsm.get_battery_status() {
+            Err(GsmError::PowerOff) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(gsm.serial.written().is_empty());
+    }
+
+    #[test]
+    fn signal_quality() {
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        let quality = gsm.signal_quality().unwrap();
+        assert_eq!(quality.get_rssi_dbm(), Some(-78));
+        assert_eq!(quality.get_ber(), Some(0));
+    }
+
+    #[test]
+    fn signal_quality_without_network() {
+        let mut emulator = Emulator::new();
+        emulator.add_dropout(Duration::from_secs(0), Duration::from_secs(60));
+        let mut gsm = Gsm::simulated(emulator, "");
+        let quality = gsm.signal_quality().unwrap();
+        assert_eq!(quality.get_rssi_dbm(), None);
+        assert_eq!(quality.get_ber(), None);
+
+        gsm.simulated_on = false;
+        match gsm.signal_quality() {
+            Err(GsmError::PowerOff) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    /// Scripts the commands that open the GPRS bearer.
+    fn expect_bearer(transport: &mut ScriptedTransport) {
+        transport.expect("AT+CGATT=1", &["OK"])
+            .expect("AT+SAPBR=3,1,\"CONTYPE\",\"GPRS\"", &["OK"])
+            .expect("AT+SAPBR=3,1,\"APN\",\"internet\"", &["OK"])
+            .expect("AT+SAPBR=1,1", &["OK"]);
+    }
+
+    #[test]
+    fn coordinates() {
+        let mut transport = ScriptedTransport::new();
+        transport.expect("AT+CMGF=1", &["OK"]);
+        expect_bearer(&mut transport);
+        transport.expect("AT+CIPGSMLOC=1,1",
+                    &["+CIPGSMLOC: 0,-3.703790,40.416775,2017/01/17,15:55:04", "", "OK"])
+            .expect("AT+SAPBR=0,1", &["OK"]);
+        let mut gsm = gsm(transport);
+
+        let coordinates = gsm.get_coordinates().unwrap();
+        assert_eq!(coordinates.get_latitude(), 40.416775);
+        assert_eq!(coordinates.get_longitude(), -3.703790);
+        assert!(gsm.serial.is_finished());
+    }
+
+    #[test]
+    fn coordinates_not_found() {
+        let mut transport = ScriptedTransport::new();
+        transport.expect("AT+CMGF=1", &["OK"]);
+        expect_bearer(&mut transport);
+        transport.expect("AT+CIPGSMLOC=1,1", &["+CIPGSMLOC: 404", "", "OK"])
+            .expect("AT+SAPBR=0,1", &["OK"]);
+        let mut gsm = gsm(transport);
+
+        match gsm.get_coordinates() {
+            Err(GsmError::UnexpectedResponse { ref response, .. }) => {
+                assert_eq!(response, "location code 404 (not found)")
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        // The bearer is closed even if the location failed.
+        assert!(gsm.serial.is_finished());
+    }
+
+    #[test]
+    #[cfg(feature = "sms")]
+    fn sms() {
+        let pdu = "0031000B914306000000F10000A70AE8329BFD4697D9EC37";
+        let mut transport = ScriptedTransport::new();
+        transport.expect("AT+CMGF=0", &["OK"])
+            .expect("AT+CNMI=2,1,0,1,0", &["OK"])
+            .expect("AT+CMGS=23", &["> "])
+            .expect(format!("{}\u{1A}", pdu), &["", "+CMGS: 12", "", "OK"]);
+        let mut gsm = gsm(transport);
+
+        let results = gsm.send_sms_segments("hellohello", "+34600000001", 1, &[]).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 1);
+        assert_eq!(results[0].1.as_ref().unwrap(), &12);
+        assert!(gsm.serial.is_finished());
+        assert_eq!(gsm.get_delivery_state("+34600000001"), Some(DeliveryState::Sent));
+
+        // Status report for the message, in PDU mode.
+        gsm.serial
+            .push_line("+CDS: 25")
+            .push_line("00060C0B914306000000F1711071515540407110715155404000");
+        gsm.poll_urcs(Duration::from_millis(50)).unwrap();
+        assert_eq!(gsm.get_delivery_state("+34600000001"),
+                   Some(DeliveryState::Delivered));
+    }
+
+    #[test]
+    #[cfg(feature = "sms")]
+    fn concatenated_sms_segment_error() {
+        let text = "a".repeat(200);
+        let pdus = pdu::encode_submit("+34600000001",
+                                      &text,
+                                      1,
+                                      Some(Duration::from_secs(24 * 60 * 60)),
+                                      true)
+            .unwrap();
+        assert_eq!(pdus.len(), 2);
+        let mut transport = ScriptedTransport::new();
+        transport.expect("AT+CMGF=0", &["OK"])
+            .expect("AT+CNMI=2,1,0,1,0", &["OK"])
+            .expect(format!("AT+CMGS={}", pdus[0].get_length()), &["> "])
+            .expect(format!("{}\u{1A}", pdus[0].get_hex()), &["+CMGS: 40", "OK"])
+            .expect(format!("AT+CMGS={}", pdus[1].get_length()), &["> "])
+            .expect(format!("{}\u{1A}", pdus[1].get_hex()), &["+CMS ERROR: 38"]);
+        let mut gsm = gsm(transport);
+
+        let results = gsm.send_sms_segments(&text, "+34600000001", 1, &[]).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].1.as_ref().unwrap(), &40);
+        match results[1].1 {
+            Err(GsmError::MessageService(ref e)) => assert_eq!(e.get_code(), Some(38)),
+            ref result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(gsm.serial.is_finished());
+        assert_eq!(gsm.get_delivery_state("+34600000001"), Some(DeliveryState::Failed));
+    }
+
+    #[test]
+    fn sms_invalid_number() {
+        let mut gsm = gsm(ScriptedTransport::new());
+
+        match gsm.send_sms_segments("hello", "+34 600", 1, &[]) {
+            Err(GsmError::InvalidInput(_)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(gsm.serial.written().is_empty());
+    }
+}
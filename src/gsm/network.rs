@@ -0,0 +1,285 @@
+//! Network registration.
+//!
+//! After power-on the modem needs some time to find and register in a network, and it can lose
+//! the registration at any moment during the flight. Registration is polled with exponential
+//! backoff until it succeeds or the deadline passes, logging every state change.
+
+use std::{cmp, thread};
+use std::time::{Duration, Instant};
+
+use log::LogLevel::*;
+use time::{self, Timespec};
+
+use super::{Gsm, GsmError, Transport};
+use super::parse::{CellInfo, ClockTime, Operator, OperatorMode, Registration,
+                   RegistrationStatus};
+
+/// First delay between registration checks, in milliseconds.
+const REGISTRATION_INITIAL_BACKOFF: u64 = 500;
+/// Maximum delay between registration checks, in seconds.
+const REGISTRATION_MAX_BACKOFF: u64 = 30;
+/// Modem clock years before this one mean that the network time was never received.
+const NETWORK_TIME_MIN_YEAR: i32 = 2017;
+
+/// Network registration state after waiting for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkStatus {
+    registration: Registration,
+    gprs_registration: Registration,
+    operator: Option<Operator>,
+}
+
+impl NetworkStatus {
+    /// Checks if the modem is registered in a network other than its home network.
+    pub fn is_roaming(&self) -> bool {
+        self.registration.get_status() == RegistrationStatus::RegisteredRoaming
+    }
+
+    /// Checks if GPRS is available, so that data connections can be opened.
+    pub fn has_gprs(&self) -> bool {
+        self.gprs_registration.get_status().is_registered()
+    }
+
+    /// Gets the current operator, if it could be read.
+    pub fn get_operator(&self) -> Option<&Operator> {
+        self.operator.as_ref()
+    }
+}
+
+impl<T: Transport> Gsm<T> {
+    /// Waits until the modem registers in the GSM network, or the deadline passes.
+    ///
+    /// If an operator is given in numeric format (MCC and MNC, such as `"21407"`), it is selected
+    /// first, falling back to automatic selection if it is not available. On timeout, the error
+    /// contains the last registration status seen, to tell a denied registration from a modem
+    /// still searching.
+    pub fn wait_for_registration(&mut self,
+                                 deadline: Duration,
+                                 operator: Option<&str>)
+                                 -> Result<NetworkStatus, GsmError> {
+        self.logger.log("Waiting for network registration…", Info);
+        if !self.is_on() {
+            error!("Trying to wait for network registration, but GSM was off.");
+            return Err(GsmError::PowerOff);
+        }
+
+        let start = Instant::now();
+        if let Some(operator) = operator {
+            self.logger.log(&format!("Selecting operator {}…", operator), Info);
+            try!(self.send_command_ok(&format!("AT+COPS=4,2,\"{}\"", operator)));
+        }
+
+        let mut backoff = Duration::from_millis(REGISTRATION_INITIAL_BACKOFF);
+        let mut last_status = None;
+        loop {
+            let registration = try!(self.registration("AT+CREG?", "+CREG:"));
+            let status = registration.get_status();
+            if last_status != Some(status) {
+                self.logger.log(&format!("Network registration status: {:?}.", status),
+                                if status == RegistrationStatus::Denied { Warn } else { Info });
+                last_status = Some(status);
+            }
+
+            if status.is_registered() {
+                let gprs_registration = try!(self.registration("AT+CGREG?", "+CGREG:"));
+                let current = match self.send_command_ok("AT+COPS?") {
+                    Ok(response) => self.parse_information(&response, "+COPS:").ok(),
+                    Err(_) => None,
+                };
+                self.logger.log(&format!("Registered in {} after {} s, GPRS: {:?}.",
+                                         current.as_ref()
+                                             .and_then(|o: &Operator| o.get_name())
+                                             .unwrap_or("unknown operator"),
+                                         start.elapsed().as_secs(),
+                                         gprs_registration.get_status()),
+                                Info);
+                // The modem falls back to automatic selection if the operator is not available.
+                let automatic = current.as_ref()
+                    .map_or(false, |o: &Operator| o.get_mode() == OperatorMode::Automatic);
+                if let Some(operator) = operator.filter(|_| automatic) {
+                    self.logger.log(&format!("Operator {} not available, selected automatically.",
+                                             operator),
+                                    Warn);
+                }
+
+                return Ok(NetworkStatus {
+                    registration: registration,
+                    gprs_registration: gprs_registration,
+                    operator: current,
+                });
+            }
+
+            let elapsed = start.elapsed();
+            if elapsed >= deadline {
+                self.logger.log(&format!("Not registered after {} s: {:?}.",
+                                         elapsed.as_secs(),
+                                         status),
+                                Error);
+                return Err(GsmError::NotRegistered(status));
+            }
+            thread::sleep(cmp::min(backoff, deadline - elapsed));
+            backoff = cmp::min(backoff * 2, Duration::from_secs(REGISTRATION_MAX_BACKOFF));
+        }
+    }
+
+    /// Gets the current time from the modem clock, set by the network, as a UTC timestamp.
+    ///
+    /// The network only sends its time while the modem registers, and not every network does, so
+    /// an error is returned if the modem clock was not set.
+    pub fn get_network_time(&mut self) -> Result<Timespec, GsmError> {
+        if !self.is_on() {
+            error!("Trying to get the network time, but GSM was off.");
+            return Err(GsmError::PowerOff);
+        }
+
+        let response = try!(self.send_command_ok("AT+CCLK?"));
+        let clock: ClockTime = try!(self.parse_information(&response, "+CCLK:"));
+        if time::at_utc(clock.get_utc()).tm_year + 1900 < NETWORK_TIME_MIN_YEAR {
+            self.logger.log("The modem clock was not set by the network.", Warn);
+            return Err(GsmError::unexpected("AT+CCLK?", "clock not set by the network"));
+        }
+
+        Ok(clock.get_utc())
+    }
+
+    /// Checks if the modem is registered in the GPRS network, so that a bearer can be opened.
+    pub fn has_gprs_connectivity(&mut self) -> Result<bool, GsmError> {
+        if self.is_on() {
+            let registration = try!(self.registration("AT+CGREG?", "+CGREG:"));
+            Ok(registration.get_status().is_registered())
+        } else {
+            error!("Trying to check GPRS connectivity, but GSM was off.");
+            Err(GsmError::PowerOff)
+        }
+    }
+
+    /// Reads the serving and neighbour cells from the engineering mode, serving cell first.
+    ///
+    /// Unlike the cell location service this does not need GPRS, and the cells can be looked up
+    /// in public cell databases. Cells the modem could not identify are left out.
+    pub fn get_cell_info(&mut self) -> Result<Vec<CellInfo>, GsmError> {
+        if !self.is_on() {
+            error!("Trying to read cell information, but GSM was off.");
+            return Err(GsmError::PowerOff);
+        }
+
+        // Engineering mode with the identity of the neighbour cells.
+        try!(self.send_command_ok("AT+CENG=1,1"));
+        let response = try!(self.send_command_ok("AT+CENG?"));
+        let mut cells = Vec::new();
+        // The first line is the engineering mode setting, the cells have quoted data.
+        for line in response.lines().iter().filter(|l| l.starts_with("+CENG:") && l.contains('"')) {
+            match line.parse::<CellInfo>() {
+                Ok(cell) => {
+                    if cell.is_identified() {
+                        cells.push(cell);
+                    }
+                }
+                Err(e) => {
+                    self.logger.log(&format!("Invalid '+CENG:' response: {}", e), Error);
+                    return Err(GsmError::Parse(e));
+                }
+            }
+        }
+
+        Ok(cells)
+    }
+
+    /// Reads the GSM or GPRS network registration.
+    fn registration(&mut self, command: &str, prefix: &str) -> Result<Registration, GsmError> {
+        let response = try!(self.send_command_ok(command));
+        self.parse_information(&response, prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use time;
+    use super::super::{Gsm, GsmError};
+    use super::super::parse::RegistrationStatus;
+    use super::super::emulator::Emulator;
+
+    #[test]
+    fn registration() {
+        let mut emulator = Emulator::new();
+        emulator.add_dropout(Duration::from_secs(0), Duration::from_millis(700));
+        let mut gsm = Gsm::simulated(emulator, "");
+        let status = gsm.wait_for_registration(Duration::from_secs(5), None).unwrap();
+        assert!(!status.is_roaming());
+        assert_eq!(status.get_operator().and_then(|o| o.get_name()), Some("Emulated network"));
+    }
+
+    #[test]
+    fn registration_timeout() {
+        let mut emulator = Emulator::new();
+        emulator.add_dropout(Duration::from_secs(0), Duration::from_secs(60));
+        let mut gsm = Gsm::simulated(emulator, "");
+        match gsm.wait_for_registration(Duration::from_secs(1), None) {
+            Err(GsmError::NotRegistered(RegistrationStatus::Searching)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn cell_info() {
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        let cells = gsm.get_cell_info()
+            .unwrap()
+            .iter()
+            .map(|c| (c.is_serving(), c.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(cells,
+                   vec![(true, String::from("214-07 1A2B:00C4 -65dBm")),
+                        (false, String::from("214-07 1A2B:00C5 -82dBm")),
+                        (false, String::from("214-07 1A2C:0D31 -74dBm"))]);
+    }
+
+    #[test]
+    fn cell_info_without_network() {
+        let mut emulator = Emulator::new();
+        emulator.add_dropout(Duration::from_secs(0), Duration::from_secs(60));
+        let mut gsm = Gsm::simulated(emulator, "");
+        assert!(gsm.get_cell_info().unwrap().is_empty());
+    }
+
+    #[test]
+    fn network_time() {
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        let now = gsm.get_network_time().unwrap();
+        assert!((now.sec - time::now_utc().to_timespec().sec).abs() <= 2);
+    }
+
+    #[test]
+    fn network_time_not_set() {
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CCLK?", &["+CCLK: \"04/01/01,00:00:00+00\"", "OK"]);
+        let mut gsm = Gsm::simulated(emulator, "");
+        match gsm.get_network_time() {
+            Err(GsmError::UnexpectedResponse { .. }) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn network_time_without_network() {
+        let mut emulator = Emulator::new();
+        emulator.add_dropout(Duration::from_secs(0), Duration::from_secs(60));
+        let mut gsm = Gsm::simulated(emulator, "");
+        match gsm.get_network_time() {
+            Err(GsmError::UnexpectedResponse { .. }) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn network_time_power_off() {
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        gsm.simulated_on = false;
+        match gsm.get_network_time() {
+            Err(GsmError::PowerOff) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
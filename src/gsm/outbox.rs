@@ -0,0 +1,577 @@
+//! Persistent SMS outbox.
+//!
+//! Messages are stored on disk, one file per message, until they are sent, so that nothing is
+//! lost when there is no coverage or the system reboots, even into safe mode. Pending messages
+//! are sent in priority order, oldest first, once the modem is registered in the network. A new
+//! position report replaces the pending ones to the same number, since only the freshest position
+//! is useful.
+//!
+//! Failed messages are retried, resending only the segments that failed. They are only dropped
+//! after a permanent error or too many failed attempts, and landing reports and high priority
+//! messages never are.
+
+use std::{fs, io, fmt};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use time;
+use log::LogLevel::*;
+
+use logger::Logger;
+use super::{Gsm, GsmError, Transport};
+
+/// Directory where pending messages are stored.
+pub const OUTBOX_DIR: &'static str = "data/outbox";
+/// Extension of the pending message files.
+const MESSAGE_EXTENSION: &'static str = "sms";
+/// Failed attempts to send a message, with errors that are not transient, before dropping it.
+const MAX_SEND_ATTEMPTS: u32 = 10;
+
+/// Priority of an outgoing message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+impl FromStr for Priority {
+    type Err = io::Error;
+    fn from_str(s: &str) -> Result<Priority, io::Error> {
+        match s {
+            "Low" => Ok(Priority::Low),
+            "Normal" => Ok(Priority::Normal),
+            "High" => Ok(Priority::High),
+            _ => Err(invalid_data(&format!("invalid message priority '{}'", s))),
+        }
+    }
+}
+
+/// Kind of an outgoing message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    /// Position report, replaced by newer position reports to the same number.
+    Position,
+    /// Landing report, never replaced, since it is the one needed to recover the probe.
+    Landing,
+    /// Any other message, always sent.
+    Other,
+}
+
+impl FromStr for MessageKind {
+    type Err = io::Error;
+    fn from_str(s: &str) -> Result<MessageKind, io::Error> {
+        match s {
+            "Position" => Ok(MessageKind::Position),
+            "Landing" => Ok(MessageKind::Landing),
+            "Other" => Ok(MessageKind::Other),
+            _ => Err(invalid_data(&format!("invalid message kind '{}'", s))),
+        }
+    }
+}
+
+/// Message waiting in the outbox.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingSms {
+    id: u64,
+    priority: Priority,
+    kind: MessageKind,
+    created: i64,
+    attempts: u32,
+    number: String,
+    reference: Option<u8>,
+    sent: Vec<u8>,
+    text: String,
+}
+
+impl OutgoingSms {
+    /// Gets the kind of the message.
+    pub fn get_kind(&self) -> MessageKind {
+        self.kind
+    }
+
+    /// Gets the destination number.
+    pub fn get_number(&self) -> &str {
+        &self.number
+    }
+
+    /// Gets the text of the message.
+    #[cfg(test)]
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+
+    /// Checks if the message must be kept until it is sent, whatever the errors sending it.
+    fn is_kept(&self) -> bool {
+        self.kind == MessageKind::Landing || self.priority == Priority::High
+    }
+}
+
+impl fmt::Display for OutgoingSms {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f,
+                    "priority={:?}\nkind={:?}\ncreated={}\nattempts={}\nnumber={}\n",
+                    self.priority,
+                    self.kind,
+                    self.created,
+                    self.attempts,
+                    self.number));
+        // The segments already sent are only known once the message was tried.
+        if let Some(reference) = self.reference {
+            let sent = self.sent.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            try!(write!(f, "reference={}\nsent={}\n", reference, sent.join(",")));
+        }
+        write!(f, "\n{}", self.text)
+    }
+}
+
+/// Disk-backed queue of outgoing SMS.
+pub struct Outbox {
+    dir: PathBuf,
+    messages: Vec<OutgoingSms>,
+    next_id: u64,
+    logger: Logger,
+}
+
+impl Outbox {
+    /// Opens the outbox in the given directory, loading the messages pending from previous runs.
+    ///
+    /// The outbox logs to the given logger, usually the GSM log.
+    pub fn open<P: AsRef<Path>>(dir: P, mut logger: Logger) -> Result<Outbox, io::Error> {
+        let dir = dir.as_ref().to_path_buf();
+        try!(fs::create_dir_all(&dir));
+
+        let mut messages = Vec::new();
+        for entry in try!(fs::read_dir(&dir)) {
+            let path = try!(entry).path();
+            if path.extension().and_then(|e| e.to_str()) != Some(MESSAGE_EXTENSION) {
+                continue;
+            }
+            match load_message(&path) {
+                Ok(message) => messages.push(message),
+                Err(e) => {
+                    logger.log(&format!("Discarding invalid outbox file {}: {}",
+                                        path.display(),
+                                        e),
+                               Error);
+                    try!(fs::remove_file(&path));
+                }
+            }
+        }
+        let next_id = messages.iter().map(|m| m.id + 1).max().unwrap_or(0);
+        logger.log(&format!("Outbox opened with {} pending messages.", messages.len()),
+                   Info);
+
+        Ok(Outbox {
+            dir: dir,
+            messages: messages,
+            next_id: next_id,
+            logger: logger,
+        })
+    }
+
+    /// Gets the pending messages.
+    pub fn pending(&self) -> &[OutgoingSms] {
+        &self.messages
+    }
+
+    /// Adds a message to the outbox, storing it on disk.
+    ///
+    /// A position report replaces any pending position report to the same number.
+    pub fn push<N: Into<String>, M: Into<String>>(&mut self,
+                                                  number: N,
+                                                  text: M,
+                                                  priority: Priority,
+                                                  kind: MessageKind)
+                                                  -> Result<(), io::Error> {
+        let message = OutgoingSms {
+            id: self.next_id,
+            priority: priority,
+            kind: kind,
+            created: time::get_time().sec,
+            attempts: 0,
+            number: number.into(),
+            reference: None,
+            sent: Vec::new(),
+            text: text.into(),
+        };
+        self.next_id += 1;
+
+        if kind == MessageKind::Position {
+            let stale = self.messages
+                .iter()
+                .filter(|m| m.kind == MessageKind::Position && m.number == message.number)
+                .map(|m| m.id)
+                .collect::<Vec<_>>();
+            for id in stale {
+                self.logger.log(&format!("Dropping stale position report {} to {}.",
+                                         id,
+                                         message.number),
+                                Info);
+                try!(self.remove(id));
+            }
+        }
+
+        try!(self.store(&message));
+        self.logger.log(&format!("Message {} to {} queued with {:?} priority: \"{}\"",
+                                 message.id,
+                                 message.number,
+                                 message.priority,
+                                 message.text),
+                        Info);
+        self.messages.push(message);
+
+        Ok(())
+    }
+
+    /// Sends the pending messages if the modem has connectivity, highest priority and oldest
+    /// first, returning how many were sent.
+    ///
+    /// Sending stops at the first transient error, leaving the rest of the messages for a later
+    /// retry. Messages failing with a permanent error, or failing `MAX_SEND_ATTEMPTS` times, are
+    /// dropped, unless they must be kept until sent.
+    pub fn flush<T: Transport>(&mut self, gsm: &mut Gsm<T>) -> Result<usize, GsmError> {
+        if self.messages.is_empty() || !try!(gsm.has_connectivity()) {
+            return Ok(0);
+        }
+
+        let mut queue = self.messages.clone();
+        queue.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.created.cmp(&b.created)));
+
+        let mut sent = 0;
+        for mut message in queue {
+            self.logger.log(&format!("Sending message {} to {} (attempt {})…",
+                                     message.id,
+                                     message.number,
+                                     message.attempts + 1),
+                            Info);
+
+            // Segments are resent with the first reference, so that they can be joined.
+            let reference = match message.reference {
+                Some(reference) => reference,
+                None => gsm.new_concatenated_reference(),
+            };
+            message.reference = Some(reference);
+            let error = match gsm.send_sms_segments(&message.text,
+                                                    &message.number,
+                                                    reference,
+                                                    &message.sent) {
+                Ok(segments) => {
+                    let mut error = None;
+                    for (segment, result) in segments {
+                        match result {
+                            Ok(_) => message.sent.push(segment),
+                            Err(e) => error = error.or(Some(e)),
+                        }
+                    }
+                    error
+                }
+                Err(e) => Some(e),
+            };
+
+            let e = match error {
+                None => {
+                    self.logger.log(&format!("Message {} sent.", message.id), Info);
+                    try!(self.remove(message.id));
+                    sent += 1;
+                    continue;
+                }
+                Some(e) => e,
+            };
+            message.attempts += 1;
+            if !e.is_transient() && !message.is_kept() &&
+               (e.is_permanent() || message.attempts >= MAX_SEND_ATTEMPTS) {
+                self.logger.log(&format!("Message {} failed {} times, dropping it: {}",
+                                         message.id,
+                                         message.attempts,
+                                         e),
+                                Error);
+                try!(self.remove(message.id));
+                continue;
+            }
+
+            self.logger.log(&format!("Message {} not sent, will retry: {}", message.id, e),
+                            Warn);
+            try!(self.store(&message));
+            if let Some(m) = self.messages.iter_mut().find(|m| m.id == message.id) {
+                *m = message;
+            }
+            if e.is_transient() {
+                break;
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// Gets the path of the file of the given message.
+    fn path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{:010}.{}", id, MESSAGE_EXTENSION))
+    }
+
+    /// Writes a message to disk.
+    fn store(&self, message: &OutgoingSms) -> Result<(), io::Error> {
+        let mut f = try!(fs::File::create(self.path(message.id)));
+        try!(f.write_all(message.to_string().as_bytes()));
+        f.sync_all()
+    }
+
+    /// Removes a message from the outbox and from disk.
+    fn remove(&mut self, id: u64) -> Result<(), io::Error> {
+        self.messages.retain(|m| m.id != id);
+        fs::remove_file(self.path(id))
+    }
+}
+
+/// Loads a message from its file.
+fn load_message(path: &Path) -> Result<OutgoingSms, io::Error> {
+    let id = try!(path.file_stem()
+        .and_then(|s| s.to_str())
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid_data("invalid message file name")));
+
+    let mut contents = String::new();
+    try!(try!(fs::File::open(path)).read_to_string(&mut contents));
+    let (header, text) = match contents.find("\n\n") {
+        Some(i) => (&contents[..i], &contents[i + 2..]),
+        None => return Err(invalid_data("missing message text")),
+    };
+
+    let mut priority = None;
+    let mut kind = None;
+    let mut created = None;
+    let mut attempts = None;
+    let mut number = None;
+    let mut reference = None;
+    let mut sent = Vec::new();
+    for line in header.lines() {
+        let mut parts = line.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some("priority"), Some(value)) => priority = Some(try!(value.parse())),
+            (Some("kind"), Some(value)) => kind = Some(try!(value.parse())),
+            (Some("created"), Some(value)) => {
+                created = Some(try!(value.parse().map_err(|_| invalid_data("invalid time"))))
+            }
+            (Some("attempts"), Some(value)) => {
+                attempts = Some(try!(value.parse().map_err(|_| invalid_data("invalid attempts"))))
+            }
+            (Some("number"), Some(value)) => number = Some(value.to_owned()),
+            (Some("reference"), Some(value)) => {
+                reference = Some(try!(value.parse().map_err(|_| invalid_data("invalid reference"))))
+            }
+            (Some("sent"), Some(value)) => {
+                for segment in value.split(',').filter(|s| !s.is_empty()) {
+                    sent.push(try!(segment.parse().map_err(|_| invalid_data("invalid segment"))));
+                }
+            }
+            _ => return Err(invalid_data(&format!("invalid header line '{}'", line))),
+        }
+    }
+
+    match (priority, kind, created, attempts, number) {
+        (Some(priority), Some(kind), Some(created), Some(attempts), Some(number)) => {
+            Ok(OutgoingSms {
+                id: id,
+                priority: priority,
+                kind: kind,
+                created: created,
+                attempts: attempts,
+                number: number,
+                reference: reference,
+                sent: sent,
+                text: text.to_owned(),
+            })
+        }
+        _ => Err(invalid_data("missing header field")),
+    }
+}
+
+/// Creates an invalid data error.
+fn invalid_data(description: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, description)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    #[cfg(feature = "sms")]
+    use std::time::Duration;
+
+    use logger::Logger;
+    use super::*;
+    #[cfg(feature = "sms")]
+    use super::super::pdu;
+    #[cfg(feature = "sms")]
+    use super::super::transport::ScriptedTransport;
+
+    /// Opens the outbox in a temporary directory with the given name, emptying it first if
+    /// `empty` is set.
+    fn open_outbox(name: &str, empty: bool) -> Outbox {
+        let dir = env::temp_dir().join(name);
+        if empty {
+            let _ = fs::remove_dir_all(&dir);
+        }
+        let logger = Logger::new(&env::temp_dir().join("Outbox"), "OutboxTest", "Outbox")
+            .unwrap();
+        Outbox::open(dir, logger).unwrap()
+    }
+
+    /// Expects the given segments of an SMS to `+34600000001`, answered with the given result
+    /// lines, and the connectivity check first if `check` is set.
+    #[cfg(feature = "sms")]
+    fn expect_sms<'a>(transport: &'a mut ScriptedTransport,
+                      check: bool,
+                      text: &str,
+                      reference: u8,
+                      segments: &[(usize, &[&str])])
+                      -> &'a mut ScriptedTransport {
+        let pdus = pdu::encode_submit("+34600000001",
+                                      text,
+                                      reference,
+                                      Some(Duration::from_secs(24 * 60 * 60)),
+                                      true)
+            .unwrap();
+        if check {
+            transport.expect("AT+CREG?", &["+CREG: 0,1", "OK"]);
+        }
+        transport.expect("AT+CMGF=0", &["OK"]).expect("AT+CNMI=2,1,0,1,0", &["OK"]);
+        for &(segment, result) in segments {
+            let pdu = &pdus[segment - 1];
+            transport.expect(format!("AT+CMGS={}", pdu.get_length()), &["> "])
+                .expect(format!("{}\u{1A}", pdu.get_hex()), result);
+        }
+        transport
+    }
+
+    #[test]
+    fn stale_positions() {
+        let mut outbox = open_outbox("OutboxStale", true);
+        outbox.push("+34600000001", "Lat: 1", Priority::Normal, MessageKind::Position).unwrap();
+        outbox.push("+34600000002", "Lat: 2", Priority::Normal, MessageKind::Position).unwrap();
+        outbox.push("+34600000001", "Landed.", Priority::High, MessageKind::Landing).unwrap();
+        outbox.push("+34600000001", "Lat: 3", Priority::Normal, MessageKind::Position).unwrap();
+
+        let texts = outbox.pending().iter().map(|m| m.get_text()).collect::<Vec<_>>();
+        assert_eq!(texts, ["Lat: 2", "Landed.", "Lat: 3"]);
+        assert_eq!(fs::read_dir(env::temp_dir().join("OutboxStale")).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn reload() {
+        let mut outbox = open_outbox("OutboxReload", true);
+        outbox.push("+34600000001", "Landed.\n\nLat: 1", Priority::High, MessageKind::Landing)
+            .unwrap();
+        outbox.push("+34600000002", "STATUS", Priority::Low, MessageKind::Other).unwrap();
+        let pending = outbox.pending().to_vec();
+        fs::write(env::temp_dir().join("OutboxReload").join("0000000099.sms"), "garbage")
+            .unwrap();
+
+        // Invalid files are discarded, and new messages do not reuse the loaded identifiers.
+        let mut outbox = open_outbox("OutboxReload", false);
+        assert_eq!(outbox.pending(), pending.as_slice());
+        outbox.push("+34600000003", "POS", Priority::Normal, MessageKind::Other).unwrap();
+        assert_eq!(outbox.pending()[2].id, 2);
+        assert_eq!(fs::read_dir(env::temp_dir().join("OutboxReload")).unwrap().count(), 3);
+    }
+
+    #[test]
+    #[cfg(feature = "sms")]
+    fn priority_order() {
+        let mut outbox = open_outbox("OutboxPriority", true);
+        outbox.push("+34600000001", "low", Priority::Low, MessageKind::Other).unwrap();
+        outbox.push("+34600000001", "normal", Priority::Normal, MessageKind::Other).unwrap();
+        outbox.push("+34600000001", "high", Priority::High, MessageKind::Other).unwrap();
+        let mut transport = ScriptedTransport::new();
+        expect_sms(&mut transport, true, "high", 1, &[(1, &["+CMGS: 1", "OK"])]);
+        expect_sms(&mut transport, false, "normal", 2, &[(1, &["+CMGS: 2", "OK"])]);
+        expect_sms(&mut transport, false, "low", 3, &[(1, &["+CMGS: 3", "OK"])]);
+        let mut gsm = Gsm::simulated(transport, "");
+
+        assert_eq!(outbox.flush(&mut gsm).unwrap(), 3);
+        assert!(outbox.pending().is_empty());
+        assert!(gsm.serial.is_finished());
+    }
+
+    #[test]
+    #[cfg(feature = "sms")]
+    fn transient_errors() {
+        let mut outbox = open_outbox("OutboxTransient", true);
+        outbox.push("+34600000001", "first", Priority::Normal, MessageKind::Other).unwrap();
+        outbox.push("+34600000001", "second", Priority::Normal, MessageKind::Other).unwrap();
+        let mut transport = ScriptedTransport::new();
+        // Network out of order: the second message is not even tried.
+        expect_sms(&mut transport, true, "first", 1, &[(1, &["+CMS ERROR: 38"])]);
+        let mut gsm = Gsm::simulated(transport, "");
+
+        assert_eq!(outbox.flush(&mut gsm).unwrap(), 0);
+        assert!(gsm.serial.is_finished());
+        assert_eq!(outbox.pending().len(), 2);
+        assert_eq!(outbox.pending()[0].attempts, 1);
+        assert_eq!(outbox.pending()[1].attempts, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "sms")]
+    fn permanent_errors() {
+        let mut outbox = open_outbox("OutboxPermanent", true);
+        outbox.push("+34600000001", "Landed.", Priority::High, MessageKind::Landing).unwrap();
+        outbox.push("+34600000001", "report", Priority::Normal, MessageKind::Other).unwrap();
+        let mut transport = ScriptedTransport::new();
+        // Unassigned number: only the landing report is kept.
+        expect_sms(&mut transport, true, "Landed.", 1, &[(1, &["+CMS ERROR: 1"])]);
+        expect_sms(&mut transport, false, "report", 2, &[(1, &["+CMS ERROR: 1"])]);
+        let mut gsm = Gsm::simulated(transport, "");
+
+        assert_eq!(outbox.flush(&mut gsm).unwrap(), 0);
+        assert!(gsm.serial.is_finished());
+        assert_eq!(outbox.pending().len(), 1);
+        assert_eq!(outbox.pending()[0].get_kind(), MessageKind::Landing);
+    }
+
+    #[test]
+    #[cfg(feature = "sms")]
+    fn bounded_attempts() {
+        let mut outbox = open_outbox("OutboxAttempts", true);
+        outbox.push("+34600000001", "report", Priority::Normal, MessageKind::Other).unwrap();
+        let mut transport = ScriptedTransport::new();
+        for _ in 0..MAX_SEND_ATTEMPTS {
+            expect_sms(&mut transport, true, "report", 1, &[(1, &["+CMS ERROR: 500"])]);
+        }
+        let mut gsm = Gsm::simulated(transport, "");
+
+        for attempt in 1..MAX_SEND_ATTEMPTS {
+            assert_eq!(outbox.flush(&mut gsm).unwrap(), 0);
+            assert_eq!(outbox.pending()[0].attempts, attempt);
+        }
+        assert_eq!(outbox.flush(&mut gsm).unwrap(), 0);
+        assert!(outbox.pending().is_empty());
+        assert!(gsm.serial.is_finished());
+    }
+
+    #[test]
+    #[cfg(feature = "sms")]
+    fn failed_segments() {
+        let text = "a".repeat(200);
+        let mut outbox = open_outbox("OutboxSegments", true);
+        outbox.push("+34600000001", text.as_str(), Priority::Normal, MessageKind::Other).unwrap();
+        let mut transport = ScriptedTransport::new();
+        expect_sms(&mut transport,
+                   true,
+                   &text,
+                   1,
+                   &[(1, &["+CMGS: 40", "OK"]), (2, &["+CMS ERROR: 38"])]);
+        let mut gsm = Gsm::simulated(transport, "");
+        assert_eq!(outbox.flush(&mut gsm).unwrap(), 0);
+        assert!(gsm.serial.is_finished());
+
+        // Only the failed segment is resent, with the same reference, even after a restart.
+        let mut outbox = open_outbox("OutboxSegments", false);
+        let mut transport = ScriptedTransport::new();
+        expect_sms(&mut transport, true, &text, 1, &[(2, &["+CMGS: 41", "OK"])]);
+        let mut gsm = Gsm::simulated(transport, "");
+        assert_eq!(outbox.flush(&mut gsm).unwrap(), 1);
+        assert!(outbox.pending().is_empty());
+        assert!(gsm.serial.is_finished());
+    }
+}
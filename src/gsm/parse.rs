@@ -0,0 +1,1300 @@
+//! Parsers for SIM800 information responses.
+//!
+//! Each information response has its own type, parsed from the complete response line (prefix
+//! included) with `FromStr`, following the formats in the SIM800 AT command manual.
+
+use std::fmt;
+use std::str::FromStr;
+use std::error::Error as StdError;
+
+use time::{self, Timespec};
+
+use Coordinates;
+
+/// Error parsing an information response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseResponseError {
+    /// The line did not start with the expected prefix.
+    Prefix {
+        expected: &'static str,
+        line: String,
+    },
+    /// A mandatory field was missing.
+    MissingField {
+        response: &'static str,
+        field: &'static str,
+    },
+    /// A field had an invalid value.
+    InvalidField {
+        response: &'static str,
+        field: &'static str,
+        value: String,
+    },
+}
+
+impl fmt::Display for ParseResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseResponseError::Prefix { expected, ref line } => {
+                write!(f, "expected '{}' response, found '{}'", expected, line)
+            }
+            ParseResponseError::MissingField { response, field } => {
+                write!(f, "missing <{}> field in '{}' response", field, response)
+            }
+            ParseResponseError::InvalidField { response, field, ref value } => {
+                write!(f,
+                       "invalid <{}> field in '{}' response: '{}'",
+                       field,
+                       response,
+                       value)
+            }
+        }
+    }
+}
+
+impl StdError for ParseResponseError {
+    fn description(&self) -> &str {
+        match *self {
+            ParseResponseError::Prefix { .. } => "unexpected response",
+            ParseResponseError::MissingField { .. } => "missing field in response",
+            ParseResponseError::InvalidField { .. } => "invalid field in response",
+        }
+    }
+}
+
+/// Fields of an information response.
+struct Fields<'a> {
+    response: &'static str,
+    fields: Vec<&'a str>,
+}
+
+impl<'a> Fields<'a> {
+    /// Splits the given line into its fields, checking the response prefix.
+    ///
+    /// Fields are separated by commas, except inside quoted strings, and quotes are removed.
+    fn new(line: &'a str, response: &'static str) -> Result<Fields<'a>, ParseResponseError> {
+        let line = line.trim();
+        let rest = match line.strip_prefix(response) {
+            Some(rest) => rest.trim_start(),
+            None => {
+                return Err(ParseResponseError::Prefix {
+                    expected: response,
+                    line: line.to_owned(),
+                })
+            }
+        };
+
+        let mut fields = Vec::new();
+        if !rest.is_empty() {
+            let mut in_quotes = false;
+            let mut start = 0;
+            for (i, c) in rest.char_indices() {
+                match c {
+                    '"' => in_quotes = !in_quotes,
+                    ',' if !in_quotes => {
+                        fields.push(rest[start..i].trim().trim_matches('"'));
+                        start = i + 1;
+                    }
+                    _ => {}
+                }
+            }
+            fields.push(rest[start..].trim().trim_matches('"'));
+        }
+
+        Ok(Fields {
+            response: response,
+            fields: fields,
+        })
+    }
+
+    /// Gets the number of fields.
+    fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Gets a mandatory field as a string.
+    fn get(&self, index: usize, field: &'static str) -> Result<&'a str, ParseResponseError> {
+        match self.fields.get(index) {
+            Some(value) => Ok(value),
+            None => {
+                Err(ParseResponseError::MissingField {
+                    response: self.response,
+                    field: field,
+                })
+            }
+        }
+    }
+
+    /// Gets an optional field as a string.
+    fn get_optional(&self, index: usize) -> Option<&'a str> {
+        self.fields.get(index).cloned()
+    }
+
+    /// Parses a mandatory field.
+    fn parse<F: FromStr>(&self,
+                         index: usize,
+                         field: &'static str)
+                         -> Result<F, ParseResponseError> {
+        let value = try!(self.get(index, field));
+        self.parse_value(value, field)
+    }
+
+    /// Parses an optional field.
+    fn parse_optional<F: FromStr>(&self,
+                                  index: usize,
+                                  field: &'static str)
+                                  -> Result<Option<F>, ParseResponseError> {
+        match self.get_optional(index) {
+            Some(value) => Ok(Some(try!(self.parse_value(value, field)))),
+            None => Ok(None),
+        }
+    }
+
+    /// Parses a field value.
+    fn parse_value<F: FromStr>(&self,
+                               value: &str,
+                               field: &'static str)
+                               -> Result<F, ParseResponseError> {
+        value.parse().map_err(|_| self.invalid(field, value))
+    }
+
+    /// Creates an invalid field error.
+    fn invalid(&self, field: &'static str, value: &str) -> ParseResponseError {
+        ParseResponseError::InvalidField {
+            response: self.response,
+            field: field,
+            value: value.to_owned(),
+        }
+    }
+}
+
+/// Network registration status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrationStatus {
+    NotRegistered,
+    RegisteredHome,
+    Searching,
+    Denied,
+    Unknown,
+    RegisteredRoaming,
+}
+
+impl RegistrationStatus {
+    /// Checks if the modem is registered, either in its home network or roaming.
+    pub fn is_registered(&self) -> bool {
+        *self == RegistrationStatus::RegisteredHome ||
+        *self == RegistrationStatus::RegisteredRoaming
+    }
+}
+
+/// Network registration response: `+CREG: <n>,<stat>[,<lac>,<ci>]`, or `+CGREG` for GPRS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registration {
+    status: RegistrationStatus,
+}
+
+impl Registration {
+    /// Gets the registration status.
+    pub fn get_status(&self) -> RegistrationStatus {
+        self.status
+    }
+}
+
+impl FromStr for Registration {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<Registration, ParseResponseError> {
+        let gprs = s.trim().starts_with("+CGREG:");
+        let fields = try!(Fields::new(s, if gprs { "+CGREG:" } else { "+CREG:" }));
+
+        let status = match try!(fields.parse::<u8>(1, "stat")) {
+            0 => RegistrationStatus::NotRegistered,
+            1 => RegistrationStatus::RegisteredHome,
+            2 => RegistrationStatus::Searching,
+            3 => RegistrationStatus::Denied,
+            4 => RegistrationStatus::Unknown,
+            5 => RegistrationStatus::RegisteredRoaming,
+            _ => return Err(fields.invalid("stat", try!(fields.get(1, "stat")))),
+        };
+
+        Ok(Registration { status: status })
+    }
+}
+
+/// Battery charge status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChargeStatus {
+    NotCharging,
+    Charging,
+    Finished,
+}
+
+/// Battery charge response: `+CBC: <bcs>,<bcl>,<voltage>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryCharge {
+    status: ChargeStatus,
+    percent: u8,
+    voltage: u32,
+}
+
+impl BatteryCharge {
+    /// Gets the charge status.
+    pub fn get_status(&self) -> ChargeStatus {
+        self.status
+    }
+
+    /// Gets the battery level estimated by the modem, in percent.
+    pub fn get_percent(&self) -> u8 {
+        self.percent
+    }
+
+    /// Gets the battery voltage, in millivolts.
+    pub fn get_voltage(&self) -> u32 {
+        self.voltage
+    }
+}
+
+impl FromStr for BatteryCharge {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<BatteryCharge, ParseResponseError> {
+        let fields = try!(Fields::new(s, "+CBC:"));
+
+        let status = match try!(fields.parse::<u8>(0, "bcs")) {
+            0 => ChargeStatus::NotCharging,
+            1 => ChargeStatus::Charging,
+            2 => ChargeStatus::Finished,
+            _ => return Err(fields.invalid("bcs", try!(fields.get(0, "bcs")))),
+        };
+        let percent = try!(fields.parse::<u8>(1, "bcl"));
+        if percent > 100 {
+            return Err(fields.invalid("bcl", try!(fields.get(1, "bcl"))));
+        }
+
+        Ok(BatteryCharge {
+            status: status,
+            percent: percent,
+            voltage: try!(fields.parse(2, "voltage")),
+        })
+    }
+}
+
+/// ADC reading response: `+CADC: <status>,<value>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdcReading {
+    success: bool,
+    value: u32,
+}
+
+impl AdcReading {
+    /// Checks if the ADC was read successfully.
+    pub fn is_success(&self) -> bool {
+        self.success
+    }
+
+    /// Gets the ADC value, in millivolts.
+    pub fn get_value(&self) -> u32 {
+        self.value
+    }
+}
+
+impl FromStr for AdcReading {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<AdcReading, ParseResponseError> {
+        let fields = try!(Fields::new(s, "+CADC:"));
+
+        let success = match try!(fields.get(0, "status")) {
+            "0" => false,
+            "1" => true,
+            value => return Err(fields.invalid("status", value)),
+        };
+
+        Ok(AdcReading {
+            success: success,
+            value: try!(fields.parse(1, "value")),
+        })
+    }
+}
+
+/// Signal quality response: `+CSQ: <rssi>,<ber>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignalQuality {
+    rssi: u8,
+    ber: u8,
+}
+
+impl SignalQuality {
+    /// Gets the received signal strength, in dBm, if known.
+    ///
+    /// The modem only reports values between -115 dBm and -52 dBm, lower and higher values are
+    /// reported as those limits.
+    pub fn get_rssi_dbm(&self) -> Option<i32> {
+        match self.rssi {
+            0 => Some(-115),
+            1 => Some(-111),
+            rssi @ 2..=30 => Some(-110 + 2 * (rssi as i32 - 2)),
+            31 => Some(-52),
+            _ => None,
+        }
+    }
+
+    /// Gets the bit error rate as the RXQUAL value (0-7), if known.
+    pub fn get_ber(&self) -> Option<u8> {
+        if self.ber <= 7 { Some(self.ber) } else { None }
+    }
+}
+
+impl FromStr for SignalQuality {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<SignalQuality, ParseResponseError> {
+        let fields = try!(Fields::new(s, "+CSQ:"));
+
+        let rssi = try!(fields.parse::<u8>(0, "rssi"));
+        if rssi > 31 && rssi != 99 {
+            return Err(fields.invalid("rssi", try!(fields.get(0, "rssi"))));
+        }
+        let ber = try!(fields.parse::<u8>(1, "ber"));
+        if ber > 7 && ber != 99 {
+            return Err(fields.invalid("ber", try!(fields.get(1, "ber"))));
+        }
+
+        Ok(SignalQuality {
+            rssi: rssi,
+            ber: ber,
+        })
+    }
+}
+
+/// Engineering mode cell information: `+CENG: <cell>,"<data>"`.
+///
+/// Cell 0 is the serving cell, with `<arfcn>,<rxl>,<rxq>,<mcc>,<mnc>,<bsic>,<cellid>,<rla>,<txp>,
+/// <lac>,<TA>` data, and cells 1 to 6 are the neighbour cells, with
+/// `<arfcn>,<rxl>,<bsic>,<cellid>,<mcc>,<mnc>,<lac>` data. The cell ID and the LAC are
+/// hexadecimal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellInfo {
+    cell: u8,
+    mcc: u16,
+    mnc: u16,
+    lac: u16,
+    cell_id: u16,
+    rx_level: u8,
+}
+
+impl CellInfo {
+    /// Checks if this is the serving cell.
+    pub fn is_serving(&self) -> bool {
+        self.cell == 0
+    }
+
+    /// Gets the received signal level, in dBm.
+    pub fn get_rx_level_dbm(&self) -> i32 {
+        self.rx_level as i32 - 110
+    }
+
+    /// Checks if the cell was identified, since the modem reports empty neighbour cells with
+    /// zeros.
+    pub fn is_identified(&self) -> bool {
+        self.mcc != 0 && self.cell_id != 0 && self.cell_id != 0xFFFF
+    }
+}
+
+impl fmt::Display for CellInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{}-{:02} {:04X}:{:04X} {}dBm",
+               self.mcc,
+               self.mnc,
+               self.lac,
+               self.cell_id,
+               self.get_rx_level_dbm())
+    }
+}
+
+impl FromStr for CellInfo {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<CellInfo, ParseResponseError> {
+        let fields = try!(Fields::new(s, "+CENG:"));
+
+        let cell = try!(fields.parse::<u8>(0, "cell"));
+        if cell > 6 {
+            return Err(fields.invalid("cell", try!(fields.get(0, "cell"))));
+        }
+        let data = try!(fields.get(1, "data")).split(',').map(|f| f.trim()).collect::<Vec<_>>();
+        let get = |index: usize, field: &'static str| {
+            data.get(index).cloned().ok_or(ParseResponseError::MissingField {
+                response: "+CENG:",
+                field: field,
+            })
+        };
+        let decimal = |index: usize, field: &'static str| {
+            get(index, field).and_then(|value| fields.parse_value::<u16>(value, field))
+        };
+        let hexadecimal = |index: usize, field: &'static str| {
+            get(index, field).and_then(|value| {
+                u16::from_str_radix(value, 16).map_err(|_| fields.invalid(field, value))
+            })
+        };
+
+        // Positions of the MCC, MNC, LAC and cell ID in the data.
+        let (mcc, mnc, lac, cell_id) = if cell == 0 { (3, 4, 9, 6) } else { (4, 5, 6, 3) };
+        let rx_level = try!(get(1, "rxl").and_then(|value| fields.parse_value::<u8>(value, "rxl")));
+        if rx_level > 63 {
+            return Err(fields.invalid("rxl", try!(get(1, "rxl"))));
+        }
+
+        Ok(CellInfo {
+            cell: cell,
+            mcc: try!(decimal(mcc, "mcc")),
+            mnc: try!(decimal(mnc, "mnc")),
+            lac: try!(hexadecimal(lac, "lac")),
+            cell_id: try!(hexadecimal(cell_id, "cellid")),
+            rx_level: rx_level,
+        })
+    }
+}
+
+/// Cell location response: `+CIPGSMLOC: <locationcode>[,<longitude>,<latitude>,<date>,<time>]`.
+///
+/// The coordinates, date and time are only present if the location code is 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellLocation {
+    code: u16,
+    coordinates: Option<Coordinates>,
+    date_time: Option<String>,
+}
+
+impl CellLocation {
+    /// Gets the location code, 0 meaning success.
+    pub fn get_code(&self) -> u16 {
+        self.code
+    }
+
+    /// Gets the meaning of the location code.
+    pub fn get_code_description(&self) -> &'static str {
+        match self.code {
+            0 => "success",
+            404 => "not found",
+            408 => "request time-out",
+            601 => "network error",
+            602 => "no memory",
+            603 => "DNS error",
+            604 => "stack busy",
+            _ => "other error",
+        }
+    }
+
+    /// Gets the coordinates, if the location was found.
+    pub fn get_coordinates(&self) -> Option<Coordinates> {
+        self.coordinates
+    }
+
+    /// Gets the UTC date and time of the location as `YYYY/MM/DD,HH:MM:SS`, if found.
+    pub fn get_date_time(&self) -> Option<&str> {
+        self.date_time.as_deref()
+    }
+}
+
+impl FromStr for CellLocation {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<CellLocation, ParseResponseError> {
+        let fields = try!(Fields::new(s, "+CIPGSMLOC:"));
+
+        let code = try!(fields.parse::<u16>(0, "locationcode"));
+        if code != 0 {
+            return Ok(CellLocation {
+                code: code,
+                coordinates: None,
+                date_time: None,
+            });
+        }
+
+        let longitude = try!(fields.parse::<f64>(1, "longitude"));
+        if !(-180f64..=180f64).contains(&longitude) {
+            return Err(fields.invalid("longitude", try!(fields.get(1, "longitude"))));
+        }
+        let latitude = try!(fields.parse::<f64>(2, "latitude"));
+        if !(-90f64..=90f64).contains(&latitude) {
+            return Err(fields.invalid("latitude", try!(fields.get(2, "latitude"))));
+        }
+        let date = try!(fields.get(3, "date"));
+        let time = try!(fields.get(4, "time"));
+
+        Ok(CellLocation {
+            code: code,
+            coordinates: Some(Coordinates::new(latitude, longitude)),
+            date_time: Some(format!("{},{}", date, time)),
+        })
+    }
+}
+
+/// SMS sent response: `+CMGS: <mr>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmsReference {
+    reference: u8,
+}
+
+impl SmsReference {
+    /// Gets the message reference assigned to the SMS.
+    pub fn get_reference(&self) -> u8 {
+        self.reference
+    }
+}
+
+impl FromStr for SmsReference {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<SmsReference, ParseResponseError> {
+        let fields = try!(Fields::new(s, "+CMGS:"));
+
+        Ok(SmsReference { reference: try!(fields.parse(0, "mr")) })
+    }
+}
+
+/// Delivery status of an SMS, from the `<st>` field of a status report (3GPP TS 23.040).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// The message was delivered to the recipient.
+    Delivered,
+    /// The SMSC is still trying to deliver the message.
+    Pending,
+    /// The message could not be delivered, and the SMSC gave up.
+    Failed,
+}
+
+impl DeliveryStatus {
+    /// Gets the delivery status corresponding to a `<st>` value.
+    pub fn from_status(status: u8) -> DeliveryStatus {
+        match status {
+            0x00..=0x1F => DeliveryStatus::Delivered,
+            0x20..=0x3F => DeliveryStatus::Pending,
+            _ => DeliveryStatus::Failed,
+        }
+    }
+}
+
+/// SMS status report, in text mode: `+CDS: <fo>,<mr>,[<ra>],[<tora>],<scts>,<dt>,<st>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusReport {
+    reference: u8,
+    recipient: String,
+    status: u8,
+}
+
+impl StatusReport {
+    /// Creates a status report for the message with the given reference and recipient.
+    pub fn new<S: Into<String>>(reference: u8, recipient: S, status: u8) -> StatusReport {
+        StatusReport {
+            reference: reference,
+            recipient: recipient.into(),
+            status: status,
+        }
+    }
+
+    /// Gets the reference of the message the report is about.
+    pub fn get_reference(&self) -> u8 {
+        self.reference
+    }
+
+    /// Gets the recipient of the message.
+    pub fn get_recipient(&self) -> &str {
+        &self.recipient
+    }
+
+    /// Gets the delivery status of the message.
+    pub fn get_delivery_status(&self) -> DeliveryStatus {
+        DeliveryStatus::from_status(self.status)
+    }
+}
+
+impl FromStr for StatusReport {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<StatusReport, ParseResponseError> {
+        let fields = try!(Fields::new(s, "+CDS:"));
+
+        Ok(StatusReport {
+            reference: try!(fields.parse(1, "mr")),
+            recipient: fields.get_optional(2).unwrap_or("").to_owned(),
+            status: try!(fields.parse(6, "st")),
+        })
+    }
+}
+
+/// Operator selection mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperatorMode {
+    Automatic,
+    Manual,
+    Deregistered,
+    ManualAutomatic,
+}
+
+/// Operator selection response: `+COPS: <mode>[,<format>,<oper>]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operator {
+    mode: OperatorMode,
+    name: Option<String>,
+}
+
+impl Operator {
+    /// Gets the operator selection mode.
+    pub fn get_mode(&self) -> OperatorMode {
+        self.mode
+    }
+
+    /// Gets the operator name, if the modem is registered.
+    pub fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+impl FromStr for Operator {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<Operator, ParseResponseError> {
+        let fields = try!(Fields::new(s, "+COPS:"));
+
+        let mode = match try!(fields.parse::<u8>(0, "mode")) {
+            0 => OperatorMode::Automatic,
+            1 => OperatorMode::Manual,
+            2 => OperatorMode::Deregistered,
+            4 => OperatorMode::ManualAutomatic,
+            _ => return Err(fields.invalid("mode", try!(fields.get(0, "mode")))),
+        };
+        let format = try!(fields.parse_optional::<u8>(1, "format"));
+        if format.map_or(false, |f| f > 2) {
+            return Err(fields.invalid("format", try!(fields.get(1, "format"))));
+        }
+        if format.is_some() && fields.len() < 3 {
+            return Err(ParseResponseError::MissingField {
+                response: "+COPS:",
+                field: "oper",
+            });
+        }
+
+        Ok(Operator {
+            mode: mode,
+            name: fields.get_optional(2).map(|s| s.to_owned()),
+        })
+    }
+}
+
+/// SIM card state: `+CPIN: <code>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimState {
+    /// Unlocked and ready.
+    Ready,
+    /// Waiting for the PIN.
+    PinRequired,
+    /// Blocked after too many wrong PINs, waiting for the PUK.
+    PukRequired,
+    /// Waiting for the phone-to-SIM lock password.
+    PhoneSimPinRequired,
+    /// Waiting for the phone-to-SIM lock unblocking password.
+    PhoneSimPukRequired,
+    /// Waiting for the PIN2.
+    Pin2Required,
+    /// Waiting for the PUK2.
+    Puk2Required,
+    /// No SIM card inserted.
+    NotInserted,
+    /// The SIM card is still starting up.
+    NotReady,
+}
+
+impl SimState {
+    /// Checks if the SIM card is ready to be used.
+    pub fn is_ready(&self) -> bool {
+        *self == SimState::Ready
+    }
+}
+
+impl FromStr for SimState {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<SimState, ParseResponseError> {
+        let fields = try!(Fields::new(s, "+CPIN:"));
+
+        match try!(fields.get(0, "code")) {
+            "READY" => Ok(SimState::Ready),
+            "SIM PIN" => Ok(SimState::PinRequired),
+            "SIM PUK" => Ok(SimState::PukRequired),
+            "PH_SIM PIN" => Ok(SimState::PhoneSimPinRequired),
+            "PH_SIM PUK" => Ok(SimState::PhoneSimPukRequired),
+            "SIM PIN2" => Ok(SimState::Pin2Required),
+            "SIM PUK2" => Ok(SimState::Puk2Required),
+            "NOT INSERTED" => Ok(SimState::NotInserted),
+            "NOT READY" => Ok(SimState::NotReady),
+            code => Err(fields.invalid("code", code)),
+        }
+    }
+}
+
+/// Subscriber number response: `+CNUM: [<alpha>],<number>,<type>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriberNumber {
+    number: String,
+}
+
+impl SubscriberNumber {
+    /// Gets the phone number.
+    pub fn get_number(&self) -> &str {
+        &self.number
+    }
+}
+
+impl FromStr for SubscriberNumber {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<SubscriberNumber, ParseResponseError> {
+        let fields = try!(Fields::new(s, "+CNUM:"));
+
+        Ok(SubscriberNumber { number: try!(fields.get(1, "number")).to_owned() })
+    }
+}
+
+/// Real-time clock response: `+CCLK: "<yy/MM/dd,hh:mm:ss±zz>"`, in local time with the time
+/// zone in quarters of an hour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockTime {
+    utc: Timespec,
+}
+
+impl ClockTime {
+    /// Gets the time as a UTC timestamp.
+    pub fn get_utc(&self) -> Timespec {
+        self.utc
+    }
+}
+
+impl FromStr for ClockTime {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<ClockTime, ParseResponseError> {
+        let fields = try!(Fields::new(s, "+CCLK:"));
+        let value = try!(fields.get(0, "time"));
+        if value.len() != 20 || !value.is_char_boundary(17) {
+            return Err(fields.invalid("time", value));
+        }
+
+        let (local, zone) = value.split_at(17);
+        let mut local = try!(time::strptime(local, "%y/%m/%d,%H:%M:%S")
+            .map_err(|_| fields.invalid("time", value)));
+        // Years are counted from 2000 by the modem, but from 1900 by `strptime()`.
+        local.tm_year += 100;
+        let quarters = try!(zone.parse::<i64>().map_err(|_| fields.invalid("time", value)));
+
+        Ok(ClockTime { utc: local.to_timespec() - time::Duration::minutes(15 * quarters) })
+    }
+}
+
+/// State of a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallState {
+    Active,
+    Held,
+    Dialing,
+    Alerting,
+    Incoming,
+    Waiting,
+    Disconnected,
+}
+
+/// Current call response: `+CLCC: <id>,<dir>,<stat>,<mode>,<mpty>[,<number>,<type>]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrentCall {
+    outgoing: bool,
+    state: CallState,
+}
+
+impl CurrentCall {
+    /// Checks if the call was placed by the modem.
+    pub fn is_outgoing(&self) -> bool {
+        self.outgoing
+    }
+
+    /// Gets the state of the call.
+    pub fn get_state(&self) -> CallState {
+        self.state
+    }
+}
+
+impl FromStr for CurrentCall {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<CurrentCall, ParseResponseError> {
+        let fields = try!(Fields::new(s, "+CLCC:"));
+
+        let state = match try!(fields.parse::<u8>(2, "stat")) {
+            0 => CallState::Active,
+            1 => CallState::Held,
+            2 => CallState::Dialing,
+            3 => CallState::Alerting,
+            4 => CallState::Incoming,
+            5 => CallState::Waiting,
+            6 => CallState::Disconnected,
+            _ => return Err(fields.invalid("stat", try!(fields.get(2, "stat")))),
+        };
+
+        try!(fields.parse::<u8>(0, "id"));
+        Ok(CurrentCall {
+            outgoing: try!(fields.parse::<u8>(1, "dir")) == 0,
+            state: state,
+        })
+    }
+}
+
+/// Status of a stored SMS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageStatus {
+    ReceivedUnread,
+    ReceivedRead,
+    StoredUnsent,
+    StoredSent,
+}
+
+impl MessageStatus {
+    /// Checks if the message was received, rather than stored to be sent.
+    pub fn is_received(&self) -> bool {
+        matches!(*self, MessageStatus::ReceivedUnread | MessageStatus::ReceivedRead)
+    }
+}
+
+impl FromStr for MessageStatus {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<MessageStatus, ParseResponseError> {
+        match s {
+            "0" => Ok(MessageStatus::ReceivedUnread),
+            "1" => Ok(MessageStatus::ReceivedRead),
+            "2" => Ok(MessageStatus::StoredUnsent),
+            "3" => Ok(MessageStatus::StoredSent),
+            _ => {
+                Err(ParseResponseError::InvalidField {
+                    response: "+CMGL:",
+                    field: "stat",
+                    value: s.to_owned(),
+                })
+            }
+        }
+    }
+}
+
+/// Header of a stored SMS in PDU mode, as listed with `AT+CMGL`
+/// (`+CMGL: <index>,<stat>,[<alpha>],<length>`). The PDU of the message comes in the next line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageHeader {
+    index: u32,
+    status: MessageStatus,
+    length: usize,
+}
+
+impl MessageHeader {
+    /// Gets the storage index of the message.
+    pub fn get_index(&self) -> u32 {
+        self.index
+    }
+
+    /// Gets the status of the message.
+    pub fn get_status(&self) -> MessageStatus {
+        self.status
+    }
+
+    /// Gets the length of the TPDU in octets, without the SMSC address.
+    pub fn get_length(&self) -> usize {
+        self.length
+    }
+}
+
+impl FromStr for MessageHeader {
+    type Err = ParseResponseError;
+    fn from_str(s: &str) -> Result<MessageHeader, ParseResponseError> {
+        let fields = try!(Fields::new(s, "+CMGL:"));
+        let status = try!(fields.get(1, "stat"));
+        let status = try!(status.parse::<MessageStatus>()
+            .map_err(|_| fields.invalid("stat", status)));
+
+        Ok(MessageHeader {
+            index: try!(fields.parse(0, "index")),
+            status: status,
+            length: try!(fields.parse(3, "length")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a missing field error.
+    fn missing(response: &'static str, field: &'static str) -> ParseResponseError {
+        ParseResponseError::MissingField {
+            response: response,
+            field: field,
+        }
+    }
+
+    /// Creates an invalid field error.
+    fn invalid(response: &'static str, field: &'static str, value: &str) -> ParseResponseError {
+        ParseResponseError::InvalidField {
+            response: response,
+            field: field,
+            value: value.to_owned(),
+        }
+    }
+
+    /// Creates a prefix error.
+    fn prefix(expected: &'static str, line: &str) -> ParseResponseError {
+        ParseResponseError::Prefix {
+            expected: expected,
+            line: line.to_owned(),
+        }
+    }
+
+    #[test]
+    fn registration() {
+        let valid = [("+CREG: 0,1", RegistrationStatus::RegisteredHome),
+                     ("+CREG: 1,5,\"1A2B\",\"00C3\"", RegistrationStatus::RegisteredRoaming),
+                     ("+CGREG: 0,2\r\n", RegistrationStatus::Searching),
+                     ("+CGREG:0,3", RegistrationStatus::Denied),
+                     ("+CREG: 2,0", RegistrationStatus::NotRegistered),
+                     ("+CREG: 2,4", RegistrationStatus::Unknown)];
+        for &(line, status) in &valid {
+            assert_eq!(line.parse::<Registration>().unwrap().get_status(), status, "{}", line);
+        }
+        assert!(RegistrationStatus::RegisteredHome.is_registered());
+        assert!(RegistrationStatus::RegisteredRoaming.is_registered());
+        assert!(!RegistrationStatus::Searching.is_registered());
+
+        let malformed = [("+CREG: 0,6", invalid("+CREG:", "stat", "6")),
+                         ("+CGREG: 0,x", invalid("+CGREG:", "stat", "x")),
+                         ("+CREG: 0,", invalid("+CREG:", "stat", "")),
+                         ("+CREG: 0", missing("+CREG:", "stat")),
+                         ("+CREG:", missing("+CREG:", "stat")),
+                         ("+CSQ: 0,1", prefix("+CREG:", "+CSQ: 0,1")),
+                         ("", prefix("+CREG:", ""))];
+        for &(line, ref error) in &malformed {
+            assert_eq!(line.parse::<Registration>().as_ref(), Err(error), "{}", line);
+        }
+    }
+
+    #[test]
+    fn battery_charge() {
+        let valid = [("+CBC: 0,75,3950", ChargeStatus::NotCharging, 75, 3950),
+                     ("+CBC: 1,100,4200", ChargeStatus::Charging, 100, 4200),
+                     ("  +CBC: 2,0,3300  ", ChargeStatus::Finished, 0, 3300)];
+        for &(line, status, percent, voltage) in &valid {
+            let charge = line.parse::<BatteryCharge>().unwrap();
+            assert_eq!(charge.get_status(), status, "{}", line);
+            assert_eq!(charge.get_percent(), percent, "{}", line);
+            assert_eq!(charge.get_voltage(), voltage, "{}", line);
+        }
+
+        let malformed = [("+CBC: 3,75,3950", invalid("+CBC:", "bcs", "3")),
+                         ("+CBC: 0,101,3950", invalid("+CBC:", "bcl", "101")),
+                         ("+CBC: 0,-1,3950", invalid("+CBC:", "bcl", "-1")),
+                         ("+CBC: 0,75,3.9V", invalid("+CBC:", "voltage", "3.9V")),
+                         ("+CBC: 0,75", missing("+CBC:", "voltage")),
+                         ("+CBC: 0", missing("+CBC:", "bcl")),
+                         ("+CBC:", missing("+CBC:", "bcs")),
+                         ("CBC: 0,75,3950", prefix("+CBC:", "CBC: 0,75,3950"))];
+        for &(line, ref error) in &malformed {
+            assert_eq!(line.parse::<BatteryCharge>().as_ref(), Err(error), "{}", line);
+        }
+    }
+
+    #[test]
+    fn adc_reading() {
+        let reading = "+CADC: 1,1823".parse::<AdcReading>().unwrap();
+        assert!(reading.is_success());
+        assert_eq!(reading.get_value(), 1823);
+        let reading = "+CADC: 0,0".parse::<AdcReading>().unwrap();
+        assert!(!reading.is_success());
+
+        let malformed = [("+CADC: 2,1823", invalid("+CADC:", "status", "2")),
+                         ("+CADC: 1,-5", invalid("+CADC:", "value", "-5")),
+                         ("+CADC: 1", missing("+CADC:", "value")),
+                         ("+CADC:", missing("+CADC:", "status")),
+                         ("+CBC: 1,1823", prefix("+CADC:", "+CBC: 1,1823"))];
+        for &(line, ref error) in &malformed {
+            assert_eq!(line.parse::<AdcReading>().as_ref(), Err(error), "{}", line);
+        }
+    }
+
+    #[test]
+    fn signal_quality() {
+        let valid = [("+CSQ: 0,0", Some(-115), Some(0)),
+                     ("+CSQ: 1,7", Some(-111), Some(7)),
+                     ("+CSQ: 2,3", Some(-110), Some(3)),
+                     ("+CSQ: 30,99", Some(-54), None),
+                     ("+CSQ: 31,0", Some(-52), Some(0)),
+                     ("+CSQ: 99,99", None, None)];
+        for &(line, rssi, ber) in &valid {
+            let quality = line.parse::<SignalQuality>().unwrap();
+            assert_eq!(quality.get_rssi_dbm(), rssi, "{}", line);
+            assert_eq!(quality.get_ber(), ber, "{}", line);
+        }
+
+        let malformed = [("+CSQ: 32,0", invalid("+CSQ:", "rssi", "32")),
+                         ("+CSQ: 15,8", invalid("+CSQ:", "ber", "8")),
+                         ("+CSQ: -1,0", invalid("+CSQ:", "rssi", "-1")),
+                         ("+CSQ: 15", missing("+CSQ:", "ber")),
+                         ("+CSQ:", missing("+CSQ:", "rssi"))];
+        for &(line, ref error) in &malformed {
+            assert_eq!(line.parse::<SignalQuality>().as_ref(), Err(error), "{}", line);
+        }
+    }
+
+    #[test]
+    fn cell_info() {
+        let serving = "+CENG: 0,\"0024,46,00,214,07,63,5AB3,05,05,07D2,255\""
+            .parse::<CellInfo>()
+            .unwrap();
+        assert!(serving.is_serving());
+        assert!(serving.is_identified());
+        assert_eq!(serving.get_rx_level_dbm(), -64);
+        assert_eq!(serving.to_string(), "214-07 07D2:5AB3 -64dBm");
+
+        let neighbour = "+CENG: 1,\"0031,30,63,5AB4,214,07,07D2\"".parse::<CellInfo>().unwrap();
+        assert!(!neighbour.is_serving());
+        assert!(neighbour.is_identified());
+        assert_eq!(neighbour.to_string(), "214-07 07D2:5AB4 -80dBm");
+
+        let empty = "+CENG: 6,\"0000,00,00,0000,000,00,0000\"".parse::<CellInfo>().unwrap();
+        assert!(!empty.is_identified());
+        let unknown = "+CENG: 2,\"0031,30,63,FFFF,214,07,07D2\"".parse::<CellInfo>().unwrap();
+        assert!(!unknown.is_identified());
+
+        let malformed = [("+CENG: 7,\"0031,30,63,5AB4,214,07,07D2\"",
+                          invalid("+CENG:", "cell", "7")),
+                         ("+CENG: 1,\"0031,64,63,5AB4,214,07,07D2\"",
+                          invalid("+CENG:", "rxl", "64")),
+                         ("+CENG: 1,\"0031,30,63,5AB4,2l4,07,07D2\"",
+                          invalid("+CENG:", "mcc", "2l4")),
+                         ("+CENG: 1,\"0031,30,63,5AB4,214,07,G7D2\"",
+                          invalid("+CENG:", "lac", "G7D2")),
+                         ("+CENG: 0,\"0024,46,00,214,07,63,15AB3,05,05,07D2,255\"",
+                          invalid("+CENG:", "cellid", "15AB3")),
+                         ("+CENG: 1,\"0031,30,63,5AB4,214,07\"", missing("+CENG:", "lac")),
+                         ("+CENG: 0,\"0024,46,00,214,07,63,5AB3\"", missing("+CENG:", "lac")),
+                         ("+CENG: 1,\"0031\"", missing("+CENG:", "rxl")),
+                         ("+CENG: 1", missing("+CENG:", "data")),
+                         ("+CENG: 1,0,0", missing("+CENG:", "rxl")),
+                         ("+CENG:", missing("+CENG:", "cell"))];
+        for &(line, ref error) in &malformed {
+            assert_eq!(line.parse::<CellInfo>().as_ref(), Err(error), "{}", line);
+        }
+    }
+
+    #[test]
+    fn cell_location() {
+        let location = "+CIPGSMLOC: 0,-3.703790,40.416775,2017/01/17,15:55:04"
+            .parse::<CellLocation>()
+            .unwrap();
+        assert_eq!(location.get_code(), 0);
+        assert_eq!(location.get_code_description(), "success");
+        assert_eq!(location.get_coordinates(),
+                   Some(Coordinates::new(40.416775, -3.703790)));
+        assert_eq!(location.get_date_time(), Some("2017/01/17,15:55:04"));
+
+        for &(line, description) in &[("+CIPGSMLOC: 404", "not found"),
+                                      ("+CIPGSMLOC: 601", "network error"),
+                                      ("+CIPGSMLOC: 1", "other error")] {
+            let location = line.parse::<CellLocation>().unwrap();
+            assert_eq!(location.get_code_description(), description, "{}", line);
+            assert_eq!(location.get_coordinates(), None, "{}", line);
+            assert_eq!(location.get_date_time(), None, "{}", line);
+        }
+
+        let malformed = [("+CIPGSMLOC: 0,-180.5,40.4,2017/01/17,15:55:04",
+                          invalid("+CIPGSMLOC:", "longitude", "-180.5")),
+                         ("+CIPGSMLOC: 0,-3.7,90.1,2017/01/17,15:55:04",
+                          invalid("+CIPGSMLOC:", "latitude", "90.1")),
+                         ("+CIPGSMLOC: 0,west,40.4,2017/01/17,15:55:04",
+                          invalid("+CIPGSMLOC:", "longitude", "west")),
+                         ("+CIPGSMLOC: 0,-3.7,40.4,2017/01/17",
+                          missing("+CIPGSMLOC:", "time")),
+                         ("+CIPGSMLOC: 0,-3.7", missing("+CIPGSMLOC:", "latitude")),
+                         ("+CIPGSMLOC: 0", missing("+CIPGSMLOC:", "longitude")),
+                         ("+CIPGSMLOC: -1", invalid("+CIPGSMLOC:", "locationcode", "-1")),
+                         ("+CIPGSMLOC:", missing("+CIPGSMLOC:", "locationcode"))];
+        for &(line, ref error) in &malformed {
+            assert_eq!(line.parse::<CellLocation>().as_ref(), Err(error), "{}", line);
+        }
+    }
+
+    #[test]
+    fn sms_reference() {
+        assert_eq!("+CMGS: 214".parse::<SmsReference>().unwrap().get_reference(), 214);
+
+        let malformed = [("+CMGS: 256", invalid("+CMGS:", "mr", "256")),
+                         ("+CMGS:", missing("+CMGS:", "mr")),
+                         ("+CMGR: 1", prefix("+CMGS:", "+CMGR: 1"))];
+        for &(line, ref error) in &malformed {
+            assert_eq!(line.parse::<SmsReference>().as_ref(), Err(error), "{}", line);
+        }
+    }
+
+    #[test]
+    fn status_report() {
+        let valid = [("+CDS: 6,214,\"+31628870634\",145,\"17/01/17,15:55:04+04\",\
+                       \"17/01/17,15:57:04+04\",0",
+                      StatusReport::new(214, "+31628870634", 0),
+                      DeliveryStatus::Delivered),
+                     ("+CDS: 6,3,,,\"17/01/17,15:55:04+04\",\"17/01/17,15:57:04+04\",48",
+                      StatusReport::new(3, "", 48),
+                      DeliveryStatus::Pending),
+                     ("+CDS: 6,3,\"612345678\",129,\"17/01/17,15:55:04+04\",\
+                       \"17/01/17,15:57:04+04\",70",
+                      StatusReport::new(3, "612345678", 70),
+                      DeliveryStatus::Failed)];
+        for &(line, ref report, status) in &valid {
+            assert_eq!(line.parse::<StatusReport>().as_ref(), Ok(report), "{}", line);
+            assert_eq!(report.get_delivery_status(), status, "{}", line);
+        }
+        assert_eq!(DeliveryStatus::from_status(0x1F), DeliveryStatus::Delivered);
+        assert_eq!(DeliveryStatus::from_status(0x20), DeliveryStatus::Pending);
+        assert_eq!(DeliveryStatus::from_status(0x3F), DeliveryStatus::Pending);
+        assert_eq!(DeliveryStatus::from_status(0x40), DeliveryStatus::Failed);
+
+        let malformed = [("+CDS: 6,214,\"+31628870634\",145,\"17/01/17,15:55:04+04\"",
+                          missing("+CDS:", "st")),
+                         ("+CDS: 6,214,\"+31628870634\",145,\"17/01/17,15:55:04+04\",\
+                           \"17/01/17,15:57:04+04\",x",
+                          invalid("+CDS:", "st", "x")),
+                         ("+CDS: 6,-1,,,,,0", invalid("+CDS:", "mr", "-1")),
+                         ("+CDS: 6", missing("+CDS:", "mr"))];
+        for &(line, ref error) in &malformed {
+            assert_eq!(line.parse::<StatusReport>().as_ref(), Err(error), "{}", line);
+        }
+    }
+
+    #[test]
+    fn operator() {
+        let valid = [("+COPS: 0", OperatorMode::Automatic, None),
+                     ("+COPS: 0,0,\"Movistar, S.A.\"",
+                      OperatorMode::Automatic,
+                      Some("Movistar, S.A.")),
+                     ("+COPS: 1,2,\"21407\"", OperatorMode::Manual, Some("21407")),
+                     ("+COPS: 2", OperatorMode::Deregistered, None),
+                     ("+COPS: 4,1,\"MOVISTAR\"", OperatorMode::ManualAutomatic, Some("MOVISTAR"))];
+        for &(line, mode, name) in &valid {
+            let operator = line.parse::<Operator>().unwrap();
+            assert_eq!(operator.get_mode(), mode, "{}", line);
+            assert_eq!(operator.get_name(), name, "{}", line);
+        }
+
+        let malformed = [("+COPS: 3", invalid("+COPS:", "mode", "3")),
+                         ("+COPS: 0,3,\"21407\"", invalid("+COPS:", "format", "3")),
+                         ("+COPS: 0,0", missing("+COPS:", "oper")),
+                         ("+COPS:", missing("+COPS:", "mode"))];
+        for &(line, ref error) in &malformed {
+            assert_eq!(line.parse::<Operator>().as_ref(), Err(error), "{}", line);
+        }
+    }
+
+    #[test]
+    fn sim_state() {
+        let valid = [("+CPIN: READY", SimState::Ready),
+                     ("+CPIN: SIM PIN", SimState::PinRequired),
+                     ("+CPIN: SIM PUK", SimState::PukRequired),
+                     ("+CPIN: PH_SIM PIN", SimState::PhoneSimPinRequired),
+                     ("+CPIN: PH_SIM PUK", SimState::PhoneSimPukRequired),
+                     ("+CPIN: SIM PIN2", SimState::Pin2Required),
+                     ("+CPIN: SIM PUK2", SimState::Puk2Required),
+                     ("+CPIN: NOT INSERTED", SimState::NotInserted),
+                     ("+CPIN: NOT READY", SimState::NotReady)];
+        for &(line, state) in &valid {
+            assert_eq!(line.parse::<SimState>(), Ok(state), "{}", line);
+        }
+        assert!(SimState::Ready.is_ready());
+        assert!(!SimState::PinRequired.is_ready());
+
+        let malformed = [("+CPIN: ready", invalid("+CPIN:", "code", "ready")),
+                         ("+CPIN: SIM", invalid("+CPIN:", "code", "SIM")),
+                         ("+CPIN:", missing("+CPIN:", "code"))];
+        for &(line, ref error) in &malformed {
+            assert_eq!(line.parse::<SimState>().as_ref(), Err(error), "{}", line);
+        }
+    }
+
+    #[test]
+    fn subscriber_number() {
+        for line in &["+CNUM: \"\",\"+34600000001\",145", "+CNUM: ,\"+34600000001\",145"] {
+            assert_eq!(line.parse::<SubscriberNumber>().unwrap().get_number(),
+                       "+34600000001");
+        }
+        assert_eq!("+CNUM: \"Me\"".parse::<SubscriberNumber>(),
+                   Err(missing("+CNUM:", "number")));
+    }
+
+    #[test]
+    fn clock_time() {
+        // 2017-01-17 15:55:04 UTC.
+        let utc = 1484668504;
+        let valid = [("+CCLK: \"17/01/17,15:55:04+00\"", utc),
+                     ("+CCLK: \"17/01/17,16:55:04+04\"", utc),
+                     ("+CCLK: \"17/01/17,12:55:04-12\"", utc),
+                     ("+CCLK: \"17/01/18,01:25:04+38\"", utc)];
+        for &(line, utc) in &valid {
+            assert_eq!(line.parse::<ClockTime>().unwrap().get_utc(),
+                       Timespec::new(utc, 0),
+                       "{}",
+                       line);
+        }
+
+        let malformed = ["17/01/17,15:55:04",
+                         "17/01/17,15:55:04+0",
+                         "17/01/17,15:55:04+004",
+                         "17/13/17,15:55:04+00",
+                         "17/01/17,25:55:04+00",
+                         "17/01/17,15:55:04+x0",
+                         "17/01/17 15:55:04+00",
+                         "17/01/17,15:55:0€+00"];
+        for value in &malformed {
+            let line = format!("+CCLK: \"{}\"", value);
+            assert_eq!(line.parse::<ClockTime>(), Err(invalid("+CCLK:", "time", value)));
+        }
+        assert_eq!("+CCLK:".parse::<ClockTime>(), Err(missing("+CCLK:", "time")));
+    }
+
+    #[test]
+    fn current_call() {
+        let valid = [("+CLCC: 1,0,2,0,0,\"+34600000001\",145", true, CallState::Dialing),
+                     ("+CLCC: 1,0,3,0,0,\"+34600000001\",145", true, CallState::Alerting),
+                     ("+CLCC: 2,1,4,0,0,\"\",128", false, CallState::Incoming),
+                     ("+CLCC: 1,0,6,0,0", true, CallState::Disconnected)];
+        for &(line, outgoing, state) in &valid {
+            let call = line.parse::<CurrentCall>().unwrap();
+            assert_eq!(call.is_outgoing(), outgoing, "{}", line);
+            assert_eq!(call.get_state(), state, "{}", line);
+        }
+
+        let malformed = [("+CLCC: 1,0,7,0,0", invalid("+CLCC:", "stat", "7")),
+                         ("+CLCC: x,0,0,0,0", invalid("+CLCC:", "id", "x")),
+                         ("+CLCC: 1,a,0,0,0", invalid("+CLCC:", "dir", "a")),
+                         ("+CLCC: 1,0", missing("+CLCC:", "stat")),
+                         ("+CLCC:", missing("+CLCC:", "stat"))];
+        for &(line, ref error) in &malformed {
+            assert_eq!(line.parse::<CurrentCall>().as_ref(), Err(error), "{}", line);
+        }
+    }
+
+    #[test]
+    fn message_header() {
+        let valid = [("+CMGL: 1,0,,24", 1, MessageStatus::ReceivedUnread, 24),
+                     ("+CMGL: 12,1,\"Ground\",140", 12, MessageStatus::ReceivedRead, 140),
+                     ("+CMGL: 3,2,,20", 3, MessageStatus::StoredUnsent, 20),
+                     ("+CMGL: 4,3,,20", 4, MessageStatus::StoredSent, 20)];
+        for &(line, index, status, length) in &valid {
+            let header = line.parse::<MessageHeader>().unwrap();
+            assert_eq!(header.get_index(), index, "{}", line);
+            assert_eq!(header.get_status(), status, "{}", line);
+            assert_eq!(header.get_length(), length, "{}", line);
+        }
+        assert!(MessageStatus::ReceivedRead.is_received());
+        assert!(!MessageStatus::StoredSent.is_received());
+
+        let malformed = [("+CMGL: 1,4,,24", invalid("+CMGL:", "stat", "4")),
+                         ("+CMGL: 1,\"REC READ\",,24", invalid("+CMGL:", "stat", "REC READ")),
+                         ("+CMGL: x,0,,24", invalid("+CMGL:", "index", "x")),
+                         ("+CMGL: 1,0,,", invalid("+CMGL:", "length", "")),
+                         ("+CMGL: 1,0", missing("+CMGL:", "length")),
+                         ("+CMGL: 1", missing("+CMGL:", "stat")),
+                         ("+CMGR: 0,,24", prefix("+CMGL:", "+CMGR: 0,,24"))];
+        for &(line, ref error) in &malformed {
+            assert_eq!(line.parse::<MessageHeader>().as_ref(), Err(error), "{}", line);
+        }
+    }
+}
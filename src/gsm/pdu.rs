@@ -0,0 +1,908 @@
+//! SMS PDU encoding and decoding.
+//!
+//! Messages are sent in PDU mode, which allows splitting long texts into concatenated segments
+//! that the receiving handset reassembles. Each segment carries a user data header (UDH) with a
+//! reference number shared by all the segments of the message, the total number of segments and
+//! the position of the segment, as described in 3GPP TS 23.040.
+//!
+//! Texts are encoded in the GSM 03.38 default alphabet with its extension table whenever
+//! possible, and in UCS-2 otherwise. Received messages and status reports are decoded too. This
+//! module does not talk to the modem, so that the PDUs it produces can be checked byte for byte.
+
+use std::fmt;
+use std::time::Duration;
+use std::error::Error as StdError;
+
+use super::parse::StatusReport;
+
+/// Maximum number of segments of a concatenated SMS.
+pub const MAX_SEGMENTS: usize = 255;
+/// Maximum number of octets of user data in a single SMS.
+const MAX_USER_DATA: usize = 140;
+/// Length of the concatenated SMS user data header, in octets.
+const CONCATENATED_UDH_LENGTH: usize = 6;
+
+/// Message type indicator mask.
+const TP_MTI_MASK: u8 = 0x03;
+/// SMS-DELIVER message type indicator.
+const TP_MTI_DELIVER: u8 = 0x00;
+/// SMS-SUBMIT message type indicator.
+const TP_MTI_SUBMIT: u8 = 0x01;
+/// SMS-STATUS-REPORT message type indicator.
+const TP_MTI_STATUS_REPORT: u8 = 0x02;
+/// Validity period format: relative.
+const TP_VPF_RELATIVE: u8 = 0x10;
+/// Status report request.
+const TP_SRR: u8 = 0x20;
+/// User data header indicator.
+const TP_UDHI: u8 = 0x40;
+/// Information element identifier of the concatenated SMS header with 8-bit reference.
+const IEI_CONCATENATED: u8 = 0x00;
+/// Escape to the GSM 03.38 extension table.
+const GSM_ESCAPE: u8 = 0x1B;
+
+/// GSM 03.38 default alphabet, indexed by septet. The escape code (`0x1B`) is never matched.
+const GSM_ALPHABET: [char; 128] =
+    ['@', '£', '$', '¥', 'è', 'é', 'ù', 'ì', 'ò', 'Ç', '\n', 'Ø', 'ø', '\r', 'Å', 'å',
+     'Δ', '_', 'Φ', 'Γ', 'Λ', 'Ω', 'Π', 'Ψ', 'Σ', 'Θ', 'Ξ', '\u{1B}', 'Æ', 'æ', 'ß', 'É',
+     ' ', '!', '"', '#', '¤', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+     '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+     '¡', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+     'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', 'Ä', 'Ö', 'Ñ', 'Ü', '§',
+     '¿', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+     'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', 'ä', 'ö', 'ñ', 'ü', 'à'];
+
+/// GSM 03.38 extension table, with the septet following the escape code.
+const GSM_EXTENSION: &'static [(char, u8)] = &[('\u{0C}', 0x0A),
+                                               ('^', 0x14),
+                                               ('{', 0x28),
+                                               ('}', 0x29),
+                                               ('\\', 0x2F),
+                                               ('[', 0x3C),
+                                               ('~', 0x3D),
+                                               (']', 0x3E),
+                                               ('|', 0x40),
+                                               ('€', 0x65)];
+
+/// Error encoding a message into PDUs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PduError {
+    /// The phone number is not valid.
+    InvalidNumber(String),
+    /// The message needs more segments than can be concatenated.
+    TooLong(usize),
+    /// The validity period cannot be represented in relative format.
+    InvalidValidityPeriod(Duration),
+    /// A received PDU could not be decoded.
+    InvalidPdu(String),
+}
+
+impl fmt::Display for PduError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PduError::InvalidNumber(ref number) => write!(f, "invalid phone number '{}'", number),
+            PduError::TooLong(segments) => {
+                write!(f, "message too long: {} segments, maximum {}", segments, MAX_SEGMENTS)
+            }
+            PduError::InvalidValidityPeriod(period) => {
+                write!(f, "invalid SMS validity period: {} seconds", period.as_secs())
+            }
+            PduError::InvalidPdu(ref pdu) => write!(f, "invalid PDU '{}'", pdu),
+        }
+    }
+}
+
+impl StdError for PduError {
+    fn description(&self) -> &str {
+        match *self {
+            PduError::InvalidNumber(_) => "invalid phone number",
+            PduError::TooLong(_) => "message too long",
+            PduError::InvalidValidityPeriod(_) => "invalid SMS validity period",
+            PduError::InvalidPdu(_) => "invalid PDU",
+        }
+    }
+}
+
+/// Alphabet used to encode the text of an SMS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alphabet {
+    /// GSM 03.38 default alphabet with extension table, 7 bits per character.
+    Gsm7,
+    /// UCS-2, 16 bits per character.
+    Ucs2,
+}
+
+impl Alphabet {
+    /// Gets the most compact alphabet that can encode the whole text.
+    pub fn for_text(text: &str) -> Alphabet {
+        if text.chars().all(|c| gsm_septets(c).is_some()) {
+            Alphabet::Gsm7
+        } else {
+            Alphabet::Ucs2
+        }
+    }
+
+    /// Gets the alphabet of a received message from its data coding scheme.
+    ///
+    /// Returns `None` for 8-bit data, compressed texts and reserved coding groups.
+    fn from_data_coding_scheme(dcs: u8) -> Option<Alphabet> {
+        match dcs & 0xF0 {
+            // General data coding, optionally marked for automatic deletion.
+            0x00..=0x10 | 0x40..=0x50 => {
+                match dcs & 0x0C {
+                    0x00 => Some(Alphabet::Gsm7),
+                    0x08 => Some(Alphabet::Ucs2),
+                    _ => None,
+                }
+            }
+            // Message waiting indication groups.
+            0xC0 | 0xD0 => Some(Alphabet::Gsm7),
+            0xE0 => Some(Alphabet::Ucs2),
+            // Data coding and message class.
+            0xF0 if dcs & 0x04 == 0 => Some(Alphabet::Gsm7),
+            _ => None,
+        }
+    }
+
+    /// Gets the data coding scheme of the alphabet.
+    fn data_coding_scheme(&self) -> u8 {
+        match *self {
+            Alphabet::Gsm7 => 0x00,
+            Alphabet::Ucs2 => 0x08,
+        }
+    }
+
+    /// Gets the maximum number of encoding units (septets or UCS-2 code units) in a single SMS,
+    /// or in each segment of a concatenated one.
+    fn capacity(&self, concatenated: bool) -> usize {
+        let octets = if concatenated {
+            MAX_USER_DATA - CONCATENATED_UDH_LENGTH
+        } else {
+            MAX_USER_DATA
+        };
+        match *self {
+            Alphabet::Gsm7 => octets * 8 / 7,
+            Alphabet::Ucs2 => octets / 2,
+        }
+    }
+}
+
+/// Counts the septets needed to encode the text in the GSM 7-bit alphabet, counting extension
+/// characters twice.
+///
+/// Returns `None` if some character is not in the GSM alphabet.
+pub fn septet_count(text: &str) -> Option<usize> {
+    text.chars().map(|c| gsm_septets(c).map(|s| s.len())).sum()
+}
+
+/// Encoded SMS-SUBMIT PDU, ready to be sent with `AT+CMGS`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmitPdu {
+    hex: String,
+    length: usize,
+}
+
+impl SubmitPdu {
+    /// Gets the PDU in hexadecimal, including the SMSC address.
+    pub fn get_hex(&self) -> &str {
+        &self.hex
+    }
+
+    /// Gets the length of the TPDU in octets, without the SMSC address, as expected by
+    /// `AT+CMGS=<length>`.
+    pub fn get_length(&self) -> usize {
+        self.length
+    }
+}
+
+/// Encodes a text message to the given number, splitting it in concatenated segments if it does
+/// not fit in a single SMS.
+///
+/// `reference` identifies the segments of the same concatenated message, so it should change
+/// for each message sent. If a validity period is given, the network discards the message if it
+/// cannot be delivered in that time. If `status_report` is set, the network sends a status report
+/// once each segment is delivered or fails.
+pub fn encode_submit(number: &str,
+                     text: &str,
+                     reference: u8,
+                     validity: Option<Duration>,
+                     status_report: bool)
+                     -> Result<Vec<SubmitPdu>, PduError> {
+    let address = try!(encode_address(number));
+    let validity = match validity {
+        Some(period) => Some(try!(encode_validity_period(period))),
+        None => None,
+    };
+
+    // Each character is kept as a unit, so that extension characters and surrogate pairs are
+    // never split between segments.
+    let alphabet = Alphabet::for_text(text);
+    let characters = text.chars()
+        .map(|c| match alphabet {
+            Alphabet::Gsm7 => gsm_septets(c).unwrap(),
+            Alphabet::Ucs2 => c.encode_utf16(&mut [0; 2]).to_vec(),
+        })
+        .collect::<Vec<_>>();
+    let units = characters.iter().map(|c| c.len()).sum::<usize>();
+
+    if units <= alphabet.capacity(false) {
+        let units = characters.concat();
+        return Ok(vec![submit_pdu(&address, validity, status_report, alphabet, None, &units)]);
+    }
+
+    let mut segments = Vec::new();
+    let mut segment: Vec<u16> = Vec::new();
+    for character in characters {
+        if segment.len() + character.len() > alphabet.capacity(true) {
+            segments.push(segment);
+            segment = Vec::new();
+        }
+        segment.extend(character);
+    }
+    segments.push(segment);
+    if segments.len() > MAX_SEGMENTS {
+        return Err(PduError::TooLong(segments.len()));
+    }
+
+    let total = segments.len() as u8;
+    Ok(segments.iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            let udh = [0x05, IEI_CONCATENATED, 0x03, reference, total, i as u8 + 1];
+            submit_pdu(&address, validity, status_report, alphabet, Some(&udh), segment)
+        })
+        .collect())
+}
+
+/// Builds an SMS-SUBMIT PDU with the given destination address, validity period, status report
+/// request, optional UDH and encoded text.
+fn submit_pdu(address: &[u8],
+              validity: Option<u8>,
+              status_report: bool,
+              alphabet: Alphabet,
+              udh: Option<&[u8]>,
+              units: &[u16])
+              -> SubmitPdu {
+    let mut first_octet = TP_MTI_SUBMIT;
+    if validity.is_some() {
+        first_octet |= TP_VPF_RELATIVE;
+    }
+    if status_report {
+        first_octet |= TP_SRR;
+    }
+    if udh.is_some() {
+        first_octet |= TP_UDHI;
+    }
+
+    let mut tpdu = vec![first_octet, 0x00]; // Message reference, set by the modem.
+    tpdu.extend_from_slice(address);
+    tpdu.push(0x00); // Protocol identifier.
+    tpdu.push(alphabet.data_coding_scheme());
+    if let Some(validity) = validity {
+        tpdu.push(validity);
+    }
+
+    let udh = udh.unwrap_or(&[]);
+    match alphabet {
+        Alphabet::Gsm7 => {
+            // The header is padded to a septet boundary, and the user data length counts septets.
+            let fill_bits = (7 - (udh.len() * 8) % 7) % 7;
+            let header_septets = (udh.len() * 8 + fill_bits) / 7;
+            tpdu.push((header_septets + units.len()) as u8);
+            tpdu.extend_from_slice(udh);
+            tpdu.extend(pack_septets(units, fill_bits));
+        }
+        Alphabet::Ucs2 => {
+            tpdu.push((udh.len() + units.len() * 2) as u8);
+            tpdu.extend_from_slice(udh);
+            for unit in units {
+                tpdu.push((unit >> 8) as u8);
+                tpdu.push(*unit as u8);
+            }
+        }
+    }
+
+    SubmitPdu {
+        // Empty SMSC address, so that the one stored in the SIM is used.
+        hex: format!("00{}", to_hex(&tpdu)),
+        length: tpdu.len(),
+    }
+}
+
+/// SMS-DELIVER PDU received from the network, as listed with `AT+CMGL` in PDU mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliverPdu {
+    sender: String,
+    timestamp: String,
+    text: String,
+}
+
+impl DeliverPdu {
+    /// Gets the phone number of the sender.
+    pub fn get_sender(&self) -> &str {
+        &self.sender
+    }
+
+    /// Gets the service center timestamp, as `yy/MM/dd,hh:mm:ss±zz`, with the time zone in
+    /// quarters of an hour.
+    pub fn get_timestamp(&self) -> &str {
+        &self.timestamp
+    }
+
+    /// Gets the text of the message.
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// Decodes an SMS-DELIVER PDU in hexadecimal, as listed with `AT+CMGL` in PDU mode.
+///
+/// The segments of a concatenated message are decoded on their own, since ground commands always
+/// fit in a single SMS. Messages with 8-bit data cannot be decoded.
+pub fn decode_deliver(hex: &str) -> Result<DeliverPdu, PduError> {
+    let invalid = || PduError::InvalidPdu(hex.to_owned());
+    let octets = try!(from_hex(hex).ok_or_else(invalid));
+
+    let smsc_length = *try!(octets.first().ok_or_else(invalid)) as usize;
+    let tpdu = try!(octets.get(smsc_length + 1..).ok_or_else(invalid));
+    let first_octet = *try!(tpdu.first().ok_or_else(invalid));
+    if first_octet & TP_MTI_MASK != TP_MTI_DELIVER {
+        return Err(invalid());
+    }
+
+    // Originating address, then protocol identifier, data coding scheme, service center
+    // timestamp of 7 octets and user data length.
+    let (sender, address_length) = try!(decode_address(&tpdu[1..]).ok_or_else(invalid));
+    let fields = 1 + address_length;
+    let (alphabet, timestamp, length) =
+        match (tpdu.get(fields + 1).and_then(|&dcs| Alphabet::from_data_coding_scheme(dcs)),
+               tpdu.get(fields + 2..fields + 9),
+               tpdu.get(fields + 9)) {
+            (Some(alphabet), Some(timestamp), Some(&length)) => {
+                (alphabet, decode_timestamp(timestamp), length as usize)
+            }
+            _ => return Err(invalid()),
+        };
+    let user_data = &tpdu[fields + 10..];
+    let udh_length = if first_octet & TP_UDHI != 0 {
+        1 + *try!(user_data.first().ok_or_else(invalid)) as usize
+    } else {
+        0
+    };
+
+    let text = match alphabet {
+        Alphabet::Gsm7 => {
+            // The header is padded to a septet boundary, and the user data length counts septets.
+            let fill_bits = (7 - (udh_length * 8) % 7) % 7;
+            let header_septets = (udh_length * 8 + fill_bits) / 7;
+            let septets = try!(length.checked_sub(header_septets)
+                .and_then(|count| {
+                    user_data.get(udh_length..)
+                        .and_then(|data| unpack_septets(data, fill_bits, count))
+                })
+                .ok_or_else(invalid));
+            decode_septets(&septets)
+        }
+        Alphabet::Ucs2 => {
+            let data = try!(user_data.get(udh_length..length).ok_or_else(invalid));
+            if data.len() % 2 != 0 {
+                return Err(invalid());
+            }
+            let units = data.chunks(2)
+                .map(|c| (c[0] as u16) << 8 | c[1] as u16)
+                .collect::<Vec<_>>();
+            try!(String::from_utf16(&units).map_err(|_| invalid()))
+        }
+    };
+
+    Ok(DeliverPdu {
+        sender: sender,
+        timestamp: timestamp,
+        text: text,
+    })
+}
+
+/// Encodes a single SMS-DELIVER PDU in hexadecimal, as the modem lists received messages.
+///
+/// The timestamp is given as year, month, day, hour, minute and second in UTC. Only used to
+/// emulate received messages.
+#[cfg(any(test, feature = "sim", feature = "real-sim"))]
+pub fn encode_deliver(sender: &str, text: &str, timestamp: [u8; 6]) -> Result<String, PduError> {
+    let alphabet = Alphabet::for_text(text);
+    let units = match alphabet {
+        Alphabet::Gsm7 => text.chars().flat_map(|c| gsm_septets(c).unwrap()).collect::<Vec<_>>(),
+        Alphabet::Ucs2 => text.encode_utf16().collect(),
+    };
+    if units.len() > alphabet.capacity(false) {
+        return Err(PduError::TooLong(units.len() / alphabet.capacity(true) + 1));
+    }
+
+    let mut tpdu = vec![TP_MTI_DELIVER];
+    tpdu.extend(try!(encode_address(sender)));
+    tpdu.push(0x00); // Protocol identifier.
+    tpdu.push(alphabet.data_coding_scheme());
+    tpdu.extend(timestamp.iter().map(|v| ((v % 10) << 4) | (v / 10)));
+    tpdu.push(0x00); // UTC.
+    match alphabet {
+        Alphabet::Gsm7 => {
+            tpdu.push(units.len() as u8);
+            tpdu.extend(pack_septets(&units, 0));
+        }
+        Alphabet::Ucs2 => {
+            tpdu.push((units.len() * 2) as u8);
+            for unit in units {
+                tpdu.push((unit >> 8) as u8);
+                tpdu.push(unit as u8);
+            }
+        }
+    }
+
+    // Empty SMSC address.
+    Ok(format!("00{}", to_hex(&tpdu)))
+}
+
+/// Decodes an SMS-STATUS-REPORT PDU in hexadecimal, as received in a `+CDS` URC in PDU mode.
+pub fn decode_status_report(hex: &str) -> Result<StatusReport, PduError> {
+    let invalid = || PduError::InvalidPdu(hex.to_owned());
+    let octets = try!(from_hex(hex).ok_or_else(invalid));
+
+    // SMSC address, first octet and message reference.
+    let smsc_length = *try!(octets.first().ok_or_else(invalid)) as usize;
+    let tpdu = try!(octets.get(smsc_length + 1..).ok_or_else(invalid));
+    if tpdu.len() < 2 || tpdu[0] & TP_MTI_MASK != TP_MTI_STATUS_REPORT {
+        return Err(invalid());
+    }
+    let reference = tpdu[1];
+
+    // Recipient address, then service center timestamp and discharge time of 7 octets each.
+    let (recipient, address_length) = try!(decode_address(&tpdu[2..]).ok_or_else(invalid));
+    let status = *try!(tpdu.get(2 + address_length + 14).ok_or_else(invalid));
+
+    Ok(StatusReport::new(reference, recipient, status))
+}
+
+/// Encodes a phone number as a destination address: number of digits, type of address and the
+/// digits in swapped semi-octets.
+fn encode_address(number: &str) -> Result<Vec<u8>, PduError> {
+    let trimmed = number.trim();
+    let (international, digits) = match trimmed.strip_prefix('+') {
+        Some(digits) => (true, digits),
+        None => (false, trimmed),
+    };
+    if digits.is_empty() || digits.len() > 20 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(PduError::InvalidNumber(number.to_owned()));
+    }
+
+    let mut address = vec![digits.len() as u8, if international { 0x91 } else { 0x81 }];
+    for pair in digits.as_bytes().chunks(2) {
+        let low = pair[0] - b'0';
+        let high = if pair.len() == 2 { pair[1] - b'0' } else { 0x0F };
+        address.push(high << 4 | low);
+    }
+
+    Ok(address)
+}
+
+/// Decodes an address, returning the phone number and the number of octets it takes.
+///
+/// Only numeric addresses are supported, alphanumeric ones are returned as a hexadecimal string.
+fn decode_address(octets: &[u8]) -> Option<(String, usize)> {
+    let (digits, type_of_address) = match (octets.first(), octets.get(1)) {
+        (Some(&digits), Some(&type_of_address)) => (digits as usize, type_of_address),
+        _ => return None,
+    };
+    let length = 2 + (digits + 1) / 2;
+
+    octets.get(2..length).map(|value| {
+        let mut number = String::new();
+        if type_of_address & 0x70 == 0x10 {
+            number.push('+');
+        }
+        for octet in value {
+            for &digit in &[octet & 0x0F, octet >> 4] {
+                match digit {
+                    0x0..=0x9 => number.push((b'0' + digit) as char),
+                    0xF => {}
+                    _ => number.push_str(&format!("{:X}", digit)),
+                }
+            }
+        }
+        number.truncate(digits + if number.starts_with('+') { 1 } else { 0 });
+
+        (number, length)
+    })
+}
+
+/// Decodes a service center timestamp, as `yy/MM/dd,hh:mm:ss±zz`.
+///
+/// Each value is in swapped semi-octets, and the sign of the time zone is in the third bit of its
+/// first octet.
+fn decode_timestamp(octets: &[u8]) -> String {
+    let value = |octet: u8| (octet & 0x0F) * 10 + (octet >> 4);
+    format!("{:02}/{:02}/{:02},{:02}:{:02}:{:02}{}{:02}",
+            value(octets[0]),
+            value(octets[1]),
+            value(octets[2]),
+            value(octets[3]),
+            value(octets[4]),
+            value(octets[5]),
+            if octets[6] & 0x08 == 0 { '+' } else { '-' },
+            value(octets[6] & 0xF7))
+}
+
+/// Encodes a validity period in relative format, rounding up to the next representable value.
+///
+/// Values up to 143 are steps of 5 minutes up to 12 hours, up to 167 steps of 30 minutes up to
+/// 24 hours, up to 196 days up to 30 days, and up to 255 weeks up to 63 weeks.
+fn encode_validity_period(period: Duration) -> Result<u8, PduError> {
+    let minutes = (period.as_secs() + 59) / 60;
+    let value = match minutes {
+        0 => return Err(PduError::InvalidValidityPeriod(period)),
+        1..=720 => (minutes + 4) / 5 - 1,
+        721..=1440 => 143 + (minutes - 720 + 29) / 30,
+        1441..=43200 => 166 + (minutes + 24 * 60 - 1) / (24 * 60),
+        43201..=635040 => 192 + (minutes + 7 * 24 * 60 - 1) / (7 * 24 * 60),
+        _ => return Err(PduError::InvalidValidityPeriod(period)),
+    };
+
+    Ok(value as u8)
+}
+
+/// Gets the GSM 7-bit encoding of a character: a single septet for the default alphabet, or the
+/// escape code followed by a septet for the extension table.
+fn gsm_septets(c: char) -> Option<Vec<u16>> {
+    if c == '\u{1B}' {
+        return None;
+    }
+    match GSM_ALPHABET.iter().position(|&g| g == c) {
+        Some(septet) => Some(vec![septet as u16]),
+        None => {
+            GSM_EXTENSION.iter()
+                .find(|&&(e, _)| e == c)
+                .map(|&(_, septet)| vec![GSM_ESCAPE as u16, septet as u16])
+        }
+    }
+}
+
+/// Decodes GSM 7-bit septets into text, replacing the escape code and the septet after it with
+/// the extension character.
+///
+/// Unknown extension characters are decoded as the character of the septet in the default
+/// alphabet, as the standard recommends.
+fn decode_septets(septets: &[u8]) -> String {
+    let mut text = String::with_capacity(septets.len());
+    let mut escaped = false;
+    for &septet in septets {
+        if escaped {
+            escaped = false;
+            match GSM_EXTENSION.iter().find(|&&(_, e)| e == septet) {
+                Some(&(c, _)) => text.push(c),
+                None => text.push(GSM_ALPHABET[septet as usize]),
+            }
+        } else if septet == GSM_ESCAPE {
+            escaped = true;
+        } else {
+            text.push(GSM_ALPHABET[septet as usize]);
+        }
+    }
+
+    text
+}
+
+/// Decodes a hexadecimal string into octets.
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.trim();
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+/// Encodes octets as a hexadecimal string.
+fn to_hex(octets: &[u8]) -> String {
+    octets.iter().map(|octet| format!("{:02X}", octet)).collect()
+}
+
+/// Unpacks the given number of septets from octets, least significant bits first, after the
+/// given number of fill bits.
+///
+/// Returns `None` if there are not enough octets.
+fn unpack_septets(octets: &[u8], fill_bits: usize, count: usize) -> Option<Vec<u8>> {
+    if octets.len() * 8 < fill_bits + count * 7 {
+        return None;
+    }
+
+    Some((0..count)
+        .map(|i| {
+            let bit = fill_bits + i * 7;
+            let low = octets[bit / 8] as u16;
+            let high = octets.get(bit / 8 + 1).map_or(0, |&o| o as u16);
+            ((high << 8 | low) >> (bit % 8)) as u8 & 0x7F
+        })
+        .collect())
+}
+
+/// Packs septets into octets, least significant bits first, after the given number of fill bits.
+fn pack_septets(septets: &[u16], fill_bits: usize) -> Vec<u8> {
+    let mut octets = Vec::with_capacity((septets.len() * 7 + fill_bits + 7) / 8);
+    let mut buffer = 0u32;
+    let mut bits = fill_bits;
+
+    for &septet in septets {
+        buffer |= (septet as u32 & 0x7F) << bits;
+        bits += 7;
+        while bits >= 8 {
+            octets.push(buffer as u8);
+            buffer >>= 8;
+            bits -= 8;
+        }
+    }
+    if bits > 0 {
+        octets.push(buffer as u8);
+    }
+
+    octets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Destination address of +46708251358.
+    const ADDRESS: &'static str = "0B916407281553F8";
+
+    /// Encodes a text to +46708251358 with reference 2, without validity period nor status
+    /// report.
+    fn encode(text: &str) -> Vec<SubmitPdu> {
+        encode_submit("+46708251358", text, 2, None, false).unwrap()
+    }
+
+    #[test]
+    fn gsm7_single() {
+        let pdus = encode_submit("+46708251358",
+                                 "hellohello",
+                                 0,
+                                 Some(Duration::from_secs(4 * 24 * 60 * 60)),
+                                 false)
+            .unwrap();
+
+        assert_eq!(pdus.len(), 1);
+        assert_eq!(pdus[0].get_hex(), "0011000B916407281553F80000AA0AE8329BFD4697D9EC37");
+        assert_eq!(pdus[0].get_length(), 23);
+    }
+
+    #[test]
+    fn gsm7_national_number_and_status_report() {
+        let pdus = encode_submit("612345678", "hellohello", 0, None, true).unwrap();
+
+        assert_eq!(pdus[0].get_hex(), "002100098116325476F800000AE8329BFD4697D9EC37");
+        assert_eq!(pdus[0].get_length(), 21);
+    }
+
+    #[test]
+    fn gsm7_extension_characters() {
+        assert_eq!(Alphabet::for_text("[1€]"), Alphabet::Gsm7);
+        assert_eq!(septet_count("[1€]"), Some(7));
+        assert_eq!(septet_count("hello"), Some(5));
+        assert_eq!(septet_count("hello 😀"), None);
+
+        let pdus = encode("€");
+        assert_eq!(pdus[0].get_hex(), format!("000100{}0000029B32", ADDRESS));
+    }
+
+    #[test]
+    fn gsm7_at_sign_and_nul() {
+        // '@' is septet 0x00 in the default alphabet, while NUL cannot be encoded in it.
+        assert_eq!(Alphabet::for_text("A@"), Alphabet::Gsm7);
+        assert_eq!(encode("A@")[0].get_hex(), format!("000100{}0000024100", ADDRESS));
+        assert_eq!(encode("@")[0].get_hex(), format!("000100{}00000100", ADDRESS));
+
+        assert_eq!(Alphabet::for_text("A\0"), Alphabet::Ucs2);
+        assert_eq!(encode("\0")[0].get_hex(), format!("000100{}0008020000", ADDRESS));
+    }
+
+    #[test]
+    fn gsm7_septet_boundaries() {
+        // With 7 septets the last octet has a spare bit, which the user data length excludes.
+        assert_eq!(encode("1234567")[0].get_hex(),
+                   format!("000100{}00000731D98C56B3DD00", ADDRESS));
+        assert_eq!(encode("12345678")[0].get_hex(),
+                   format!("000100{}00000831D98C56B3DD70", ADDRESS));
+    }
+
+    #[test]
+    fn gsm7_single_capacity() {
+        assert_eq!(encode(&"a".repeat(160)).len(), 1);
+        assert_eq!(encode(&"a".repeat(161)).len(), 2);
+        assert_eq!(encode(&"€".repeat(80)).len(), 1);
+        assert_eq!(encode(&format!("{}€", "a".repeat(159))).len(), 2);
+    }
+
+    #[test]
+    fn ucs2_surrogate_pair() {
+        assert_eq!(Alphabet::for_text("😀"), Alphabet::Ucs2);
+
+        let pdus = encode_submit("+46708251358", "😀", 0, None, true).unwrap();
+        assert_eq!(pdus[0].get_hex(), format!("002100{}000804D83DDE00", ADDRESS));
+        assert_eq!(pdus[0].get_length(), 17);
+    }
+
+    #[test]
+    fn gsm7_concatenated_fill_bits() {
+        // The extension character does not fit in the first segment, so it is not split.
+        let text = format!("{}€bbbbbbbb", "a".repeat(152));
+        let pdus = encode(&text);
+
+        assert_eq!(pdus.len(), 2);
+        let first = format!("004100{}00009F050003020201C2E17038", ADDRESS);
+        assert!(pdus[0].get_hex().starts_with(&first));
+        assert!(pdus[0].get_hex().ends_with("0E8701"));
+        assert_eq!(pdus[0].get_length(), 13 + 6 + 134);
+        assert_eq!(pdus[1].get_hex(),
+                   format!("004100{}0000110500030202023665B1582C168BC562", ADDRESS));
+        assert_eq!(pdus[1].get_length(), 13 + 6 + 9);
+    }
+
+    #[test]
+    fn gsm7_concatenated_header_septets() {
+        let pdus = encode_submit("+46708251358",
+                                 &format!("{}hellohello", "a".repeat(153)),
+                                 0xD6,
+                                 Some(Duration::from_secs(5 * 60)),
+                                 true)
+            .unwrap();
+
+        assert_eq!(pdus.len(), 2);
+        assert_eq!(pdus[1].get_hex(),
+                   format!("007100{}00000011050003D60202D06536FB8D2EB3D96F", ADDRESS));
+    }
+
+    #[test]
+    fn ucs2_concatenated_surrogate_pair() {
+        let pdus = encode(&format!("{}😀aaa", "a".repeat(66)));
+
+        assert_eq!(pdus.len(), 2);
+        assert_eq!(pdus[0].get_length(), 13 + 6 + 132);
+        assert_eq!(pdus[1].get_hex(),
+                   format!("004100{}000810050003020202D83DDE00006100610061", ADDRESS));
+    }
+
+    #[test]
+    fn too_long() {
+        assert_eq!(encode(&"a".repeat(153 * 255)).len(), 255);
+        assert_eq!(encode_submit("+46708251358", &"a".repeat(153 * 255 + 1), 0, None, false),
+                   Err(PduError::TooLong(256)));
+    }
+
+    #[test]
+    fn invalid_number() {
+        for number in &["", "+", "+3462a", "123456789012345678901"] {
+            assert_eq!(encode_submit(number, "hello", 0, None, false),
+                       Err(PduError::InvalidNumber(number.to_string())));
+        }
+    }
+
+    #[test]
+    fn validity_period_boundaries() {
+        let minutes = |m: u64| encode_validity_period(Duration::from_secs(m * 60));
+
+        assert_eq!(encode_validity_period(Duration::from_secs(0)),
+                   Err(PduError::InvalidValidityPeriod(Duration::from_secs(0))));
+        assert_eq!(encode_validity_period(Duration::from_secs(1)), Ok(0));
+        assert_eq!(minutes(5), Ok(0));
+        assert_eq!(encode_validity_period(Duration::from_secs(5 * 60 + 1)), Ok(1));
+        assert_eq!(minutes(12 * 60), Ok(143));
+        assert_eq!(minutes(12 * 60 + 1), Ok(144));
+        assert_eq!(minutes(24 * 60), Ok(167));
+        assert_eq!(minutes(24 * 60 + 1), Ok(168));
+        assert_eq!(minutes(2 * 24 * 60), Ok(168));
+        assert_eq!(minutes(30 * 24 * 60), Ok(196));
+        assert_eq!(minutes(30 * 24 * 60 + 1), Ok(197));
+        assert_eq!(minutes(5 * 7 * 24 * 60), Ok(197));
+        assert_eq!(minutes(63 * 7 * 24 * 60), Ok(255));
+        let too_long = Duration::from_secs((63 * 7 * 24 * 60 + 1) * 60);
+        assert_eq!(encode_validity_period(too_long),
+                   Err(PduError::InvalidValidityPeriod(too_long)));
+    }
+
+    #[test]
+    fn status_report() {
+        let report = decode_status_report("07911326040000F0\
+                                           06D60B911326880736F4\
+                                           111011719551401110117195714000")
+            .unwrap();
+        assert_eq!(report, StatusReport::new(0xD6, "+31628870634", 0x00));
+
+        let report = decode_status_report("000645098116325476F8\
+                                           111011719551401110117195714046")
+            .unwrap();
+        assert_eq!(report, StatusReport::new(0x45, "612345678", 0x46));
+    }
+
+    #[test]
+    fn invalid_status_report() {
+        for pdu in &["",
+                     "0",
+                     "00",
+                     "0006",
+                     "0006D6ZZ",
+                     "0006D60B911326880736F4111011719551401110117195714",
+                     "0006D60B911326880736F41110117195514011101171957140",
+                     "0011000B916407281553F80000AA0AE8329BFD4697D9EC37",
+                     "07911326040000F0"] {
+            assert_eq!(decode_status_report(pdu), Err(PduError::InvalidPdu(pdu.to_string())));
+        }
+    }
+
+    #[test]
+    fn deliver() {
+        let sms = decode_deliver("07917283010010F5\
+                                  040BC87238880900F10000993092516195800AE8329BFD4697D9EC37")
+            .unwrap();
+        assert_eq!(sms.get_sender(), "27838890001");
+        assert_eq!(sms.get_timestamp(), "99/03/29,15:16:59+08");
+        assert_eq!(sms.get_text(), "hellohello");
+
+        // UCS-2, with the time zone west of UTC.
+        let sms = decode_deliver(format!("0004{}0008{}0C{}",
+                                         ADDRESS,
+                                         "7121104100004A",
+                                         "00D1006F00F1006FD83DDE00")
+                .as_str())
+            .unwrap();
+        assert_eq!(sms.get_sender(), "+46708251358");
+        assert_eq!(sms.get_timestamp(), "17/12/01,14:00:00-24");
+        assert_eq!(sms.get_text(), "Ñoño😀");
+    }
+
+    #[test]
+    fn deliver_encoding() {
+        for text in &["STATUS", "SILENCE 2h", "[1€] {~}", "Ñoño😀", ""] {
+            let pdu = encode_deliver("+34600000001", text, [17, 1, 17, 15, 55, 4]).unwrap();
+            let sms = decode_deliver(&pdu).unwrap();
+            assert_eq!(sms.get_sender(), "+34600000001");
+            assert_eq!(sms.get_timestamp(), "17/01/17,15:55:04+00");
+            assert_eq!(sms.get_text(), *text);
+        }
+        assert_eq!(encode_deliver("+34600000001", &"a".repeat(161), [17, 1, 17, 15, 55, 4]),
+                   Err(PduError::TooLong(2)));
+    }
+
+    #[test]
+    fn deliver_concatenated_segment() {
+        // The segments sent by the encoder, as they would be received, with the fill bits after
+        // the header.
+        let text = format!("{}hellohello", "a".repeat(153));
+        let segments = encode(&text)
+            .iter()
+            .map(|pdu| {
+                let user_data = &pdu.get_hex()[2 + 4 + ADDRESS.len() + 4..];
+                let deliver = format!("0044{}000017011751554000{}", ADDRESS, user_data);
+                decode_deliver(&deliver).unwrap().get_text().to_owned()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(segments, [&text[..153], "hellohello"]);
+    }
+
+    #[test]
+    fn invalid_deliver() {
+        let deliver = format!("0004{}000017011751554000", ADDRESS);
+        for pdu in &["".to_owned(),
+                     "00".to_owned(),
+                     "0004".to_owned(),
+                     "0004ZZ".to_owned(),
+                     // SMS-SUBMIT.
+                     format!("000100{}0000029B32", ADDRESS),
+                     // Truncated timestamp.
+                     format!("0004{}0000170117515540", ADDRESS),
+                     // Fewer septets than the user data length.
+                     format!("{}0AE8329BFD4697D9EC", deliver),
+                     // Odd UCS-2 user data.
+                     format!("0004{}0008170117515540000300D100", ADDRESS),
+                     // 8-bit data.
+                     format!("0004{}0004170117515540000201FF", ADDRESS)] {
+            assert_eq!(decode_deliver(pdu), Err(PduError::InvalidPdu(pdu.to_string())));
+        }
+    }
+}
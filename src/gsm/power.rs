@@ -0,0 +1,212 @@
+//! Power control.
+//!
+//! The modem is turned on and off by pulsing its power key, which toggles its power state, and
+//! its status pin tells if it is on. Pulses are not always effective, so the status pin is checked
+//! after each one, and the modem is reset through its reset line if it does not react to the
+//! configured number of pulses. Since a late reaction to a pulse would be undone by the next one,
+//! the status pin is checked again right before pulsing again.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::LogLevel::*;
+use wiringpi::pin::Value;
+
+use super::{Gsm, GsmError, Transport};
+
+/// Duration of the power key pulse, in milliseconds.
+const POWER_KEY_PULSE: u64 = 2000;
+/// Deadline for the status pin to change after a power key pulse, in milliseconds.
+#[cfg(not(test))]
+const POWER_STATUS_TIMEOUT: u64 = 8000;
+/// Deadline for the status pin to change in tests, where the simulated pin changes right away.
+#[cfg(test)]
+const POWER_STATUS_TIMEOUT: u64 = 300;
+/// Duration of the reset pulse, in milliseconds.
+const RESET_PULSE: u64 = 200;
+/// Time between status pin checks, in milliseconds.
+const POWER_POLL_INTERVAL: u64 = 100;
+
+impl<T: Transport> Gsm<T> {
+    /// Turns the modem on, retrying and resetting it if needed, and configures it.
+    ///
+    /// The modem is configured even if it was already on, since the set-up can be repeated.
+    pub fn turn_on(&mut self) -> Result<(), GsmError> {
+        try!(self.set_power(true));
+        self.initialize_modem()
+    }
+
+    /// Turns the modem off, retrying and resetting it if needed.
+    pub fn turn_off(&mut self) -> Result<(), GsmError> {
+        self.set_power(false)
+    }
+
+    /// Brings the modem to the given power state, checking the status pin after each pulse.
+    fn set_power(&mut self, on: bool) -> Result<(), GsmError> {
+        let state = if on { "on" } else { "off" };
+        self.logger.log(&format!("Turning GSM {}…", state), Info);
+        if self.is_on() == on {
+            warn!("Trying to turn GSM {}, but GSM was already {}.", state, state);
+            self.logger.log(&format!("GSM already {}.", state), Warn);
+            return Ok(());
+        }
+
+        let start = Instant::now();
+        let retries = self.config.get_power_retries();
+        for attempt in 1..=retries {
+            if attempt > 1 && self.is_on() == on {
+                self.logger.log(&format!("GSM turned {} late, after power key pulse {}.",
+                                         state,
+                                         attempt - 1),
+                                Info);
+                return Ok(());
+            }
+            self.pulse_power_key();
+            if self.wait_for_power(on) {
+                self.logger.log(&format!("GSM {} after {} power key pulse(s), in {} ms.",
+                                         state,
+                                         attempt,
+                                         millis(start.elapsed())),
+                                Info);
+                return Ok(());
+            }
+            self.logger.log(&format!("GSM did not turn {} after power key pulse {} of {}.",
+                                     state,
+                                     attempt,
+                                     retries),
+                            Warn);
+        }
+
+        self.logger.log("Resetting GSM…", Warn);
+        self.reset();
+        // The modem restarts after a reset, so it still needs a pulse to turn off, unless it did
+        // not restart.
+        let done = if self.wait_for_power(true) && !on {
+            self.pulse_power_key();
+            self.wait_for_power(false)
+        } else {
+            self.is_on() == on
+        };
+
+        if done {
+            self.logger.log(&format!("GSM {} after reset, in {} ms.",
+                                     state,
+                                     millis(start.elapsed())),
+                            Info);
+            Ok(())
+        } else {
+            self.logger.log(&format!("GSM refused to turn {} after {} ms.",
+                                     state,
+                                     millis(start.elapsed())),
+                            Error);
+            Err(GsmError::PowerStuck(!on))
+        }
+    }
+
+    /// Waits for the status pin to show the given power state, returning whether it did.
+    fn wait_for_power(&self, on: bool) -> bool {
+        let start = Instant::now();
+        while self.is_on() != on {
+            if start.elapsed() >= Duration::from_millis(POWER_STATUS_TIMEOUT) {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(POWER_POLL_INTERVAL));
+        }
+
+        true
+    }
+
+    /// Pulses the power key of the modem, which toggles its power state.
+    ///
+    /// The simulated modem ignores the number of pulses set in `simulated_ignored_pulses`.
+    fn pulse_power_key(&mut self) {
+        if cfg!(any(test, feature = "sim", feature = "real-sim")) {
+            if self.simulated_ignored_pulses > 0 {
+                self.simulated_ignored_pulses -= 1;
+            } else {
+                self.simulated_on = !self.simulated_on;
+            }
+        } else {
+            self.power_pin.digital_write(Value::Low);
+            thread::sleep(Duration::from_millis(POWER_KEY_PULSE));
+            self.power_pin.digital_write(Value::High);
+        }
+    }
+
+    /// Pulses the reset line of the modem, which restarts it.
+    fn reset(&mut self) {
+        if cfg!(any(test, feature = "sim", feature = "real-sim")) {
+            self.simulated_on = true;
+        } else {
+            self.reset_pin.digital_write(Value::Low);
+            thread::sleep(Duration::from_millis(RESET_PULSE));
+            self.reset_pin.digital_write(Value::High);
+        }
+    }
+}
+
+/// Converts a duration to whole milliseconds.
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + duration.subsec_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Gsm, GsmError};
+    use super::super::transport::ScriptedTransport;
+
+    /// Creates a GSM module trying two power key pulses, in the given power state, ignoring the
+    /// given number of pulses.
+    fn gsm(on: bool, ignored_pulses: u8) -> Gsm<ScriptedTransport> {
+        let mut gsm = Gsm::simulated(ScriptedTransport::new(), "[gsm]\npower_retries = 2\n");
+        gsm.simulated_on = on;
+        gsm.simulated_ignored_pulses = ignored_pulses;
+        gsm
+    }
+
+    #[test]
+    fn power_already_set() {
+        let mut on = gsm(true, 0);
+        on.set_power(true).unwrap();
+        assert!(on.is_on());
+
+        let mut off = gsm(false, 0);
+        off.set_power(false).unwrap();
+        assert!(!off.is_on());
+    }
+
+    #[test]
+    fn power_retries() {
+        let mut gsm = gsm(false, 1);
+        gsm.set_power(true).unwrap();
+        assert!(gsm.is_on());
+
+        gsm.simulated_ignored_pulses = 1;
+        gsm.set_power(false).unwrap();
+        assert!(!gsm.is_on());
+        assert!(gsm.serial.written().is_empty());
+    }
+
+    #[test]
+    fn power_reset() {
+        let mut gsm = gsm(false, 2);
+        gsm.set_power(true).unwrap();
+        assert!(gsm.is_on());
+
+        // The modem restarts after the reset, and takes one more pulse to turn off.
+        gsm.simulated_ignored_pulses = 2;
+        gsm.set_power(false).unwrap();
+        assert!(!gsm.is_on());
+        assert_eq!(gsm.simulated_ignored_pulses, 0);
+    }
+
+    #[test]
+    fn power_stuck() {
+        let mut gsm = gsm(true, 3);
+        match gsm.set_power(false) {
+            Err(GsmError::PowerStuck(true)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(gsm.is_on());
+    }
+}
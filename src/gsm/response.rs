@@ -0,0 +1,439 @@
+//! AT command response reader.
+//!
+//! The modem answers every command with zero or more information lines followed by a final
+//! result code. The reader collects the information lines until a final result code arrives,
+//! stripping the command echo, and gives up once the command deadline has passed. Unsolicited
+//! result codes received meanwhile are handed to the URC dispatcher.
+
+use std::{fmt, mem, thread};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use log::LogLevel::*;
+
+use logger::Logger;
+use super::Transport;
+use super::error::{GsmError, ModemError};
+use super::urc::{Urc, UrcDispatcher};
+
+/// Final result code ending a command response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinalResult {
+    /// `OK`, or the `SHUT OK` and `<n>, CLOSE OK` of the IP stack commands.
+    Ok,
+    /// `ERROR`.
+    Error,
+    /// `+CME ERROR: <err>`, with the error code or text.
+    CmeError(String),
+    /// `+CMS ERROR: <err>`, with the error code or text.
+    CmsError(String),
+    /// `> ` or `DOWNLOAD`, the prompt asking for the body of an SMS or a data transfer.
+    Prompt,
+    /// `BUSY`, `NO ANSWER`, `NO CARRIER` or `NO DIALTONE`, ending `ATD` when the call could not
+    /// be placed.
+    CallResult(String),
+}
+
+impl FinalResult {
+    /// Parses a final result code from a response line.
+    fn from_line(line: &str) -> Option<FinalResult> {
+        if line == "OK" || line == "SHUT OK" || line.ends_with(", CLOSE OK") {
+            Some(FinalResult::Ok)
+        } else if line == "ERROR" {
+            Some(FinalResult::Error)
+        } else if let Some(error) = line.strip_prefix("+CME ERROR:") {
+            Some(FinalResult::CmeError(error.trim().to_owned()))
+        } else {
+            line.strip_prefix("+CMS ERROR:")
+                .map(|error| FinalResult::CmsError(error.trim().to_owned()))
+        }
+    }
+
+    /// Parses the result code of a call that could not be placed from a response line.
+    fn from_call_line(line: &str) -> Option<FinalResult> {
+        match line {
+            "BUSY" | "NO ANSWER" | "NO CARRIER" | "NO DIALTONE" => {
+                Some(FinalResult::CallResult(line.to_owned()))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FinalResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FinalResult::Ok => write!(f, "OK"),
+            FinalResult::Error => write!(f, "ERROR"),
+            FinalResult::CmeError(ref e) => write!(f, "+CME ERROR: {}", e),
+            FinalResult::CmsError(ref e) => write!(f, "+CMS ERROR: {}", e),
+            FinalResult::Prompt => write!(f, "> "),
+            FinalResult::CallResult(ref r) => write!(f, "{}", r),
+        }
+    }
+}
+
+/// Complete response to an AT command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    lines: Vec<String>,
+    result: FinalResult,
+}
+
+impl Response {
+    /// Gets the information lines of the response, without the echo and the final result code.
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Gets the final result code of the response.
+    pub fn result(&self) -> &FinalResult {
+        &self.result
+    }
+
+    /// Checks if the command succeeded.
+    pub fn is_ok(&self) -> bool {
+        self.result == FinalResult::Ok
+    }
+
+    /// Checks if the modem is waiting for more data after a `> ` prompt.
+    pub fn is_prompt(&self) -> bool {
+        self.result == FinalResult::Prompt
+    }
+
+    /// Gets the error corresponding to an unsuccessful response.
+    pub fn to_error(&self, command: &str) -> GsmError {
+        match self.result {
+            FinalResult::CmeError(ref e) => GsmError::Equipment(ModemError::cme(e)),
+            FinalResult::CmsError(ref e) => GsmError::MessageService(ModemError::cms(e)),
+            ref result => GsmError::unexpected(command, result.to_string()),
+        }
+    }
+
+    /// Gets the first information line starting with the given prefix, such as `+CREG:`.
+    pub fn information(&self, prefix: &str) -> Option<&str> {
+        self.lines.iter().find(|l| l.starts_with(prefix)).map(|l| l.as_str())
+    }
+}
+
+/// Reader for AT command responses.
+pub struct ResponseReader {
+    partial: String,
+    data: Vec<u8>,
+    urcs: UrcDispatcher,
+}
+
+impl ResponseReader {
+    /// Creates a new response reader.
+    pub fn new() -> ResponseReader {
+        ResponseReader {
+            partial: String::new(),
+            data: Vec::new(),
+            urcs: UrcDispatcher::new(),
+        }
+    }
+
+    /// Subscribes to the unsolicited result codes received from now on.
+    pub fn subscribe(&mut self) -> Receiver<Urc> {
+        self.urcs.subscribe()
+    }
+
+    /// Reads from the transport for the given time without expecting any response, dispatching
+    /// the unsolicited result codes received.
+    pub fn poll<T: Transport>(&mut self,
+                              transport: &mut T,
+                              timeout: Duration,
+                              logger: &mut Logger)
+                              -> Result<(), GsmError> {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            let line = match try!(self.read_line(transport)) {
+                Some(ref line) if line.is_empty() => continue,
+                Some(line) => line,
+                None => continue,
+            };
+            logger.log(&format!("Received: '{}'", line), Info);
+
+            if !self.urcs.handle_line(&line) {
+                logger.log(&format!("Unexpected line outside a command response: '{}'", line),
+                           Warn);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the response to the given command, stopping at the final result code.
+    ///
+    /// The first line matching `command` is considered the echo and is discarded without logging
+    /// it. If no final result code is received before `timeout` elapses, a timeout error is
+    /// returned.
+    ///
+    /// Call results are URCs once a call is set up, but they are the final result code of `ATD`
+    /// if the call cannot be placed, so they end its response.
+    pub fn read<T: Transport>(&mut self,
+                              transport: &mut T,
+                              command: Option<&str>,
+                              timeout: Duration,
+                              logger: &mut Logger)
+                              -> Result<Response, GsmError> {
+        let start = Instant::now();
+        let mut echo_pending = command.is_some();
+        let dialing = command.map_or(false, |c| c.starts_with("ATD"));
+        let mut lines = Vec::new();
+
+        while start.elapsed() < timeout {
+            let line = match try!(self.read_line(transport)) {
+                Some(line) => line,
+                None => continue,
+            };
+            // The echo is not logged, since it repeats the sent command, which might be masked.
+            if echo_pending && Some(line.as_str()) == command {
+                echo_pending = false;
+                continue;
+            }
+            logger.log(&format!("Received: '{}'", line), Info);
+
+            if line == ">" || line == "DOWNLOAD" {
+                return Ok(Response {
+                    lines: lines,
+                    result: FinalResult::Prompt,
+                });
+            }
+            if dialing {
+                if let Some(result) = FinalResult::from_call_line(&line) {
+                    return Ok(Response {
+                        lines: lines,
+                        result: result,
+                    });
+                }
+            }
+            if line.is_empty() || self.urcs.handle_line(&line) {
+                continue;
+            }
+            if let Some(result) = FinalResult::from_line(&line) {
+                return Ok(Response {
+                    lines: lines,
+                    result: result,
+                });
+            }
+
+            lines.push(line);
+        }
+
+        Err(GsmError::Timeout(command.unwrap_or("<no command>").to_owned()))
+    }
+
+    /// Reads a line from the transport, without the line terminator and trailing spaces.
+    ///
+    /// Returns `None` if no complete line is available yet. The `> ` prompt is returned as a
+    /// line on its own, since the modem does not terminate it.
+    /// While the data of a `+RECEIVE` URC is pending, it is read instead, returning `None`.
+    fn read_line<T: Transport>(&mut self, transport: &mut T) -> Result<Option<String>, GsmError> {
+        if let Some(length) = self.urcs.pending_data() {
+            try!(self.read_data(transport, length));
+            return Ok(None);
+        }
+
+        if try!(transport.read_line(&mut self.partial)) == 0 {
+            // In-memory transports return immediately, so avoid spinning until the deadline.
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        if self.partial.ends_with('\n') || self.partial.trim() == ">" {
+            let line = self.partial.trim().to_owned();
+            self.partial.clear();
+            Ok(Some(line))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Reads the data of a `+RECEIVE` URC, handing it to the dispatcher once complete.
+    fn read_data<T: Transport>(&mut self,
+                               transport: &mut T,
+                               length: usize)
+                               -> Result<(), GsmError> {
+        let missing = length - self.data.len();
+        if try!(transport.read_bytes(&mut self.data, missing)) == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        if self.data.len() == length {
+            self.urcs.handle_data(mem::take(&mut self.data));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::time::{Duration, Instant};
+
+    use logger::Logger;
+    use gsm::error::GsmError;
+    use gsm::transport::ScriptedTransport;
+    use gsm::urc::Urc;
+    use super::*;
+
+    fn logger() -> Logger {
+        Logger::new(&env::temp_dir().join("GSM"), "ResponseTest", "GSM").unwrap()
+    }
+
+    /// Reads the response to `command` from the given modem lines.
+    fn read(lines: &[&str], command: &str) -> Result<Response, GsmError> {
+        let mut transport = ScriptedTransport::new();
+        for line in lines {
+            transport.push_line(*line);
+        }
+        ResponseReader::new().read(&mut transport,
+                                   Some(command),
+                                   Duration::from_millis(200),
+                                   &mut logger())
+    }
+
+    #[test]
+    fn it_strips_the_echo_once() {
+        let response = read(&["AT+CSQ", "", "+CSQ: 18,0", "", "OK"], "AT+CSQ").unwrap();
+        assert_eq!(response.lines(), &["+CSQ: 18,0"]);
+        assert!(response.is_ok());
+        assert_eq!(response.information("+CSQ:"), Some("+CSQ: 18,0"));
+
+        // Only the first line equal to the command is taken as the echo.
+        let response = read(&["ATI", "SIM800 R14.18", "ATI", "OK"], "ATI").unwrap();
+        assert_eq!(response.lines(), &["SIM800 R14.18", "ATI"]);
+    }
+
+    #[test]
+    fn it_stops_at_prompts() {
+        let response = read(&["AT+CMGS=20", "> "], "AT+CMGS=20").unwrap();
+        assert!(response.is_prompt());
+        assert!(response.lines().is_empty());
+
+        let response = read(&["DOWNLOAD"], "AT+HTTPDATA=10,10000").unwrap();
+        assert_eq!(response.result(), &FinalResult::Prompt);
+    }
+
+    #[test]
+    fn it_times_out_without_final_result() {
+        let start = Instant::now();
+        match read(&["AT+COPS=?", "+COPS: (1,\"Movistar\",\"MOVISTAR\",\"21407\")"],
+                   "AT+COPS=?") {
+            Err(GsmError::Timeout(ref command)) => assert_eq!(command, "AT+COPS=?"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn it_reads_error_results() {
+        let response = read(&["+CME ERROR: 10"], "AT+CPIN?").unwrap();
+        assert_eq!(response.result(), &FinalResult::CmeError(String::from("10")));
+        match response.to_error("AT+CPIN?") {
+            GsmError::Equipment(ref e) => assert_eq!(e.get_code(), Some(10)),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let response = read(&["AT+CMGS=20", "+CMS ERROR: 331"], "AT+CMGS=20").unwrap();
+        match response.to_error("AT+CMGS=20") {
+            GsmError::MessageService(ref e) => assert_eq!(e.get_code(), Some(331)),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let response = read(&["ERROR"], "AT+CGATT=1").unwrap();
+        assert_eq!(response.result(), &FinalResult::Error);
+        match response.to_error("AT+CGATT=1") {
+            GsmError::UnexpectedResponse { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn it_dispatches_urcs_inside_responses() {
+        let mut transport = ScriptedTransport::new();
+        transport.push_line("AT+CBC")
+            .push_line("RING")
+            .push_line("+CBC: 0,80,4100")
+            .push_line("+CMTI: \"SM\",3")
+            .push_line("OK");
+        let mut reader = ResponseReader::new();
+        let urcs = reader.subscribe();
+
+        let response = reader.read(&mut transport,
+                                   Some("AT+CBC"),
+                                   Duration::from_millis(200),
+                                   &mut logger())
+            .unwrap();
+        assert_eq!(response.lines(), &["+CBC: 0,80,4100"]);
+        assert_eq!(urcs.try_recv().unwrap(), Urc::Ring);
+        assert_eq!(urcs.try_recv().unwrap(),
+                   Urc::NewMessage {
+                       storage: String::from("SM"),
+                       index: 3,
+                   });
+        assert!(urcs.try_recv().is_err());
+    }
+
+    #[test]
+    fn it_reads_received_data_inside_responses() {
+        let mut transport = ScriptedTransport::new();
+        transport.push_line("AT+CSQ")
+            .push_line("+RECEIVE,0,6:")
+            .push_data(b"OK\r\n\x00\xFF")
+            .push_line("+CSQ: 18,0")
+            .push_line("OK");
+        let mut reader = ResponseReader::new();
+        let urcs = reader.subscribe();
+
+        let response = reader.read(&mut transport,
+                                   Some("AT+CSQ"),
+                                   Duration::from_millis(200),
+                                   &mut logger())
+            .unwrap();
+        assert_eq!(response.lines(), &["+CSQ: 18,0"]);
+        assert_eq!(urcs.try_recv().unwrap(),
+                   Urc::SocketData {
+                       link: 0,
+                       data: b"OK\r\n\x00\xFF".to_vec(),
+                   });
+    }
+
+    #[test]
+    fn it_ends_dialing_with_call_results() {
+        let mut transport = ScriptedTransport::new();
+        transport.push_line("ATD+34600000001;").push_line("BUSY");
+        let mut reader = ResponseReader::new();
+        let urcs = reader.subscribe();
+
+        let response = reader.read(&mut transport,
+                                   Some("ATD+34600000001;"),
+                                   Duration::from_millis(200),
+                                   &mut logger())
+            .unwrap();
+        assert_eq!(response.result(), &FinalResult::CallResult(String::from("BUSY")));
+        assert!(urcs.try_recv().is_err());
+
+        // Once the call is set up, call results are URCs.
+        transport.push_line("AT+CLCC").push_line("NO CARRIER").push_line("OK");
+        let response = reader.read(&mut transport,
+                                   Some("AT+CLCC"),
+                                   Duration::from_millis(200),
+                                   &mut logger())
+            .unwrap();
+        assert!(response.is_ok());
+        assert_eq!(urcs.try_recv().unwrap(), Urc::NoCarrier);
+    }
+
+    #[test]
+    fn it_polls_for_urcs() {
+        let mut transport = ScriptedTransport::new();
+        transport.push_line("UNDER-VOLTAGE WARNNING").push_line("OK");
+        let mut reader = ResponseReader::new();
+        let urcs = reader.subscribe();
+
+        reader.poll(&mut transport, Duration::from_millis(50), &mut logger()).unwrap();
+        assert_eq!(urcs.try_recv().unwrap(), Urc::UnderVoltageWarning);
+        assert!(urcs.try_recv().is_err());
+    }
+}
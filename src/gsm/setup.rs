@@ -0,0 +1,134 @@
+//! Modem set-up after power-on.
+//!
+//! The SIM800 starts in autobauding mode, echoing commands and without error details, so it is
+//! configured every time it is turned on. Every setting is written explicitly, so the set-up can
+//! be repeated at any time with the same result.
+
+use std::time::Duration;
+
+use log::LogLevel::*;
+
+use super::{Gsm, GsmError, Transport};
+
+/// Number of `AT` commands sent to let the modem detect the baud rate.
+const AUTOBAUD_ATTEMPTS: u32 = 10;
+/// Deadline for each autobauding `AT` command, in milliseconds.
+const AUTOBAUD_TIMEOUT: u64 = 500;
+/// Storage used for received and sent SMS: the SIM card.
+const SMS_STORAGE: &'static str = "SM";
+/// Character set used in text mode SMS.
+const SMS_CHARSET: &'static str = "GSM";
+
+impl<T: Transport> Gsm<T> {
+    /// Configures the modem after power-on: baud rate, echo, error reporting, SMS settings, caller
+    /// ID and network time updates.
+    ///
+    /// The SIM card is unlocked too, failing if it is locked, absent or blocked.
+    pub fn initialize_modem(&mut self) -> Result<(), GsmError> {
+        self.logger.log("Initializing GSM…", Info);
+        if !self.is_on() {
+            error!("Trying to initialize GSM, but GSM was off.");
+            return Err(GsmError::PowerOff);
+        }
+
+        try!(self.autobaud());
+        try!(self.send_command_ok("ATE0"));
+        try!(self.send_command_ok("AT+CMEE=2"));
+        try!(self.unlock_sim());
+        // The configured baud rate is fixed in the modem, so that it does not need autobauding.
+        try!(self.send_command_ok(&format!("AT+IPR={}", self.baud_rate)));
+        try!(self.send_command_ok(&format!("AT+CPMS=\"{0}\",\"{0}\",\"{0}\"", SMS_STORAGE)));
+        try!(self.send_command_ok(&format!("AT+CSCS=\"{}\"", SMS_CHARSET)));
+        try!(self.send_command_ok("AT+CLIP=1"));
+        // The network time is received while registering, which happens right after power-on,
+        // so its update is saved to be enabled from the next start-up on.
+        try!(self.send_command_ok("AT+CLTS=1"));
+        try!(self.send_command_ok("AT&W"));
+
+        self.logger.log("GSM initialized.", Info);
+        Ok(())
+    }
+
+    /// Sends `AT` until the modem answers, so that it detects the baud rate of the port.
+    fn autobaud(&mut self) -> Result<(), GsmError> {
+        let timeout = Duration::from_millis(AUTOBAUD_TIMEOUT);
+        for attempt in 1..=AUTOBAUD_ATTEMPTS {
+            match self.send_command_timeout("AT", timeout) {
+                Ok(ref response) if response.is_ok() => {
+                    self.logger.log(&format!("GSM answered after {} 'AT' command(s).", attempt),
+                                    Info);
+                    return Ok(());
+                }
+                Ok(_) | Err(GsmError::Timeout(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.logger.log(&format!("GSM did not answer {} 'AT' commands.", AUTOBAUD_ATTEMPTS),
+                        Error);
+        Err(GsmError::Timeout(String::from("AT")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AUTOBAUD_ATTEMPTS;
+    use super::super::{Gsm, GsmError, Transport};
+    use super::super::parse::SimState;
+    use super::super::emulator::Emulator;
+
+    #[test]
+    fn initialize_repeatedly() {
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        gsm.initialize_modem().unwrap();
+        gsm.initialize_modem().unwrap();
+
+        // The echo is disabled.
+        gsm.serial.write_bytes(b"AT\r").unwrap();
+        let mut line = String::new();
+        gsm.serial.read_line(&mut line).unwrap();
+        assert_eq!(line.trim(), "OK");
+    }
+
+    #[test]
+    fn autobauding() {
+        let mut emulator = Emulator::new();
+        emulator.script("AT", &[]).script("AT", &[]);
+        let mut gsm = Gsm::simulated(emulator, "");
+        gsm.initialize_modem().unwrap();
+    }
+
+    #[test]
+    fn no_answer() {
+        let mut emulator = Emulator::new();
+        for _ in 0..AUTOBAUD_ATTEMPTS {
+            emulator.script("AT", &[]);
+        }
+        let mut gsm = Gsm::simulated(emulator, "");
+        match gsm.initialize_modem() {
+            Err(GsmError::Timeout(ref command)) => assert_eq!(command, "AT"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn sim_locked() {
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CPIN?", &["+CPIN: SIM PUK", "OK"]);
+        let mut gsm = Gsm::simulated(emulator, "");
+        match gsm.initialize_modem() {
+            Err(GsmError::SimLocked(SimState::PukRequired)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn power_off() {
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        gsm.simulated_on = false;
+        match gsm.initialize_modem() {
+            Err(GsmError::PowerOff) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
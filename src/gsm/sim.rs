@@ -0,0 +1,201 @@
+//! SIM card access.
+//!
+//! A SIM card locked with a PIN makes every network command fail until the PIN is entered. Once
+//! the card rejects the configured PIN, it is not entered again until the software restarts,
+//! even if the modem is turned on again, since wrong attempts count towards blocking the card. The
+//! PUK is never entered automatically: a blocked card has to be checked on the ground.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::LogLevel::*;
+
+use super::{Gsm, GsmError, Transport};
+use super::parse::SimState;
+
+/// Deadline for the SIM card to finish starting up, in seconds.
+const SIM_READY_TIMEOUT: u64 = 10;
+/// Time between SIM state checks while it starts up, in milliseconds.
+const SIM_POLL_INTERVAL: u64 = 500;
+
+impl<T: Transport> Gsm<T> {
+    /// Gets the state of the SIM card.
+    pub fn get_sim_state(&mut self) -> Result<SimState, GsmError> {
+        if !self.is_on() {
+            error!("Trying to check the SIM card, but GSM was off.");
+            return Err(GsmError::PowerOff);
+        }
+
+        match self.send_command_ok("AT+CPIN?") {
+            Ok(response) => self.parse_information(&response, "+CPIN:"),
+            // Without a usable SIM card, the modem answers with an error instead of a state.
+            Err(GsmError::Equipment(e)) => {
+                match e.get_code() {
+                    Some(10) => Ok(SimState::NotInserted),
+                    Some(14) => Ok(SimState::NotReady),
+                    _ => Err(GsmError::Equipment(e)),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Unlocks the SIM card with the configured PIN if needed, failing if it is not ready
+    /// afterwards.
+    pub fn unlock_sim(&mut self) -> Result<(), GsmError> {
+        let mut state = try!(self.wait_for_sim());
+        info!("SIM state: {:?}.", state);
+        self.logger.log(&format!("SIM state: {:?}.", state), Info);
+
+        if state == SimState::PinRequired {
+            let pin = match self.config.get_pin() {
+                Some(pin) => pin.to_owned(),
+                None => {
+                    self.logger.log("SIM card locked, but no PIN configured.", Error);
+                    return Err(GsmError::SimLocked(state));
+                }
+            };
+            if self.pin_rejected {
+                self.logger.log("SIM card locked, and the configured PIN was rejected before.",
+                                Error);
+                return Err(GsmError::SimLocked(state));
+            }
+            try!(self.enter_pin(&pin));
+            state = try!(self.wait_for_sim());
+            self.logger.log(&format!("SIM state after entering the PIN: {:?}.", state), Info);
+        }
+
+        match state {
+            SimState::Ready => Ok(()),
+            SimState::PukRequired => {
+                self.logger.log("SIM card blocked, PUK required. It will not be entered \
+                                 automatically.",
+                                Error);
+                Err(GsmError::SimLocked(state))
+            }
+            _ => {
+                self.logger.log(&format!("SIM card not ready: {:?}.", state), Error);
+                Err(GsmError::SimLocked(state))
+            }
+        }
+    }
+
+    /// Waits for the SIM card to finish starting up, returning its state.
+    fn wait_for_sim(&mut self) -> Result<SimState, GsmError> {
+        let start = Instant::now();
+        loop {
+            let state = try!(self.get_sim_state());
+            if state != SimState::NotReady ||
+               start.elapsed() >= Duration::from_secs(SIM_READY_TIMEOUT) {
+                return Ok(state);
+            }
+            thread::sleep(Duration::from_millis(SIM_POLL_INTERVAL));
+        }
+    }
+
+    /// Enters the PIN of the SIM card, keeping it out of the command log.
+    fn enter_pin(&mut self, pin: &str) -> Result<(), GsmError> {
+        let command = format!("AT+CPIN=\"{}\"", pin);
+        let response = try!(self.send_command_masked(&command, "AT+CPIN=\"****\""));
+        if response.is_ok() {
+            self.logger.log("SIM PIN accepted.", Info);
+            Ok(())
+        } else {
+            self.pin_rejected = true;
+            let error = response.to_error("AT+CPIN");
+            self.logger.log(&format!("SIM PIN rejected, not retrying so that the card is not \
+                                      blocked: {}",
+                                     error),
+                            Error);
+            Err(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use std::io::Read;
+
+    use logger::Logger;
+    use super::super::{Gsm, GsmError};
+    use super::super::parse::SimState;
+    use super::super::emulator::Emulator;
+
+    /// Creates a GSM module with the PIN `1234` configured, talking to the given emulator.
+    fn gsm(emulator: Emulator) -> Gsm<Emulator> {
+        Gsm::simulated(emulator, "[gsm]\npin = \"1234\"\n")
+    }
+
+    #[test]
+    fn pin_accepted() {
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CPIN?", &["+CPIN: SIM PIN", "OK"])
+            .script("AT+CPIN=\"1234\"", &["OK"]);
+        let mut gsm = gsm(emulator);
+        let dir = env::temp_dir().join("SimPinTest");
+        fs::create_dir_all(&dir).unwrap();
+        gsm.command_logger = Logger::new(&dir.join("log"), "SimPinCommands", "GSMCommands")
+            .unwrap();
+
+        gsm.unlock_sim().unwrap();
+
+        // The echo of the command is not logged either.
+        let mut log = String::new();
+        for entry in fs::read_dir(&dir).unwrap() {
+            fs::File::open(entry.unwrap().path()).unwrap().read_to_string(&mut log).unwrap();
+        }
+        assert!(log.contains("AT+CPIN=\"****\""));
+        assert!(!log.contains("1234"));
+    }
+
+    #[test]
+    fn pin_rejected() {
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CPIN?", &["+CPIN: SIM PIN", "OK"])
+            .script("AT+CPIN=\"1234\"", &["+CME ERROR: 16"])
+            .script("AT+CPIN?", &["+CPIN: SIM PIN", "OK"]);
+        let mut gsm = gsm(emulator);
+
+        match gsm.unlock_sim() {
+            Err(GsmError::Equipment(ref e)) => assert_eq!(e.get_code(), Some(16)),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        // The emulator would answer another PIN with a different error.
+        match gsm.unlock_sim() {
+            Err(GsmError::SimLocked(SimState::PinRequired)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn puk_required() {
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CPIN?", &["+CPIN: SIM PUK", "OK"]);
+        let mut gsm = gsm(emulator);
+
+        match gsm.unlock_sim() {
+            Err(GsmError::SimLocked(SimState::PukRequired)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn sim_not_inserted() {
+        let mut emulator = Emulator::new();
+        emulator.script("AT+CPIN?", &["+CME ERROR: 10"]);
+        let mut gsm = gsm(emulator);
+
+        match gsm.unlock_sim() {
+            Err(GsmError::SimLocked(SimState::NotInserted)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn sim_ready() {
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        assert_eq!(gsm.get_sim_state().unwrap(), SimState::Ready);
+        gsm.unlock_sim().unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+//! SMS sending and reception.
+//!
+//! Outgoing messages are sent in PDU mode, split in concatenated segments when they do not fit in
+//! a single SMS. Incoming messages are stored in the SIM by the modem, which announces them with
+//! a `+CMTI` URC. They are listed in PDU mode too, so that no text can be mistaken for a result
+//! code or a URC, and must be deleted once handled so that the storage does not fill up.
+
+use std::thread;
+use std::time::Duration;
+
+use log::LogLevel::*;
+
+use super::{Gsm, GsmError, Transport, command_timeout};
+use super::parse::{MessageHeader, SmsReference};
+use super::pdu;
+use super::delivery::DeliveryState;
+
+/// Time the network keeps trying to deliver an SMS, in seconds.
+const SMS_VALIDITY_PERIOD: u64 = 24 * 60 * 60;
+
+/// Result of sending one segment of an SMS: the message reference assigned by the modem, or the
+/// error sending it.
+pub type SegmentResult = Result<u8, GsmError>;
+
+/// SMS received and stored in the modem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedSms {
+    index: u32,
+    sender: String,
+    timestamp: String,
+    text: String,
+}
+
+impl ReceivedSms {
+    /// Gets the storage index of the message.
+    pub fn get_index(&self) -> u32 {
+        self.index
+    }
+
+    /// Gets the phone number of the sender.
+    pub fn get_sender(&self) -> &str {
+        &self.sender
+    }
+
+    /// Gets the service center timestamp of the message, as `yy/MM/dd,hh:mm:ss±zz`.
+    pub fn get_timestamp(&self) -> &str {
+        &self.timestamp
+    }
+
+    /// Gets the text of the message.
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl<T: Transport> Gsm<T> {
+    /// Gets a new concatenated message reference, to send an SMS with `send_sms_segments()`.
+    pub fn new_concatenated_reference(&mut self) -> u8 {
+        self.concatenated_reference = self.concatenated_reference.wrapping_add(1);
+        self.concatenated_reference
+    }
+
+    /// Sends the segments of an SMS to the given number, except the ones in `sent`, with the given
+    /// concatenated message reference. The message is split in concatenated segments if needed.
+    ///
+    /// All segments are sent even if some of them fail, and an error is returned only if the
+    /// message could not be sent at all. Segments are numbered from 1. Resending the failed
+    /// segments of a message with its original reference lets the phone join them with the ones
+    /// it already received. The number and the result of each segment sent are returned in order.
+    pub fn send_sms_segments(&mut self,
+                             message: &str,
+                             number: &str,
+                             reference: u8,
+                             sent: &[u8])
+                             -> Result<Vec<(u8, SegmentResult)>, GsmError> {
+        self.logger.log(&format!("Sending SMS: \"{}\" ({} characters) to number \"{}\"…",
+                                 message,
+                                 message.chars().count(),
+                                 number),
+                        Info);
+        if self.is_on() {
+            let pdus = match pdu::encode_submit(number,
+                                                message,
+                                                reference,
+                                                Some(Duration::from_secs(SMS_VALIDITY_PERIOD)),
+                                                true) {
+                Ok(pdus) => pdus,
+                Err(e) => {
+                    self.logger.log(&format!("Could not encode SMS: {}", e), Error);
+                    return Err(GsmError::InvalidInput(e.to_string()));
+                }
+            };
+            self.logger.log(&format!("SMS encoded in {:?} ({} septets) in {} segment(s).",
+                                     pdu::Alphabet::for_text(message),
+                                     pdu::septet_count(message)
+                                         .map_or(String::from("no GSM"), |s| s.to_string()),
+                                     pdus.len()),
+                            Info);
+            let segments = (1..=pdus.len() as u8)
+                .zip(pdus.iter())
+                .filter(|&(segment, _)| !sent.contains(&segment))
+                .collect::<Vec<_>>();
+
+            if !cfg!(feature = "sms") {
+                thread::sleep(Duration::from_secs(5));
+                return Ok(segments.iter().map(|&(segment, _)| (segment, Ok(0))).collect());
+            }
+
+            try!(self.send_command_ok("AT+CMGF=0"));
+            // Route new message indications and status reports directly as URCs.
+            try!(self.send_command_ok("AT+CNMI=2,1,0,1,0"));
+
+            let numbers = segments.iter().map(|&(segment, _)| segment).collect::<Vec<_>>();
+            let mut results = Vec::with_capacity(segments.len());
+            for (segment, pdu) in segments {
+                let result = self.send_pdu(pdu);
+                match result {
+                    Ok(reference) => {
+                        self.logger.log(&format!("SMS segment {}/{} sent with reference {}.",
+                                                 segment,
+                                                 pdus.len(),
+                                                 reference),
+                                        Info);
+                    }
+                    Err(ref e) => {
+                        self.logger.log(&format!("Error sending SMS segment {}/{}: {}",
+                                                 segment,
+                                                 pdus.len(),
+                                                 e),
+                                        Error);
+                    }
+                }
+                results.push(result);
+            }
+            self.deliveries.track(number, &results);
+
+            Ok(numbers.into_iter().zip(results).collect())
+        } else {
+            error!("Trying to send SMS, but GSM was off.");
+            Err(GsmError::PowerOff)
+        }
+    }
+
+    /// Gets the delivery state of the last message sent to the given number, after handling the
+    /// status reports received.
+    pub fn get_delivery_state(&mut self, number: &str) -> Option<DeliveryState> {
+        self.update_deliveries();
+        self.deliveries.get_recipient(number).map(|r| r.get_state())
+    }
+
+    /// Lists all the SMS received and stored in the modem, read or not.
+    ///
+    /// Messages that cannot be decoded are logged and deleted, so that they do not block the
+    /// rest. Messages stored to be sent are left alone.
+    pub fn list_sms(&mut self) -> Result<Vec<ReceivedSms>, GsmError> {
+        if self.is_on() {
+            try!(self.send_command_ok("AT+CMGF=0"));
+            // 4: all messages.
+            let response = try!(self.send_command_ok("AT+CMGL=4"));
+
+            let mut messages = Vec::new();
+            let mut invalid = Vec::new();
+            let mut lines = response.lines().iter().peekable();
+            while let Some(line) = lines.next() {
+                if !line.starts_with("+CMGL:") {
+                    self.logger.log(&format!("Unexpected line listing SMS: '{}'", line), Warn);
+                    continue;
+                }
+                let header = match line.parse::<MessageHeader>() {
+                    Ok(header) => header,
+                    Err(e) => {
+                        self.logger.log(&format!("Invalid '+CMGL:' response: {}", e), Error);
+                        continue;
+                    }
+                };
+                let pdu = match lines.peek() {
+                    Some(pdu) if !pdu.starts_with("+CMGL:") => lines.next(),
+                    _ => None,
+                };
+
+                if !header.get_status().is_received() {
+                    self.logger.log(&format!("Skipping stored outgoing SMS {}.",
+                                             header.get_index()),
+                                    Info);
+                    continue;
+                }
+                match received_sms(&header, pdu.map(|p| p.as_str())) {
+                    Ok(sms) => messages.push(sms),
+                    Err(e) => {
+                        self.logger.log(&format!("Invalid SMS {}, deleting it: {}",
+                                                 header.get_index(),
+                                                 e),
+                                        Error);
+                        invalid.push(header.get_index());
+                    }
+                }
+            }
+            for index in invalid {
+                if self.delete_sms(index).is_err() {
+                    self.logger.log(&format!("Error deleting invalid SMS {}.", index), Error);
+                }
+            }
+
+            self.logger.log(&format!("{} SMS stored in the GSM.", messages.len()), Info);
+            Ok(messages)
+        } else {
+            error!("Trying to list SMS, but GSM was off.");
+            Err(GsmError::PowerOff)
+        }
+    }
+
+    /// Deletes the SMS stored at the given index.
+    pub fn delete_sms(&mut self, index: u32) -> Result<(), GsmError> {
+        if self.is_on() {
+            try!(self.send_command_ok(&format!("AT+CMGD={}", index)));
+            self.logger.log(&format!("SMS {} deleted.", index), Info);
+            Ok(())
+        } else {
+            error!("Trying to delete SMS, but GSM was off.");
+            Err(GsmError::PowerOff)
+        }
+    }
+
+    /// Handles the status reports received, logging the delivery state changes.
+    ///
+    /// Reports are received as URCs while the modem is in use, so this should be called
+    /// regularly after sending SMS.
+    pub fn update_deliveries(&mut self) {
+        for update in self.deliveries.update() {
+            match update {
+                Ok(recipient) => {
+                    self.logger.log(&format!("SMS delivery to {}: {:?}.",
+                                             recipient.get_number(),
+                                             recipient.get_state()),
+                                    Info)
+                }
+                Err(e) => self.logger.log(&format!("Invalid status report: {}", e), Error),
+            }
+        }
+    }
+
+    /// Sends a single SMS PDU, returning its message reference.
+    fn send_pdu(&mut self, pdu: &pdu::SubmitPdu) -> SegmentResult {
+        let command = format!("AT+CMGS={}", pdu.get_length());
+        let response = try!(self.send_command(&command));
+        if !response.is_prompt() {
+            self.logger.log(&format!("No prompt received sending SMS on 'AT+CMGS' response: {}",
+                                     response.result()),
+                            Error);
+            return Err(response.to_error(&command));
+        }
+
+        try!(self.serial.write_bytes(pdu.get_hex().as_bytes()));
+        try!(self.serial.write_bytes(&[0x1A]));
+        try!(self.serial.flush());
+        self.command_logger.log(&format!("Sent: '{}'", pdu.get_hex()), Info);
+
+        let response = try!(self.reader.read(&mut self.serial,
+                                             Some(pdu.get_hex()),
+                                             command_timeout(&command),
+                                             &mut self.command_logger));
+        if !response.is_ok() {
+            self.logger.log(&format!("No '+CMGS' received after sending SMS: {}",
+                                     response.result()),
+                            Error);
+            return Err(response.to_error(&command));
+        }
+        let reference: SmsReference = try!(self.parse_information(&response, "+CMGS:"));
+
+        Ok(reference.get_reference())
+    }
+}
+
+/// Decodes a listed SMS from its header and PDU.
+fn received_sms(header: &MessageHeader, pdu: Option<&str>) -> Result<ReceivedSms, pdu::PduError> {
+    let pdu = try!(pdu.ok_or_else(|| pdu::PduError::InvalidPdu(String::new())));
+    // The SMSC address is not included in the length.
+    let smsc_octets = pdu.get(..2).and_then(|l| u8::from_str_radix(l, 16).ok()).unwrap_or(0);
+    if pdu.len() != (1 + smsc_octets as usize + header.get_length()) * 2 {
+        return Err(pdu::PduError::InvalidPdu(pdu.to_owned()));
+    }
+    let deliver = try!(pdu::decode_deliver(pdu));
+
+    Ok(ReceivedSms {
+        index: header.get_index(),
+        sender: deliver.get_sender().to_owned(),
+        timestamp: deliver.get_timestamp().to_owned(),
+        text: deliver.get_text().to_owned(),
+    })
+}
@@ -0,0 +1,490 @@
+//! TCP and UDP connections through the SIM800 IP stack.
+//!
+//! The IP stack runs in multi-connection mode, so that each connection uses one of the six links
+//! of the modem. Connection and send results, closed connections and received data are reported
+//! in unsolicited result codes, which are collected in the socket table as they arrive, so that
+//! data can be received at any time, even while other commands are running. They are only
+//! collected while some connection is open, so that they do not pile up when sockets are unused.
+
+use std::cmp;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use log::LogLevel::*;
+
+use super::{Gsm, GsmError, Transport, Urc};
+
+/// Number of connection links of the SIM800 IP stack.
+const SOCKET_LINKS: usize = 6;
+/// Maximum data that can be sent at once, in bytes.
+const SOCKET_MAX_SEND: usize = 1460;
+/// Deadline for the result of opening a connection, in seconds.
+const SOCKET_CONNECT_TIMEOUT: u64 = 75;
+/// Deadline for the result of sending data, in seconds.
+const SOCKET_SEND_TIMEOUT: u64 = 60;
+/// Time between checks for received data, in milliseconds.
+const SOCKET_POLL_INTERVAL: u64 = 100;
+
+/// Transport protocol of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    /// Gets the name of the protocol in `AT+CIPSTART`.
+    fn as_str(&self) -> &'static str {
+        match *self {
+            Protocol::Tcp => "TCP",
+            Protocol::Udp => "UDP",
+        }
+    }
+}
+
+/// Open connection, identified by its link in the IP stack.
+///
+/// Sockets cannot be cloned, so that a link is not used after closing it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Socket {
+    link: u8,
+    protocol: Protocol,
+}
+
+impl Socket {
+    /// Gets the link of the connection in the IP stack.
+    pub fn get_link(&self) -> u8 {
+        self.link
+    }
+
+    /// Gets the transport protocol of the connection.
+    pub fn get_protocol(&self) -> Protocol {
+        self.protocol
+    }
+}
+
+/// State of a connection link.
+#[derive(Debug, Clone, Default)]
+struct Link {
+    in_use: bool,
+    connected: Option<bool>,
+    sent: Option<bool>,
+    closed: bool,
+    received: Vec<u8>,
+}
+
+/// State of the connections of the IP stack, updated with the URCs received.
+pub struct SocketTable {
+    urcs: Option<Receiver<Urc>>,
+    ip_up: bool,
+    links: Vec<Link>,
+}
+
+impl SocketTable {
+    /// Creates a table with all the links free, not subscribed to the URCs yet.
+    pub fn new() -> SocketTable {
+        SocketTable {
+            urcs: None,
+            ip_up: false,
+            links: vec![Link::default(); SOCKET_LINKS],
+        }
+    }
+
+    /// Handles the URCs received since the last update.
+    fn update(&mut self) {
+        let urcs = match self.urcs {
+            Some(ref urcs) => urcs,
+            None => return,
+        };
+        while let Ok(urc) = urcs.try_recv() {
+            match urc {
+                Urc::SocketConnect { link, connected } => {
+                    if let Some(link) = self.links.get_mut(link as usize) {
+                        link.connected = Some(connected);
+                    }
+                }
+                Urc::SocketSend { link, sent } => {
+                    if let Some(link) = self.links.get_mut(link as usize) {
+                        link.sent = Some(sent);
+                    }
+                }
+                Urc::SocketClosed(link) => {
+                    if let Some(link) = self.links.get_mut(link as usize) {
+                        link.closed = true;
+                    }
+                }
+                Urc::SocketData { link, data } => {
+                    if let Some(link) = self.links.get_mut(link as usize) {
+                        link.received.extend(data);
+                    }
+                }
+                Urc::PdpDeactivated => {
+                    self.ip_up = false;
+                    for link in &mut self.links {
+                        link.closed = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Gets the state of the link of a socket.
+    fn link(&mut self, socket: &Socket) -> &mut Link {
+        &mut self.links[socket.link as usize]
+    }
+
+    /// Frees the link of a socket, dropping the URC subscription once no link is in use.
+    ///
+    /// The IP stack is then taken as down, since it could be deactivated unnoticed.
+    fn free(&mut self, socket: &Socket) {
+        self.link(socket).in_use = false;
+        if self.links.iter().all(|link| !link.in_use) {
+            self.urcs = None;
+            self.ip_up = false;
+        }
+    }
+}
+
+impl<T: Transport> Gsm<T> {
+    /// Opens a connection to the given host and port, bringing the IP stack up if needed.
+    pub fn connect(&mut self,
+                   protocol: Protocol,
+                   host: &str,
+                   port: u16)
+                   -> Result<Socket, GsmError> {
+        self.logger.log(&format!("Opening {} connection to {}:{}…",
+                                 protocol.as_str(),
+                                 host,
+                                 port),
+                        Info);
+        if !self.is_on() {
+            error!("Trying to open a connection, but GSM was off.");
+            return Err(GsmError::PowerOff);
+        }
+        if host.is_empty() || !host.chars().all(|c| c.is_ascii_graphic() && c != '"') {
+            return Err(GsmError::InvalidInput(format!("invalid host '{}'", host)));
+        }
+
+        self.sockets.update();
+        let link = match self.sockets.links.iter().position(|l| !l.in_use) {
+            Some(link) => link,
+            None => {
+                self.logger.log("No free connection links.", Error);
+                return Err(GsmError::InvalidInput(String::from("no free connection links")));
+            }
+        };
+        self.sockets.links[link] = Link {
+            in_use: true,
+            ..Link::default()
+        };
+        let socket = Socket {
+            link: link as u8,
+            protocol: protocol,
+        };
+        if let Err(e) = self.start_ip_stack() {
+            self.sockets.free(&socket);
+            return Err(e);
+        }
+
+        let command = format!("AT+CIPSTART={},\"{}\",\"{}\",\"{}\"",
+                              link,
+                              protocol.as_str(),
+                              host,
+                              port);
+        if let Err(e) = self.send_command_ok(&command) {
+            self.sockets.free(&socket);
+            return Err(e);
+        }
+
+        let start = Instant::now();
+        loop {
+            self.sockets.update();
+            match self.sockets.link(&socket).connected {
+                Some(true) => {
+                    self.logger.log(&format!("Connection to {}:{} open in link {}.",
+                                             host,
+                                             port,
+                                             link),
+                                    Info);
+                    return Ok(socket);
+                }
+                Some(false) => {
+                    self.logger.log(&format!("Connection to {}:{} failed.", host, port), Error);
+                    self.sockets.free(&socket);
+                    return Err(GsmError::unexpected(command, "CONNECT FAIL"));
+                }
+                None => {}
+            }
+
+            if start.elapsed() >= Duration::from_secs(SOCKET_CONNECT_TIMEOUT) {
+                self.logger.log(&format!("No connection result for {}:{}.", host, port), Error);
+                // The connection might still be open, so it is closed before freeing the link.
+                let _ = self.close(socket);
+                return Err(GsmError::Timeout(command));
+            }
+            if let Err(e) = self.poll_urcs(Duration::from_secs(1)) {
+                self.sockets.free(&socket);
+                return Err(e);
+            }
+        }
+    }
+
+    /// Sends data through a connection, waiting until the modem confirms that it was sent.
+    pub fn send(&mut self, socket: &Socket, data: &[u8]) -> Result<(), GsmError> {
+        if data.is_empty() || data.len() > SOCKET_MAX_SEND {
+            return Err(GsmError::InvalidInput(format!("cannot send {} bytes at once, the \
+                                                       maximum is {}",
+                                                      data.len(),
+                                                      SOCKET_MAX_SEND)));
+        }
+        self.sockets.update();
+        if self.sockets.link(socket).closed {
+            return Err(GsmError::ConnectionClosed(socket.link));
+        }
+        self.sockets.link(socket).sent = None;
+
+        let command = format!("AT+CIPSEND={},{}", socket.link, data.len());
+        let response = try!(self.send_command(&command));
+        if !response.is_prompt() {
+            self.logger.log(&format!("No prompt received on '{}' response: {}",
+                                     command,
+                                     response.result()),
+                            Error);
+            return Err(response.to_error(&command));
+        }
+        try!(self.serial.write_bytes(data));
+        try!(self.serial.flush());
+        self.command_logger.log(&format!("Sent: {} bytes of data in link {}",
+                                         data.len(),
+                                         socket.link),
+                                Info);
+
+        let start = Instant::now();
+        loop {
+            self.sockets.update();
+            match self.sockets.link(socket).sent.take() {
+                Some(true) => return Ok(()),
+                Some(false) => {
+                    self.logger.log(&format!("Error sending data in link {}.", socket.link),
+                                    Error);
+                    return Err(GsmError::unexpected(command, "SEND FAIL"));
+                }
+                None => {}
+            }
+            if self.sockets.link(socket).closed {
+                return Err(GsmError::ConnectionClosed(socket.link));
+            }
+
+            if start.elapsed() >= Duration::from_secs(SOCKET_SEND_TIMEOUT) {
+                self.logger.log(&format!("No send result in link {}.", socket.link), Error);
+                return Err(GsmError::Timeout(command));
+            }
+            try!(self.poll_urcs(Duration::from_secs(1)));
+        }
+    }
+
+    /// Receives the data that arrived through a connection, waiting at most `timeout` for it.
+    ///
+    /// Returns an empty vector if no data arrived, and an error once the connection is closed and
+    /// all its data has been received.
+    pub fn receive(&mut self, socket: &Socket, timeout: Duration) -> Result<Vec<u8>, GsmError> {
+        if !self.is_on() {
+            error!("Trying to receive data, but GSM was off.");
+            return Err(GsmError::PowerOff);
+        }
+
+        let start = Instant::now();
+        loop {
+            self.sockets.update();
+            let link = self.sockets.link(socket);
+            if !link.received.is_empty() {
+                return Ok(link.received.drain(..).collect());
+            }
+            if link.closed {
+                return Err(GsmError::ConnectionClosed(socket.link));
+            }
+
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Ok(Vec::new());
+            }
+            try!(self.poll_urcs(cmp::min(timeout - elapsed,
+                                         Duration::from_millis(SOCKET_POLL_INTERVAL))));
+        }
+    }
+
+    /// Closes a connection, discarding any data not received yet.
+    pub fn close(&mut self, socket: Socket) -> Result<(), GsmError> {
+        self.sockets.update();
+        let closed = self.sockets.link(&socket).closed;
+        self.sockets.free(&socket);
+        if closed {
+            self.logger.log(&format!("Connection in link {} was already closed.", socket.link),
+                            Info);
+            return Ok(());
+        }
+
+        try!(self.send_command_ok(&format!("AT+CIPCLOSE={}", socket.link)));
+        self.logger.log(&format!("Connection in link {} closed.", socket.link), Info);
+        Ok(())
+    }
+
+    /// Brings the IP stack up in multi-connection mode, if it is not up already.
+    fn start_ip_stack(&mut self) -> Result<(), GsmError> {
+        self.sockets.update();
+        if self.sockets.ip_up {
+            return Ok(());
+        }
+        // The connection URCs are collected from here on, until no link is in use.
+        if self.sockets.urcs.is_none() {
+            self.sockets.urcs = Some(self.reader.subscribe());
+        }
+
+        self.logger.log(&format!("Bringing the IP stack up with APN {}…", self.gprs.get_apn()),
+                        Info);
+        // Multiple connections can only be enabled in the initial state of the IP stack.
+        try!(self.send_command_ok("AT+CIPSHUT"));
+        try!(self.send_command_ok("AT+CIPMUX=1"));
+        try!(self.send_command_ok("AT+CGATT=1"));
+        let command = format!("AT+CSTT=\"{}\",\"{}\",\"{}\"",
+                              self.gprs.get_apn(),
+                              self.gprs.get_user().unwrap_or(""),
+                              self.gprs.get_password().unwrap_or(""));
+        // The password is kept out of the logs.
+        let masked = format!("AT+CSTT=\"{}\",\"{}\",\"****\"",
+                             self.gprs.get_apn(),
+                             self.gprs.get_user().unwrap_or(""));
+        try!(self.send_command_ok_masked(&command, &masked));
+        try!(self.send_command_ok("AT+CIICR"));
+
+        let response = try!(self.send_command_ok("AT+CIFSREX"));
+        let address = response.information("+CIFSREX:")
+            .and_then(|line| line.strip_prefix("+CIFSREX:"))
+            .map_or("unknown", |address| address.trim());
+        self.logger.log(&format!("IP stack up with address {}.", address), Info);
+
+        self.sockets.ip_up = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, UdpSocket};
+    use std::time::Duration;
+
+    use super::*;
+    use super::super::emulator::Emulator;
+
+    /// Receives all the data sent through a connection until it is closed.
+    fn receive_all<T: Transport>(gsm: &mut Gsm<T>, socket: &Socket) -> Vec<u8> {
+        let mut received = Vec::new();
+        loop {
+            match gsm.receive(socket, Duration::from_secs(5)) {
+                Ok(data) => {
+                    assert!(!data.is_empty(), "connection not closed");
+                    received.extend(data);
+                }
+                Err(GsmError::ConnectionClosed(link)) => {
+                    assert_eq!(link, socket.get_link());
+                    return received;
+                }
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(b"pong").unwrap();
+            request
+        });
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        assert!(gsm.sockets.urcs.is_none());
+
+        let socket = gsm.connect(Protocol::Tcp, "127.0.0.1", port).unwrap();
+        assert_eq!(socket.get_protocol(), Protocol::Tcp);
+        assert!(gsm.sockets.urcs.is_some());
+        gsm.send(&socket, b"ping").unwrap();
+        assert_eq!(&server.join().unwrap(), b"ping");
+
+        // The server closes the connection after answering.
+        assert_eq!(receive_all(&mut gsm, &socket), b"pong");
+        match gsm.send(&socket, b"ping") {
+            Err(GsmError::ConnectionClosed(0)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        gsm.close(socket).unwrap();
+        assert!(gsm.sockets.urcs.is_none());
+    }
+
+    #[test]
+    fn udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let port = server.local_addr().unwrap().port();
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+
+        let first = gsm.connect(Protocol::Udp, "127.0.0.1", port).unwrap();
+        let second = gsm.connect(Protocol::Udp, "127.0.0.1", port).unwrap();
+        assert_eq!((first.get_link(), second.get_link()), (0, 1));
+        gsm.send(&second, b"packet").unwrap();
+        let mut buf = [0; 16];
+        let (length, client) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..length], b"packet");
+
+        server.send_to(b"reply", client).unwrap();
+        assert_eq!(gsm.receive(&second, Duration::from_secs(5)).unwrap(), b"reply");
+        assert!(gsm.receive(&first, Duration::from_millis(200)).unwrap().is_empty());
+
+        // The URCs are collected until the last connection is closed.
+        gsm.close(second).unwrap();
+        assert!(gsm.sockets.urcs.is_some());
+        gsm.close(first).unwrap();
+        assert!(gsm.sockets.urcs.is_none());
+    }
+
+    #[test]
+    fn send_fail() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut emulator = Emulator::new();
+        emulator.add_dropout(Duration::from_millis(500), Duration::from_secs(60));
+        let mut gsm = Gsm::simulated(emulator, "");
+
+        let socket = gsm.connect(Protocol::Tcp, "127.0.0.1", port).unwrap();
+        thread::sleep(Duration::from_millis(500));
+        match gsm.send(&socket, b"ping") {
+            Err(GsmError::UnexpectedResponse { ref response, .. }) => {
+                assert_eq!(response, "SEND FAIL")
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        gsm.close(socket).unwrap();
+    }
+
+    #[test]
+    fn connect_fail() {
+        // Nothing listens on the port once the listener is dropped.
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+
+        match gsm.connect(Protocol::Tcp, "127.0.0.1", port) {
+            Err(GsmError::UnexpectedResponse { ref response, .. }) => {
+                assert_eq!(response, "CONNECT FAIL")
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(gsm.sockets.links.iter().all(|link| !link.in_use));
+        assert!(gsm.sockets.urcs.is_none());
+    }
+}
@@ -0,0 +1,508 @@
+//! Serial transports for the GSM modem.
+//!
+//! The modem logic only needs to read lines, write bytes and control the read timeout, so it is
+//! written against the `Transport` trait. This allows running it against the real TTY, a Linux
+//! pseudo-terminal or an in-memory scripted stream.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+#[cfg(test)]
+use std::collections::VecDeque;
+#[cfg(any(test, feature = "real-sim"))]
+use std::{cmp, mem};
+#[cfg(any(test, feature = "real-sim"))]
+use std::ffi::CStr;
+#[cfg(any(test, feature = "real-sim"))]
+use std::fs::File;
+#[cfg(any(test, feature = "real-sim"))]
+use std::os::unix::io::{AsRawFd, FromRawFd};
+#[cfg(any(test, feature = "real-sim"))]
+use std::path::PathBuf;
+#[cfg(any(test, feature = "real-sim"))]
+use std::time::Instant;
+
+#[cfg(any(test, feature = "real-sim"))]
+use libc;
+use serial::{self, PortSettings, SerialPort};
+use serial::posix::TTYPort;
+
+/// Byte stream used to talk to the modem.
+pub trait Transport {
+    /// Reads a line into `buf`, including the line terminator if one was received.
+    ///
+    /// Returns the number of bytes read, which will be 0 if nothing was received before the
+    /// timeout. A partial line (such as the `> ` SMS prompt) is returned as is when the timeout
+    /// expires.
+    fn read_line(&mut self, buf: &mut String) -> Result<usize, io::Error>;
+
+    /// Reads at most `len` bytes into `buf`, without looking for line terminators, so that
+    /// binary data can be received.
+    ///
+    /// Returns the number of bytes read, which will be 0 if nothing was received before the
+    /// timeout.
+    fn read_bytes(&mut self, buf: &mut Vec<u8>, len: usize) -> Result<usize, io::Error>;
+
+    /// Writes all the given bytes to the modem.
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), io::Error>;
+
+    /// Flushes any pending output.
+    fn flush(&mut self) -> Result<(), io::Error>;
+
+    /// Sets the maximum time a `read_line()` call will wait for data.
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), io::Error>;
+}
+
+/// Transport for a real serial TTY, such as the one the SIM800 is connected to.
+pub struct TtyTransport {
+    port: BufReader<TTYPort>,
+}
+
+impl TtyTransport {
+    /// Opens the TTY at the given path.
+    pub fn open<P: AsRef<Path> + ?Sized>(path: &P) -> Result<TtyTransport, io::Error> {
+        Ok(TtyTransport { port: BufReader::new(try!(TTYPort::open(path.as_ref()))) })
+    }
+
+    /// Applies the given baud rate, data bits, parity, stop bits and flow control to the TTY.
+    pub fn configure(&mut self, settings: &PortSettings) -> Result<(), io::Error> {
+        let settings = *settings;
+        self.port
+            .get_mut()
+            .reconfigure(&|s| {
+                try!(s.set_baud_rate(settings.baud_rate));
+                s.set_char_size(settings.char_size);
+                s.set_parity(settings.parity);
+                s.set_stop_bits(settings.stop_bits);
+                s.set_flow_control(settings.flow_control);
+                Ok(())
+            })
+            .map_err(|e: serial::Error| io::Error::from(e))
+    }
+}
+
+impl Transport for TtyTransport {
+    fn read_line(&mut self, buf: &mut String) -> Result<usize, io::Error> {
+        let start_len = buf.len();
+        match self.port.read_line(buf) {
+            Ok(read) => Ok(read),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => Ok(buf.len() - start_len),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read_bytes(&mut self, buf: &mut Vec<u8>, len: usize) -> Result<usize, io::Error> {
+        let mut chunk = vec![0u8; len];
+        match self.port.read(&mut chunk) {
+            Ok(read) => {
+                buf.extend_from_slice(&chunk[..read]);
+                Ok(read)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        self.port.get_mut().write_all(bytes)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.port.get_mut().flush()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), io::Error> {
+        self.port.get_mut().set_timeout(timeout).map_err(io::Error::from)
+    }
+}
+
+/// Transport for the master side of a Linux pseudo-terminal pair.
+///
+/// The slave side behaves like a regular serial port, so a modem emulator or a terminal program
+/// can be attached to it while the GSM logic runs on the master side.
+#[cfg(any(test, feature = "real-sim"))]
+pub struct PtyTransport {
+    master: File,
+    slave_path: PathBuf,
+    pending: Vec<u8>,
+    timeout: Duration,
+}
+
+#[cfg(any(test, feature = "real-sim"))]
+impl PtyTransport {
+    /// Creates a new pseudo-terminal pair.
+    pub fn open() -> Result<PtyTransport, io::Error> {
+        let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The file takes ownership of the descriptor, so it will be closed on every error path.
+        let master = unsafe { File::from_raw_fd(fd) };
+
+        if unsafe { libc::grantpt(fd) } != 0 || unsafe { libc::unlockpt(fd) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Raw mode, so that the line discipline does not echo or translate anything.
+        let mut termios: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe { libc::cfmakeraw(&mut termios) };
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut name = [0 as libc::c_char; 128];
+        if unsafe { libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let slave_path = match unsafe { CStr::from_ptr(name.as_ptr()) }.to_str() {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "invalid pseudo-terminal slave name"))
+            }
+        };
+
+        Ok(PtyTransport {
+            master: master,
+            slave_path: slave_path,
+            pending: Vec::new(),
+            timeout: Duration::from_millis(100),
+        })
+    }
+
+    /// Gets the path of the slave side of the pseudo-terminal.
+    pub fn slave_path(&self) -> &Path {
+        self.slave_path.as_path()
+    }
+
+    /// Opens the slave side of the pseudo-terminal as a serial port.
+    pub fn open_slave(&self) -> Result<TtyTransport, io::Error> {
+        TtyTransport::open(self.slave_path())
+    }
+
+    /// Waits until the master side is readable or the given time has passed.
+    fn wait_readable(&self, timeout: Duration) -> Result<bool, io::Error> {
+        let mut fds = libc::pollfd {
+            fd: self.master.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = timeout.as_secs() * 1000 + timeout.subsec_millis() as u64;
+
+        match unsafe { libc::poll(&mut fds, 1, millis as libc::c_int) } {
+            n if n < 0 => Err(io::Error::last_os_error()),
+            0 => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    /// Reads the bytes available in the master side into the pending buffer.
+    fn read_pending(&mut self) -> Result<(), io::Error> {
+        let mut chunk = [0u8; 256];
+        match self.master.read(&mut chunk) {
+            Ok(read) => self.pending.extend_from_slice(&chunk[..read]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(any(test, feature = "real-sim"))]
+impl Transport for PtyTransport {
+    fn read_line(&mut self, buf: &mut String) -> Result<usize, io::Error> {
+        let start = Instant::now();
+        loop {
+            if let Some(pos) = self.pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.pending.drain(..pos + 1).collect();
+                return append_bytes(buf, line);
+            }
+
+            let elapsed = start.elapsed();
+            if elapsed >= self.timeout || !try!(self.wait_readable(self.timeout - elapsed)) {
+                let line: Vec<u8> = self.pending.drain(..).collect();
+                return append_bytes(buf, line);
+            }
+
+            try!(self.read_pending());
+        }
+    }
+
+    fn read_bytes(&mut self, buf: &mut Vec<u8>, len: usize) -> Result<usize, io::Error> {
+        if self.pending.is_empty() && try!(self.wait_readable(self.timeout)) {
+            try!(self.read_pending());
+        }
+
+        let read = cmp::min(len, self.pending.len());
+        buf.extend(self.pending.drain(..read));
+        Ok(read)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        self.master.write_all(bytes)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.master.flush()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), io::Error> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+/// In-memory transport that answers commands from a script.
+///
+/// Each expected command has a list of response lines that will be returned by `read_line()`
+/// once the command has been written. Commands that do not match the script are answered with
+/// `ERROR`. Unsolicited lines can be injected at any time with `push_line()`, and binary data with
+/// `push_data()`.
+#[cfg(test)]
+pub struct ScriptedTransport {
+    script: VecDeque<(String, Vec<String>)>,
+    incoming: VecDeque<String>,
+    incoming_data: VecDeque<u8>,
+    outgoing: Vec<u8>,
+    written: Vec<String>,
+}
+
+#[cfg(test)]
+impl ScriptedTransport {
+    /// Creates an empty scripted transport.
+    pub fn new() -> ScriptedTransport {
+        ScriptedTransport {
+            script: VecDeque::new(),
+            incoming: VecDeque::new(),
+            incoming_data: VecDeque::new(),
+            outgoing: Vec::new(),
+            written: Vec::new(),
+        }
+    }
+
+    /// Adds an expected command and the lines the modem will answer with.
+    pub fn expect<S: Into<String>>(&mut self, command: S, response: &[&str]) -> &mut Self {
+        self.script
+            .push_back((command.into(), response.iter().map(|l| l.to_string()).collect()));
+        self
+    }
+
+    /// Queues a line to be read, as if the modem had sent it on its own.
+    pub fn push_line<S: Into<String>>(&mut self, line: S) -> &mut Self {
+        self.incoming.push_back(line.into());
+        self
+    }
+
+    /// Queues binary data to be read with `read_bytes()`, as if the modem had sent it.
+    pub fn push_data(&mut self, data: &[u8]) -> &mut Self {
+        self.incoming_data.extend(data);
+        self
+    }
+
+    /// Gets all the lines written to the transport so far.
+    pub fn written(&self) -> &[String] {
+        &self.written
+    }
+
+    /// Checks whether every scripted command has been sent.
+    pub fn is_finished(&self) -> bool {
+        self.script.is_empty()
+    }
+
+    /// Handles a complete line written to the transport.
+    fn handle_line(&mut self, line: String) {
+        let response = match self.script.pop_front() {
+            Some((ref command, ref response)) if *command == line => response.clone(),
+            Some(entry) => {
+                self.script.push_front(entry);
+                vec![String::from("ERROR")]
+            }
+            None => vec![String::from("ERROR")],
+        };
+        self.written.push(line);
+        for response_line in response {
+            self.incoming.push_back(response_line);
+        }
+    }
+}
+
+#[cfg(test)]
+impl Transport for ScriptedTransport {
+    fn read_line(&mut self, buf: &mut String) -> Result<usize, io::Error> {
+        match self.incoming.pop_front() {
+            Some(line) => {
+                // The prompt is the only line the modem does not terminate.
+                if line != "> " {
+                    buf.push_str(&line);
+                    buf.push_str("\r\n");
+                    Ok(line.len() + 2)
+                } else {
+                    buf.push_str(&line);
+                    Ok(line.len())
+                }
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn read_bytes(&mut self, buf: &mut Vec<u8>, len: usize) -> Result<usize, io::Error> {
+        let read = cmp::min(len, self.incoming_data.len());
+        buf.extend(self.incoming_data.drain(..read));
+        Ok(read)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), io::Error> {
+        for &byte in bytes {
+            // Lines end with a carriage return, a line feed or the Ctrl+Z of an SMS body.
+            if byte == b'\r' || byte == b'\n' || byte == 0x1A {
+                if byte == 0x1A {
+                    self.outgoing.push(byte);
+                }
+                if !self.outgoing.is_empty() {
+                    let line = String::from_utf8_lossy(&self.outgoing).into_owned();
+                    self.outgoing.clear();
+                    self.handle_line(line);
+                }
+            } else {
+                self.outgoing.push(byte);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, _timeout: Duration) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+/// Appends the given bytes to the string buffer, returning the number of bytes appended.
+#[cfg(any(test, feature = "real-sim"))]
+fn append_bytes(buf: &mut String, bytes: Vec<u8>) -> Result<usize, io::Error> {
+    let len = bytes.len();
+    match String::from_utf8(bytes) {
+        Ok(s) => {
+            buf.push_str(&s);
+            Ok(len)
+        }
+        Err(_) => {
+            Err(io::Error::new(io::ErrorKind::InvalidData,
+                               "stream did not contain valid UTF-8"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn scripted_answers_in_order() {
+        let mut transport = ScriptedTransport::new();
+        transport.expect("AT+CBC", &["+CBC: 0,50,3950", "OK"]).expect("AT+CSQ", &["OK"]);
+        let mut line = String::new();
+
+        transport.write_bytes(b"AT+CSQ\r").unwrap();
+        assert_eq!(transport.read_line(&mut line).unwrap(), 7);
+        assert_eq!(line, "ERROR\r\n");
+        assert!(!transport.is_finished());
+
+        line.clear();
+        transport.write_bytes(b"AT+").unwrap();
+        assert_eq!(transport.read_line(&mut line).unwrap(), 0);
+        transport.write_bytes(b"CBC\r").unwrap();
+        transport.read_line(&mut line).unwrap();
+        transport.read_line(&mut line).unwrap();
+        assert_eq!(line, "+CBC: 0,50,3950\r\nOK\r\n");
+        transport.write_bytes(b"AT+CSQ\n").unwrap();
+        assert!(transport.is_finished());
+        assert_eq!(transport.written(), &["AT+CSQ", "AT+CBC", "AT+CSQ"]);
+    }
+
+    #[test]
+    fn scripted_prompt_and_sms_body() {
+        let mut transport = ScriptedTransport::new();
+        transport.expect("AT+CMGS=23", &["> "]).expect("0011\u{1A}", &["+CMGS: 1", "OK"]);
+        let mut line = String::new();
+
+        transport.write_bytes(b"AT+CMGS=23\r").unwrap();
+        assert_eq!(transport.read_line(&mut line).unwrap(), 2);
+        assert_eq!(line, "> ");
+        transport.write_bytes(b"0011\x1A").unwrap();
+        assert!(transport.is_finished());
+    }
+
+    #[test]
+    fn scripted_unsolicited_data() {
+        let mut transport = ScriptedTransport::new();
+        transport.push_line("+RECEIVE,0,5").push_data(b"ab\r\ncd");
+        let mut line = String::new();
+        let mut data = Vec::new();
+
+        transport.read_line(&mut line).unwrap();
+        assert_eq!(line, "+RECEIVE,0,5\r\n");
+        assert_eq!(transport.read_bytes(&mut data, 5).unwrap(), 5);
+        assert_eq!(transport.read_bytes(&mut data, 5).unwrap(), 1);
+        assert_eq!(transport.read_bytes(&mut data, 5).unwrap(), 0);
+        assert_eq!(data, b"ab\r\ncd");
+    }
+
+    #[test]
+    fn pty_pair() {
+        let mut master = PtyTransport::open().unwrap();
+        let mut slave = master.open_slave().unwrap();
+        master.set_timeout(Duration::from_millis(200)).unwrap();
+        slave.set_timeout(Duration::from_millis(200)).unwrap();
+
+        master.write_bytes(b"AT+CBC\r\n").unwrap();
+        master.flush().unwrap();
+        let mut line = String::new();
+        slave.read_line(&mut line).unwrap();
+        assert_eq!(line, "AT+CBC\r\n");
+
+        // Complete lines are returned at once, and partial ones when the timeout expires.
+        slave.write_bytes(b"+CBC: 0,50,3950\r\nOK\r\n> ").unwrap();
+        slave.flush().unwrap();
+        let mut lines = Vec::new();
+        for _ in 0..3 {
+            let mut line = String::new();
+            master.read_line(&mut line).unwrap();
+            lines.push(line);
+        }
+        assert_eq!(lines, ["+CBC: 0,50,3950\r\n", "OK\r\n", "> "]);
+
+        let start = Instant::now();
+        line.clear();
+        assert_eq!(master.read_line(&mut line).unwrap(), 0);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+
+        slave.write_bytes(&[0x00, 0xFF, b'\n']).unwrap();
+        slave.flush().unwrap();
+        let mut data = Vec::new();
+        while data.len() < 3 {
+            master.read_bytes(&mut data, 3).unwrap();
+        }
+        assert_eq!(data, [0x00, 0xFF, b'\n']);
+    }
+
+    #[test]
+    fn pty_invalid_utf8_line() {
+        let mut master = PtyTransport::open().unwrap();
+        let mut slave = master.open_slave().unwrap();
+
+        slave.write_bytes(&[0xFF, b'\n']).unwrap();
+        slave.flush().unwrap();
+        let mut line = String::new();
+        let error = master.read_line(&mut line).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
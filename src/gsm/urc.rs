@@ -0,0 +1,329 @@
+//! Unsolicited result codes.
+//!
+//! The modem can send unsolicited result codes (URCs) at any time, even in the middle of the
+//! response to a command. The response reader hands every line to the `UrcDispatcher` first, which
+//! recognises URCs and delivers them to all subscribers through a channel.
+
+use std::sync::mpsc::{self, Sender, Receiver};
+
+/// Unsolicited result code sent by the modem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Urc {
+    /// `+CMTI: <mem>,<index>`: a new SMS was stored in the given memory and index.
+    NewMessage { storage: String, index: u32 },
+    /// `RING`: incoming call.
+    Ring,
+    /// `+CLIP: <number>,<type>,…`: caller ID of an incoming call, sent after each `RING`.
+    CallerId(String),
+    /// `BUSY`: the called number is busy, or rejected the call. Call results are the final result
+    /// code of `ATD` instead if the call could not be placed.
+    Busy,
+    /// `NO ANSWER`: the called number did not answer.
+    NoAnswer,
+    /// `NO CARRIER`: the call could not be set up, or it ended.
+    NoCarrier,
+    /// `NO DIALTONE`: there is no network to place the call.
+    NoDialTone,
+    /// `+CDS`: SMS status report, with the text mode fields or the PDU in hexadecimal.
+    StatusReport(String),
+    /// `+HTTPACTION: <method>,<status>,<length>`: an HTTP request finished with the given status
+    /// code and response length.
+    HttpAction { method: u8, status: u16, length: usize },
+    /// `<n>, CONNECT OK` or `<n>, CONNECT FAIL`: result of opening the connection in a link.
+    SocketConnect { link: u8, connected: bool },
+    /// `<n>, SEND OK` or `<n>, SEND FAIL`: result of sending data through a connection.
+    SocketSend { link: u8, sent: bool },
+    /// `<n>, CLOSED`: the connection in a link was closed by the remote side or the network.
+    SocketClosed(u8),
+    /// `+RECEIVE,<n>,<length>:` followed by the data received through a connection.
+    SocketData { link: u8, data: Vec<u8> },
+    /// `+PDP: DEACT`: the network deactivated the GPRS context, closing all the connections.
+    PdpDeactivated,
+    /// `UNDER-VOLTAGE WARNNING`: the supply voltage is getting too low.
+    UnderVoltageWarning,
+    /// `UNDER-VOLTAGE POWER DOWN`: the modem is powering down because of a low supply voltage.
+    UnderVoltagePowerDown,
+    /// `OVER-VOLTAGE WARNNING`: the supply voltage is getting too high.
+    OverVoltageWarning,
+    /// `OVER-VOLTAGE POWER DOWN`: the modem is powering down because of a high supply voltage.
+    OverVoltagePowerDown,
+    /// `NORMAL POWER DOWN`: the modem is powering down after a power key pulse or `AT+CPOWD`.
+    NormalPowerDown,
+    /// `Call Ready`: the modem is ready to place and receive calls.
+    CallReady,
+    /// `SMS Ready`: the modem is ready to send and receive SMS.
+    SmsReady,
+}
+
+/// Dispatcher of unsolicited result codes to subscribers.
+pub struct UrcDispatcher {
+    subscribers: Vec<Sender<Urc>>,
+    pending_pdu: bool,
+    pending_data: Option<(u8, usize)>,
+}
+
+impl UrcDispatcher {
+    /// Creates a dispatcher without subscribers.
+    pub fn new() -> UrcDispatcher {
+        UrcDispatcher {
+            subscribers: Vec::new(),
+            pending_pdu: false,
+            pending_data: None,
+        }
+    }
+
+    /// Subscribes to all the URCs received from now on.
+    pub fn subscribe(&mut self) -> Receiver<Urc> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Gets the length of the data of a `+RECEIVE` URC still to be read, if any.
+    ///
+    /// The data can contain any byte, so it must be read without looking for line terminators
+    /// and handed to `handle_data()`.
+    pub fn pending_data(&self) -> Option<usize> {
+        self.pending_data.map(|(_, length)| length)
+    }
+
+    /// Handles the data of a `+RECEIVE` URC.
+    pub fn handle_data(&mut self, data: Vec<u8>) {
+        if let Some((link, _)) = self.pending_data.take() {
+            self.dispatch(Urc::SocketData {
+                link: link,
+                data: data,
+            });
+        }
+    }
+
+    /// Handles a line received from the modem.
+    ///
+    /// Returns `true` if the line was part of a URC, in which case it should not be considered
+    /// part of a command response.
+    pub fn handle_line(&mut self, line: &str) -> bool {
+        if self.pending_pdu {
+            self.pending_pdu = false;
+            self.dispatch(Urc::StatusReport(line.to_owned()));
+            return true;
+        }
+
+        let urc = match line {
+            "RING" => Urc::Ring,
+            "BUSY" => Urc::Busy,
+            "NO ANSWER" => Urc::NoAnswer,
+            "NO CARRIER" => Urc::NoCarrier,
+            "NO DIALTONE" => Urc::NoDialTone,
+            "UNDER-VOLTAGE WARNNING" => Urc::UnderVoltageWarning,
+            "UNDER-VOLTAGE POWER DOWN" => Urc::UnderVoltagePowerDown,
+            "OVER-VOLTAGE WARNNING" => Urc::OverVoltageWarning,
+            "OVER-VOLTAGE POWER DOWN" => Urc::OverVoltagePowerDown,
+            "NORMAL POWER DOWN" => Urc::NormalPowerDown,
+            "Call Ready" => Urc::CallReady,
+            "SMS Ready" => Urc::SmsReady,
+            "+PDP: DEACT" => Urc::PdpDeactivated,
+            _ => {
+                if let Some(rest) = line.strip_prefix("+CMTI:") {
+                    let mut fields = rest.split(',');
+                    let storage = fields.next().unwrap_or("").trim().trim_matches('"');
+                    match fields.next().and_then(|i| i.trim().parse().ok()) {
+                        Some(index) => {
+                            Urc::NewMessage {
+                                storage: storage.to_owned(),
+                                index: index,
+                            }
+                        }
+                        None => return false,
+                    }
+                } else if let Some(rest) = line.strip_prefix("+CDS:") {
+                    // In PDU mode only the PDU length is sent, and the PDU comes in the next line.
+                    if !rest.contains(',') {
+                        self.pending_pdu = true;
+                        return true;
+                    }
+                    Urc::StatusReport(rest.trim().to_owned())
+                } else if let Some(rest) = line.strip_prefix("+HTTPACTION:") {
+                    let mut fields = rest.split(',').map(|f| f.trim());
+                    match (fields.next().and_then(|m| m.parse().ok()),
+                           fields.next().and_then(|s| s.parse().ok()),
+                           fields.next().and_then(|l| l.parse().ok())) {
+                        (Some(method), Some(status), Some(length)) => {
+                            Urc::HttpAction {
+                                method: method,
+                                status: status,
+                                length: length,
+                            }
+                        }
+                        _ => return false,
+                    }
+                } else if let Some(rest) = line.strip_prefix("+CLIP:") {
+                    let number = rest.split(',').next().unwrap_or("").trim().trim_matches('"');
+                    Urc::CallerId(number.to_owned())
+                } else if let Some(rest) = line.strip_prefix("+RECEIVE,") {
+                    let mut fields = rest.trim_end_matches(':').split(',');
+                    match (fields.next().and_then(|n| n.parse().ok()),
+                           fields.next().and_then(|l| l.parse().ok())) {
+                        (Some(link), Some(length)) => {
+                            self.pending_data = Some((link, length));
+                            return true;
+                        }
+                        _ => return false,
+                    }
+                } else {
+                    match socket_urc(line) {
+                        Some(urc) => urc,
+                        None => return false,
+                    }
+                }
+            }
+        };
+
+        self.dispatch(urc);
+        true
+    }
+
+    /// Sends the URC to all subscribers, forgetting the ones that are gone.
+    fn dispatch(&mut self, urc: Urc) {
+        self.subscribers.retain(|s| s.send(urc.clone()).is_ok());
+    }
+}
+
+/// Parses the `<n>, <result>` URCs of the connections of the IP stack.
+fn socket_urc(line: &str) -> Option<Urc> {
+    let mut parts = line.splitn(2, ", ");
+    let link = parts.next().and_then(|n| n.parse::<u8>().ok());
+
+    match (link, parts.next()) {
+        (Some(link), Some("CONNECT OK")) |
+        (Some(link), Some("ALREADY CONNECT")) => {
+            Some(Urc::SocketConnect {
+                link: link,
+                connected: true,
+            })
+        }
+        (Some(link), Some("CONNECT FAIL")) => {
+            Some(Urc::SocketConnect {
+                link: link,
+                connected: false,
+            })
+        }
+        (Some(link), Some("SEND OK")) => {
+            Some(Urc::SocketSend {
+                link: link,
+                sent: true,
+            })
+        }
+        (Some(link), Some("SEND FAIL")) => {
+            Some(Urc::SocketSend {
+                link: link,
+                sent: false,
+            })
+        }
+        (Some(link), Some("CLOSED")) => Some(Urc::SocketClosed(link)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_single_line_urcs() {
+        let mut dispatcher = UrcDispatcher::new();
+        let urcs = dispatcher.subscribe();
+
+        for line in &["RING",
+                      "+CLIP: \"+34600000001\",145,\"\",0,\"\",0",
+                      "+CMTI: \"SM\",12",
+                      "+HTTPACTION: 1,200,17",
+                      "0, CONNECT OK",
+                      "1, SEND FAIL",
+                      "2, CLOSED",
+                      "UNDER-VOLTAGE WARNNING"] {
+            assert!(dispatcher.handle_line(line), "'{}' is not a URC", line);
+        }
+        let received: Vec<Urc> = urcs.try_iter().collect();
+        assert_eq!(received,
+                   vec![Urc::Ring,
+                        Urc::CallerId(String::from("+34600000001")),
+                        Urc::NewMessage {
+                            storage: String::from("SM"),
+                            index: 12,
+                        },
+                        Urc::HttpAction {
+                            method: 1,
+                            status: 200,
+                            length: 17,
+                        },
+                        Urc::SocketConnect {
+                            link: 0,
+                            connected: true,
+                        },
+                        Urc::SocketSend {
+                            link: 1,
+                            sent: false,
+                        },
+                        Urc::SocketClosed(2),
+                        Urc::UnderVoltageWarning]);
+    }
+
+    #[test]
+    fn it_ignores_response_lines() {
+        let mut dispatcher = UrcDispatcher::new();
+        let urcs = dispatcher.subscribe();
+
+        for line in &["OK", "+CSQ: 18,0", "+CMTI: \"SM\"", "+HTTPACTION: 1,200", "0, SHUT OK"] {
+            assert!(!dispatcher.handle_line(line), "'{}' is a URC", line);
+        }
+        assert!(urcs.try_recv().is_err());
+    }
+
+    #[test]
+    fn it_reads_status_report_pdus_in_two_lines() {
+        let mut dispatcher = UrcDispatcher::new();
+        let urcs = dispatcher.subscribe();
+        let pdu = "0006D60B914306000000F1610101000000406101010000004000";
+
+        assert!(dispatcher.handle_line("+CDS: 25"));
+        assert!(urcs.try_recv().is_err());
+        // The PDU line is taken as part of the URC, whatever it looks like.
+        assert!(dispatcher.handle_line(pdu));
+        assert_eq!(urcs.try_recv().unwrap(), Urc::StatusReport(pdu.to_owned()));
+        assert!(!dispatcher.handle_line("OK"));
+
+        // In text mode the report comes in a single line.
+        assert!(dispatcher.handle_line("+CDS: 6,12,\"+34600000001\",145,\"…\",\"…\",0"));
+        assert_eq!(urcs.try_recv().unwrap(),
+                   Urc::StatusReport(String::from("6,12,\"+34600000001\",145,\"…\",\"…\",0")));
+    }
+
+    #[test]
+    fn it_reads_received_data() {
+        let mut dispatcher = UrcDispatcher::new();
+        let urcs = dispatcher.subscribe();
+
+        assert!(dispatcher.handle_line("+RECEIVE,1,4:"));
+        assert_eq!(dispatcher.pending_data(), Some(4));
+        assert!(urcs.try_recv().is_err());
+
+        dispatcher.handle_data(vec![0x00, b'\r', b'\n', 0xFF]);
+        assert_eq!(dispatcher.pending_data(), None);
+        assert_eq!(urcs.try_recv().unwrap(),
+                   Urc::SocketData {
+                       link: 1,
+                       data: vec![0x00, b'\r', b'\n', 0xFF],
+                   });
+    }
+
+    #[test]
+    fn it_forgets_gone_subscribers() {
+        let mut dispatcher = UrcDispatcher::new();
+        let kept = dispatcher.subscribe();
+        drop(dispatcher.subscribe());
+
+        assert!(dispatcher.handle_line("RING"));
+        assert_eq!(dispatcher.subscribers.len(), 1);
+        assert_eq!(kept.try_recv().unwrap(), Urc::Ring);
+    }
+}
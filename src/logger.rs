@@ -3,9 +3,10 @@ use std::path::PathBuf;
 use std::io::{Write, Error};
 use std::ffi::OsStr;
 
-use time;
 use log;
 
+use clock;
+
 pub struct Logger {
     file: File,
     prefix: &'static str,
@@ -19,14 +20,29 @@ impl Logger {
         let mut pathbuf = PathBuf::from(path);
         pathbuf.set_file_name(format!("{}.{}",
                                       filename,
-                                      time::now_utc()
+                                      clock::now_utc()
                                           .strftime("%F.%H-%M-%S")
                                           .unwrap()));
         pathbuf.set_extension(".log");
         let path = pathbuf.as_path();
 
+        let mut file = try!(File::create(path));
+        try!(file.write_all(format!("[{}] Log started at {}, time source: {}.\n",
+                                    prefix,
+                                    clock::now_utc().rfc3339(),
+                                    clock::source())
+            .as_bytes()));
+
+        Ok(Logger {
+            file: file,
+            prefix: prefix,
+        })
+    }
+
+    /// Creates another logger writing to the same file, with the given prefix.
+    pub fn try_clone(&self, prefix: &'static str) -> Result<Logger, Error> {
         Ok(Logger {
-            file: try!(File::create(path)),
+            file: try!(self.file.try_clone()),
             prefix: prefix,
         })
     }
@@ -35,7 +51,7 @@ impl Logger {
         let log_message = format!("[{}][{}] - {} - {}",
                                   self.prefix,
                                   level,
-                                  time::now_utc()
+                                  clock::now_utc()
                                       .strftime("%D %T.%f")
                                       .unwrap(),
                                   message);
@@ -0,0 +1,149 @@
+//! Landing report.
+//!
+//! Once the probe lands, the ground team gets an SMS with its position and the cells around it.
+//! The position comes from the cell location service, which needs GPRS, so the serving and
+//! strongest neighbour cells are always included, to be looked up in public cell databases if
+//! the position is not available.
+//!
+//! Until the report is delivered to some ground team phone, as told by the SMS status reports,
+//! the ground team numbers are called as an alarm: a short call attempt often gets through where
+//! an SMS does not.
+
+use std::io;
+use std::time::Duration;
+
+use gsm::{Gsm, Transport};
+use gsm::delivery::DeliveryState;
+use gsm::outbox::{Outbox, Priority, MessageKind};
+
+/// Start of the landing report, to tell it apart from other position reports.
+const LANDING_REPORT_PREFIX: &'static str = "Landed.";
+/// Maximum number of cells in the landing report, to keep it short.
+const LANDING_REPORT_CELLS: usize = 4;
+/// Time the landing report can stay unsent before calling the ground team, in seconds.
+pub const LANDING_ALARM_DELAY: u64 = 10 * 60;
+/// Time each ground team number is left ringing by the landing alarm, in seconds.
+const LANDING_ALARM_RING_TIME: u64 = 20;
+/// Time to wait for network registration before building the landing report, in seconds.
+const LANDING_REGISTRATION_TIMEOUT: u64 = 3 * 60;
+
+/// Queues the landing report for all the given ground team numbers, returning its text.
+///
+/// The modem is given some time to register in the network first, since the coverage at the
+/// landing site is often marginal. If it does not register, the report is queued anyway, to be
+/// sent once it does.
+pub fn send_landing_report<T: Transport>(gsm: &mut Gsm<T>,
+                                         outbox: &mut Outbox,
+                                         numbers: &[String])
+                                         -> Result<String, io::Error> {
+    let timeout = Duration::from_secs(LANDING_REGISTRATION_TIMEOUT);
+    match gsm.wait_for_registration(timeout, None) {
+        Ok(_) => info!("Registered in the network for the landing report."),
+        Err(e) => warn!("Not registered in the network for the landing report: {}", e),
+    }
+
+    let report = landing_report(gsm);
+    info!("Landing report: {}", report);
+    for number in numbers {
+        try!(outbox.push(number.as_str(), report.clone(), Priority::High, MessageKind::Landing));
+    }
+
+    Ok(report)
+}
+
+/// Calls the given ground team numbers unless the landing report was delivered to one of them,
+/// returning `true` once no more alarms are needed.
+///
+/// The delivery state is the one of the last SMS sent to each number, so a message delivered
+/// after the landing report, such as a position report, counts too.
+pub fn escalate_landing_report<T: Transport>(gsm: &mut Gsm<T>,
+                                             outbox: &Outbox,
+                                             numbers: &[String])
+                                             -> bool {
+    let delivered = numbers.iter()
+        .filter(|&number| {
+            let unsent = outbox.pending()
+                .iter()
+                .any(|sms| sms.get_kind() == MessageKind::Landing && sms.get_number() == number);
+            !unsent && gsm.get_delivery_state(number) == Some(DeliveryState::Delivered)
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    if !delivered.is_empty() {
+        info!("Landing report delivered to {}.", delivered.join(", "));
+        return true;
+    }
+
+    warn!("Landing report not delivered yet, calling the ground team…");
+    for number in numbers {
+        match gsm.dial(number, Duration::from_secs(LANDING_ALARM_RING_TIME)) {
+            Ok(outcome) => info!("Landing alarm call to {}: {:?}.", number, outcome),
+            Err(e) => error!("Error placing the landing alarm call to {}: {}", number, e),
+        }
+    }
+
+    false
+}
+
+/// Builds the landing report, such as
+/// `Landed. Lat: 40.416800, Lon: -3.703800. Cells: 214-07 1A2B:00C4 -65dBm, …`.
+///
+/// Cells are given as `<mcc>-<mnc> <lac>:<cell id> <level>`, with the serving cell first.
+fn landing_report<T: Transport>(gsm: &mut Gsm<T>) -> String {
+    let position = match gsm.get_coordinates() {
+        Ok(coordinates) => {
+            format!("Lat: {:.6}, Lon: {:.6}",
+                    coordinates.get_latitude(),
+                    coordinates.get_longitude())
+        }
+        Err(e) => {
+            warn!("Position unavailable for the landing report: {}", e);
+            String::from("Position unavailable")
+        }
+    };
+
+    let cells = match gsm.get_cell_info() {
+        Ok(ref mut cells) if !cells.is_empty() => {
+            // The serving cell stays first, and the strongest neighbours follow.
+            let start = if cells[0].is_serving() { 1 } else { 0 };
+            cells[start..].sort_by_key(|c| -c.get_rx_level_dbm());
+            cells.iter()
+                .take(LANDING_REPORT_CELLS)
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+        Ok(_) => String::from("none"),
+        Err(e) => {
+            warn!("Cells unavailable for the landing report: {}", e);
+            String::from("unavailable")
+        }
+    };
+
+    format!("{} {}. Cells: {}", LANDING_REPORT_PREFIX, position, cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use gsm::Gsm;
+    use gsm::emulator::Emulator;
+    use super::landing_report;
+
+    #[test]
+    fn report_with_cells() {
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        assert_eq!(landing_report(&mut gsm),
+                   "Landed. Lat: 40.416800, Lon: -3.703800. Cells: 214-07 1A2B:00C4 -65dBm, \
+                    214-07 1A2C:0D31 -74dBm, 214-07 1A2B:00C5 -82dBm");
+    }
+
+    #[test]
+    fn report_without_network() {
+        let mut emulator = Emulator::new();
+        emulator.add_dropout(Duration::from_secs(0), Duration::from_secs(60));
+        let mut gsm = Gsm::simulated(emulator, "");
+        assert_eq!(landing_report(&mut gsm), "Landed. Position unavailable. Cells: none");
+    }
+}
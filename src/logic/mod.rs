@@ -1,9 +1,27 @@
-use std::{thread, fs};
+pub mod uplink;
+pub mod telemetry;
+pub mod landing;
+
+use std::{thread, fs, io};
+use std::io::Write;
 use std::sync::{Arc, Mutex};
+use std::path::Path;
+use std::time::Duration;
 
 use {State, threads, wiringpi};
 use utils::*;
-use gsm::Gsm;
+use clock::{self, TimeSource};
+use config::{Config, CONFIG_FILE};
+use gsm;
+use gsm::{Gsm, Transport};
+use gsm::info::ModemInfo;
+use gsm::network::NetworkStatus;
+use gsm::outbox::{Outbox, OUTBOX_DIR};
+
+/// Time to wait for network registration during the self-check, in seconds.
+const SELF_CHECK_REGISTRATION: u64 = 120;
+/// Mission metadata, identifying the software and hardware of the flight.
+const METADATA_FILE: &'static str = "data/metadata.txt";
 
 /// Main logic of OpenStratos
 pub fn main_logic() {
@@ -29,6 +47,15 @@ pub fn main_logic() {
     info!("OpenStratos {}", env!("CARGO_PKG_VERSION"));
     info!("Logging started.");
 
+    let config = match Config::load(CONFIG_FILE) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Could not load the configuration: {}", e);
+            panic!("Could not load the configuration: {}", e);
+        }
+    };
+    info!("Configuration loaded.");
+
     debug!("Starting system thread…");
     let system_state = shared_state.clone();
     let system_thread = thread::spawn(move || {
@@ -41,7 +68,28 @@ pub fn main_logic() {
     // TODO from initialize?
     // TODO better error handling
     let wiring_pi = wiringpi::setup();
-    let shared_gsm = Arc::new(Mutex::new(Gsm::initialize(&wiring_pi).unwrap()));
+    let gsm_serial = config.get_gsm_serial();
+    let gsm_transport = match gsm::open_transport(gsm_serial) {
+        Ok(transport) => transport,
+        Err(e) => {
+            error!("Could not open the {} serial port {}: {}",
+                   gsm_serial.get_device(),
+                   gsm_serial.get_path(),
+                   e);
+            panic!("Could not open the {} serial port: {}", gsm_serial.get_device(), e);
+        }
+    };
+    let shared_gsm = Arc::new(Mutex::new(Gsm::initialize(gsm_transport,
+                                                         &wiring_pi,
+                                                         gsm_serial,
+                                                         config.get_gsm().clone(),
+                                                         config.get_gprs().clone())
+        .unwrap()));
+    if !self_check(&shared_gsm, config.get_gsm().get_iccid()) {
+        warn!("Hardware self-check failed.");
+    }
+    let outbox_logger = shared_gsm.lock().unwrap().open_logger("Outbox").unwrap();
+    let shared_outbox = Arc::new(Mutex::new(Outbox::open(OUTBOX_DIR, outbox_logger).unwrap()));
 
     debug!("Starting battery thread…");
     let battery_state = shared_state.clone();
@@ -51,6 +99,33 @@ pub fn main_logic() {
     });
     debug!("Battery thread started.");
 
+    debug!("Starting uplink thread…");
+    let uplink_state = shared_state.clone();
+    let gsm = shared_gsm.clone();
+    let outbox = shared_outbox.clone();
+    let whitelist = config.get_uplink().get_whitelist().to_vec();
+    let uplink_thread = thread::spawn(move || {
+        threads::uplink(&uplink_state, &gsm, &outbox, &whitelist);
+    });
+    debug!("Uplink thread started.");
+
+    let telemetry_thread = config.get_telemetry().get_url().map(|url| {
+        debug!("Starting telemetry thread…");
+        let telemetry_state = shared_state.clone();
+        let gsm = shared_gsm.clone();
+        let url = url.to_owned();
+        let interval = config.get_telemetry().get_interval();
+        let telemetry_thread = thread::spawn(move || {
+            threads::telemetry(&telemetry_state, &gsm, &url, interval);
+        });
+        debug!("Telemetry thread started.");
+
+        telemetry_thread
+    });
+    if telemetry_thread.is_none() {
+        info!("Telemetry upload disabled.");
+    }
+
     debug!("Starting pictures thread…");
     let picture_state = shared_state.clone();
     let picture_thread = thread::spawn(move || {
@@ -68,6 +143,12 @@ pub fn main_logic() {
     if let Err(e) = picture_thread.join() {
         error!("Picture thread panicked! {:?}", e)
     }
+    if let Some(Err(e)) = telemetry_thread.map(|t| t.join()) {
+        error!("Telemetry thread panicked! {:?}", e)
+    }
+    if let Err(e) = uplink_thread.join() {
+        error!("Uplink thread panicked! {:?}", e)
+    }
     if let Err(e) = battery_thread.join() {
         error!("Battery thread panicked! {:?}", e)
     }
@@ -79,8 +160,129 @@ pub fn main_logic() {
     // TODO shut_down(&logger);
 }
 
+/// Checks the hardware before the flight, logging the result of each check.
+fn self_check<T: Transport>(gsm: &Mutex<Gsm<T>>, expected_iccid: Option<&str>) -> bool {
+    info!("Running hardware self-check…");
+    let mut gsm = gsm.lock().unwrap();
+    let was_on = gsm.is_on();
+    // Turning the modem on unlocks the SIM card too.
+    let started = if was_on { gsm.unlock_sim() } else { gsm.turn_on() };
+    let ok = match started {
+        Ok(()) => {
+            let timeout = Duration::from_secs(SELF_CHECK_REGISTRATION);
+            let connected = match gsm.wait_for_registration(timeout, None)
+                .and_then(|status| {
+                    log_network(&status);
+                    gsm.check_bearer()
+                }) {
+                Ok(()) => {
+                    info!("GPRS bearer check passed.");
+                    true
+                }
+                Err(e) => {
+                    error!("GPRS bearer check failed: {}", e);
+                    false
+                }
+            };
+            // The network time is received while registering.
+            synchronize_clock(&mut gsm);
+            identify(&mut gsm, expected_iccid) && connected
+        }
+        Err(e) => {
+            error!("GSM start-up check failed, there will be no communications: {}", e);
+            false
+        }
+    };
+
+    if !was_on && gsm.is_on() {
+        if let Err(e) = gsm.turn_off() {
+            error!("GSM power-off check failed: {}", e);
+            return false;
+        }
+    }
+    ok
+}
+
+/// Logs the network the modem registered in, warning if it will not be able to use GPRS.
+fn log_network(status: &NetworkStatus) {
+    let operator = status.get_operator()
+        .and_then(|o| o.get_name())
+        .unwrap_or("unknown operator");
+    info!("GSM network: {}{}.",
+          operator,
+          if status.is_roaming() { " (roaming)" } else { "" });
+    if !status.has_gprs() {
+        warn!("GPRS not available in {}, there will be no telemetry.", operator);
+    }
+}
+
+/// Synchronizes the clock with the GSM network time, if the network sent it.
+fn synchronize_clock<T: Transport>(gsm: &mut Gsm<T>) {
+    match gsm.get_network_time() {
+        Ok(now) => {
+            let correction = clock::synchronize(now, TimeSource::Network);
+            info!("Clock synchronized with the GSM network time, corrected by {} ms.",
+                  correction);
+        }
+        Err(e) => warn!("GSM network time unavailable, using the system clock: {}", e),
+    }
+}
+
+/// Records the identity of the modem and its SIM card in the main log and the mission metadata,
+/// checking that the SIM card is the expected one.
+fn identify<T: Transport>(gsm: &mut Gsm<T>, expected_iccid: Option<&str>) -> bool {
+    let info = match gsm.get_modem_info() {
+        Ok(info) => info,
+        Err(e) => {
+            error!("Could not read the modem identity: {}", e);
+            return false;
+        }
+    };
+    info!("Modem identity: {}", info);
+    if let Err(e) = write_metadata(&info) {
+        error!("Error writing the mission metadata: {}", e);
+    }
+
+    match expected_iccid {
+        Some(expected) if !expected.eq_ignore_ascii_case(info.get_iccid()) => {
+            warn!("SIM card ICCID {} does not match the expected {}. Check the SIM card before \
+                   the launch.",
+                  info.get_iccid(),
+                  expected);
+            false
+        }
+        _ => true,
+    }
+}
+
+/// Writes the mission metadata file.
+fn write_metadata(info: &ModemInfo) -> Result<(), io::Error> {
+    let mut file = try!(fs::File::create(METADATA_FILE));
+    try!(writeln!(file, "version = \"{}\"", env!("CARGO_PKG_VERSION")));
+    try!(writeln!(file, "start = \"{}\"", clock::now_utc().rfc3339()));
+    try!(writeln!(file, "time_source = \"{}\"", clock::source()));
+    try!(writeln!(file, "imei = \"{}\"", info.get_imei()));
+    try!(writeln!(file, "iccid = \"{}\"", info.get_iccid()));
+    try!(writeln!(file, "imsi = \"{}\"", info.get_imsi()));
+    try!(writeln!(file, "firmware = \"{}\"", info.get_revision()));
+    try!(writeln!(file, "number = \"{}\"", info.get_number().unwrap_or("")));
+
+    Ok(())
+}
+
 /// Safe mode of OpenStratos
 pub fn safe_mode() {
     // TODO
-    fs::remove_dir_all("data").unwrap()
+    // Pending SMS are kept, so that they can still be sent after the reboot.
+    for entry in fs::read_dir("data").unwrap() {
+        let path = entry.unwrap().path();
+        if path == Path::new(OUTBOX_DIR) {
+            continue;
+        }
+        if path.is_dir() {
+            fs::remove_dir_all(path).unwrap()
+        } else {
+            fs::remove_file(path).unwrap()
+        }
+    }
 }
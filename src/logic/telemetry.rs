@@ -0,0 +1,107 @@
+//! Telemetry upload to the ground server.
+//!
+//! Whenever GPRS is available, the current state, position and battery levels are sent to the
+//! ground server as a JSON document in an HTTP POST request, so that the flight can be followed
+//! live on a map.
+
+use log::LogLevel::*;
+
+use {State, Coordinates};
+use clock;
+use logger::Logger;
+use gsm::{Gsm, GsmError, Transport};
+use gsm::http::HttpResponse;
+
+/// Content type of the telemetry documents.
+const TELEMETRY_CONTENT_TYPE: &'static str = "application/json";
+
+/// Uploader of telemetry to the ground server.
+pub struct Telemetry {
+    url: String,
+    logger: Logger,
+}
+
+impl Telemetry {
+    /// Creates a new uploader sending the telemetry to the given URL, logging to the given
+    /// logger.
+    pub fn new<S: Into<String>>(url: S, logger: Logger) -> Telemetry {
+        Telemetry {
+            url: url.into(),
+            logger: logger,
+        }
+    }
+
+    /// Uploads the current telemetry, returning the response of the ground server.
+    ///
+    /// The position and battery levels are sent as `null` if they cannot be read, since the
+    /// state alone is still useful on the ground.
+    pub fn report<T: Transport>(&mut self,
+                                gsm: &mut Gsm<T>,
+                                state: State)
+                                -> Result<HttpResponse, GsmError> {
+        let batteries = match gsm.get_battery_status() {
+            Ok(batteries) => Some(batteries),
+            Err(e) => {
+                self.logger.log(&format!("Batteries unavailable for telemetry: {}", e), Warn);
+                None
+            }
+        };
+
+        let mut bearer = try!(gsm.open_bearer());
+        let coordinates = match bearer.locate() {
+            Ok(coordinates) => Some(coordinates),
+            Err(e) => {
+                self.logger.log(&format!("Position unavailable for telemetry: {}", e), Warn);
+                None
+            }
+        };
+
+        let document = telemetry_json(state, coordinates, batteries);
+        let response = bearer.http_post(&self.url, TELEMETRY_CONTENT_TYPE, document.as_bytes());
+        if bearer.close().is_err() {
+            self.logger.log("Error turning GPRS down after uploading telemetry.", Error);
+        }
+
+        match response {
+            Ok(response) => {
+                self.logger.log(&format!("Telemetry uploaded with status {} ({} bytes of \
+                                          response): {}",
+                                         response.get_status(),
+                                         response.get_length(),
+                                         document),
+                                if response.is_success() { Info } else { Error });
+                Ok(response)
+            }
+            Err(e) => {
+                self.logger.log(&format!("Error uploading telemetry: {}", e), Error);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Builds the telemetry JSON document.
+///
+/// Batteries are given as `(gsm, main)` charge fractions, as returned by the GSM module.
+fn telemetry_json(state: State,
+                  coordinates: Option<Coordinates>,
+                  batteries: Option<(f64, f64)>)
+                  -> String {
+    let (latitude, longitude) = match coordinates {
+        Some(c) => (format!("{:.6}", c.get_latitude()), format!("{:.6}", c.get_longitude())),
+        None => (String::from("null"), String::from("null")),
+    };
+    let (gsm_battery, main_battery) = match batteries {
+        Some((gsm, main)) => (format!("{:.3}", gsm), format!("{:.3}", main)),
+        None => (String::from("null"), String::from("null")),
+    };
+
+    format!("{{\"time\":\"{}\",\"state\":\"{:?}\",\"latitude\":{},\"longitude\":{},\
+             \"main_battery\":{},\"gsm_battery\":{}}}",
+            clock::now_utc().rfc3339(),
+            state,
+            latitude,
+            longitude,
+            main_battery,
+            gsm_battery)
+}
@@ -0,0 +1,447 @@
+//! Ground command uplink.
+//!
+//! The ground team can send short commands to the probe by SMS. Only messages from the numbers
+//! whitelisted in the configuration are accepted, and each command is only run in the flight
+//! states where it is safe. Every message is deleted from the SIM once handled, accepted or not.
+//!
+//! During the flight and after the landing, the position is also reported to the whitelisted
+//! numbers periodically, at the interval set with `INTERVAL` unless silenced with `SILENCE`.
+
+use std::{io, fmt};
+use std::str::FromStr;
+use std::error::Error as StdError;
+use std::time::{Duration, Instant};
+
+use log::LogLevel::*;
+
+use {State, Coordinates};
+use logger::Logger;
+use gsm::{Gsm, GsmError, ReceivedSms, Transport};
+use gsm::outbox::{Outbox, Priority, MessageKind};
+use gsm::delivery::numbers_match;
+
+/// Default time between position reports, in minutes.
+const DEFAULT_REPORT_INTERVAL: u64 = 15;
+/// Minimum and maximum time between position reports that can be set, in minutes.
+const REPORT_INTERVAL_RANGE: (u64, u64) = (1, 24 * 60);
+/// Maximum time the probe can be silenced for, in seconds.
+const MAX_SILENCE: u64 = 12 * 60 * 60;
+
+/// Command sent from the ground.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// `POS`: reply with the current position.
+    Position,
+    /// `STATUS`: reply with the current state and battery levels.
+    Status,
+    /// `INTERVAL <minutes>`: change the time between position reports.
+    Interval(u64),
+    /// `SILENCE <time>`: stop automatic reports for the given time, such as `2h` or `30m`.
+    Silence(Duration),
+}
+
+impl Command {
+    /// Checks if the command can be run in the given state.
+    ///
+    /// Reports cannot be silenced during the descent, since the landing position must always be
+    /// sent, and nothing but status queries is accepted in safe mode or while shutting down.
+    pub fn is_allowed(&self, state: State) -> bool {
+        match *self {
+            Command::Status => true,
+            Command::Position => state != State::Initializing && state != State::ShutDown,
+            Command::Interval(_) => state != State::SafeMode && state != State::ShutDown,
+            Command::Silence(_) => {
+                !matches!(state, State::GoingDown | State::SafeMode | State::ShutDown)
+            }
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = ParseCommandError;
+    fn from_str(s: &str) -> Result<Command, ParseCommandError> {
+        let s = s.trim().to_uppercase();
+        let mut words = s.split_whitespace();
+        let command = match (words.next(), words.next()) {
+            (Some("POS"), None) => Command::Position,
+            (Some("STATUS"), None) => Command::Status,
+            (Some("INTERVAL"), Some(minutes)) => {
+                match minutes.parse() {
+                    Ok(minutes) if (REPORT_INTERVAL_RANGE.0..=REPORT_INTERVAL_RANGE.1)
+                        .contains(&minutes) => Command::Interval(minutes),
+                    _ => return Err(ParseCommandError::new(&s)),
+                }
+            }
+            (Some("SILENCE"), Some(time)) => {
+                match parse_duration(time) {
+                    Some(time) if time.as_secs() > 0 && time.as_secs() <= MAX_SILENCE => {
+                        Command::Silence(time)
+                    }
+                    _ => return Err(ParseCommandError::new(&s)),
+                }
+            }
+            _ => return Err(ParseCommandError::new(&s)),
+        };
+
+        if words.next().is_some() {
+            Err(ParseCommandError::new(&s))
+        } else {
+            Ok(command)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseCommandError {
+    description: String,
+}
+
+impl ParseCommandError {
+    fn new(s: &str) -> ParseCommandError {
+        ParseCommandError { description: format!("Could not parse {} as a valid command", s) }
+    }
+}
+
+impl fmt::Display for ParseCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.description)
+    }
+}
+
+impl StdError for ParseCommandError {
+    fn description(&self) -> &str {
+        self.description.as_str()
+    }
+}
+
+/// Interpreter of the commands received from the ground.
+pub struct Uplink {
+    logger: Logger,
+    whitelist: Vec<String>,
+    report_interval: Duration,
+    silenced_until: Option<Instant>,
+    last_report: Option<Instant>,
+}
+
+impl Uplink {
+    /// Creates a new uplink accepting commands from the given phone numbers, with the default
+    /// report interval, logging to the given logger.
+    pub fn new(whitelist: &[String], logger: Logger) -> Uplink {
+        Uplink {
+            logger: logger,
+            whitelist: whitelist.to_vec(),
+            report_interval: Duration::from_secs(DEFAULT_REPORT_INTERVAL * 60),
+            silenced_until: None,
+            last_report: None,
+        }
+    }
+
+    /// Gets the time between position reports.
+    pub fn get_report_interval(&self) -> Duration {
+        self.report_interval
+    }
+
+    /// Checks if automatic reports are currently silenced.
+    pub fn is_silenced(&self) -> bool {
+        self.silenced_until.map_or(false, |until| Instant::now() < until)
+    }
+
+    /// Queues a position report for every whitelisted number if the report interval has passed
+    /// since the last one, returning whether a report was queued.
+    ///
+    /// Reports are only sent while flying and once landed. The descent is always reported, even
+    /// if reports were silenced before it started.
+    pub fn report_position<T: Transport>(&mut self,
+                                         gsm: &mut Gsm<T>,
+                                         outbox: &mut Outbox,
+                                         state: State)
+                                         -> Result<bool, io::Error> {
+        if !matches!(state, State::GoingUp | State::GoingDown | State::Landed) ||
+           (self.is_silenced() && state != State::GoingDown) {
+            return Ok(false);
+        }
+        let interval = self.get_report_interval();
+        if let Some(last) = self.last_report {
+            if last.elapsed() < interval {
+                return Ok(false);
+            }
+        }
+        // Failed reports wait for the next interval too, so that GPRS is not retried constantly.
+        self.last_report = Some(Instant::now());
+
+        let report = match gsm.get_coordinates() {
+            Ok(coordinates) => format_position(&coordinates),
+            Err(e) => {
+                self.logger.log(&format!("Position unavailable for the periodic report: {}", e),
+                                Warn);
+                return Ok(false);
+            }
+        };
+        for number in &self.whitelist {
+            try!(outbox.push(number.as_str(),
+                             report.clone(),
+                             Priority::Normal,
+                             MessageKind::Position));
+        }
+        self.logger.log(&format!("Position report queued: {}", report), Info);
+
+        Ok(true)
+    }
+
+    /// Handles all the SMS stored in the modem, queueing the replies to the accepted commands in
+    /// the outbox and deleting every message afterwards.
+    pub fn process<T: Transport>(&mut self,
+                                 gsm: &mut Gsm<T>,
+                                 outbox: &mut Outbox,
+                                 state: State)
+                                 -> Result<(), GsmError> {
+        for sms in try!(gsm.list_sms()) {
+            if let Some((reply, kind)) = self.handle(gsm, &sms, state) {
+                if let Err(e) = outbox.push(sms.get_sender(), reply, Priority::Normal, kind) {
+                    self.logger.log(&format!("Error queueing reply to {}: {}",
+                                             sms.get_sender(),
+                                             e),
+                                    Error);
+                }
+            }
+            try!(gsm.delete_sms(sms.get_index()));
+        }
+
+        Ok(())
+    }
+
+    /// Handles a received SMS, returning the reply to send and its kind, if any.
+    ///
+    /// Messages from numbers outside the whitelist are logged and ignored without a reply. Numbers
+    /// match with or without the international prefix.
+    fn handle<T: Transport>(&mut self,
+                            gsm: &mut Gsm<T>,
+                            sms: &ReceivedSms,
+                            state: State)
+                            -> Option<(String, MessageKind)> {
+        let sender = sms.get_sender();
+        if !self.whitelist.iter().any(|number| numbers_match(number, sender)) {
+            self.logger.log(&format!("Ignoring SMS from non-whitelisted number {} sent at {}: \
+                                      \"{}\"",
+                                     sender,
+                                     sms.get_timestamp(),
+                                     sms.get_text()),
+                            Warn);
+            return None;
+        }
+
+        let command = match sms.get_text().parse::<Command>() {
+            Ok(command) => command,
+            Err(e) => {
+                self.logger.log(&format!("{} (from {}).", e, sender), Warn);
+                return Some((format!("Unknown command: {}", sms.get_text().trim()),
+                             MessageKind::Other));
+            }
+        };
+        self.logger.log(&format!("Command {:?} received from {} at {} in state {:?}.",
+                                 command,
+                                 sender,
+                                 sms.get_timestamp(),
+                                 state),
+                        Info);
+
+        if !command.is_allowed(state) {
+            self.logger.log(&format!("Command {:?} not allowed in state {:?}.", command, state),
+                            Warn);
+            return Some((format!("Command not allowed in state {:?}", state), MessageKind::Other));
+        }
+
+        let reply = match command {
+            Command::Position => {
+                match gsm.get_coordinates() {
+                    Ok(coordinates) => format_position(&coordinates),
+                    Err(e) => format!("Position unavailable: {}", e),
+                }
+            }
+            Command::Status => {
+                match gsm.get_battery_status() {
+                    Ok((gsm_battery, main_battery)) => {
+                        format!("State: {:?}, main battery: {:.0}%, GSM battery: {:.0}%",
+                                state,
+                                main_battery * 100f64,
+                                gsm_battery * 100f64)
+                    }
+                    Err(e) => format!("State: {:?}, batteries unavailable: {}", state, e),
+                }
+            }
+            Command::Interval(minutes) => {
+                self.report_interval = Duration::from_secs(minutes * 60);
+                format!("Report interval set to {} minutes", minutes)
+            }
+            Command::Silence(time) => {
+                self.silenced_until = Some(Instant::now() + time);
+                format!("Reports silenced for {} minutes", time.as_secs() / 60)
+            }
+        };
+        self.logger.log(&format!("Command {:?} handled.", command), Info);
+
+        let kind = if command == Command::Position {
+            MessageKind::Position
+        } else {
+            MessageKind::Other
+        };
+        Some((reply, kind))
+    }
+}
+
+/// Formats a position for a reply or a report.
+fn format_position(coordinates: &Coordinates) -> String {
+    format!("Lat: {:.6}, Lon: {:.6}",
+            coordinates.get_latitude(),
+            coordinates.get_longitude())
+}
+
+/// Parses a duration such as `2h`, `30m` or `90s`. Plain numbers are minutes.
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (number, multiplier) = if let Some(hours) = s.strip_suffix('H') {
+        (hours, 60 * 60)
+    } else if let Some(minutes) = s.strip_suffix('M') {
+        (minutes, 60)
+    } else if let Some(seconds) = s.strip_suffix('S') {
+        (seconds, 1)
+    } else {
+        (s, 60)
+    };
+
+    number.parse::<u64>().ok().and_then(|n| n.checked_mul(multiplier)).map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use std::time::Duration;
+
+    use State;
+    use logger::Logger;
+    use gsm::Gsm;
+    use gsm::emulator::Emulator;
+    use gsm::outbox::{Outbox, MessageKind};
+    use super::*;
+
+    /// Opens an empty outbox in a temporary directory with the given name.
+    fn outbox(name: &str) -> Outbox {
+        let dir = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        let logger = Logger::new(&env::temp_dir().join("Outbox"), "OutboxTest", "Outbox")
+            .unwrap();
+        Outbox::open(dir, logger).unwrap()
+    }
+
+    /// Creates an uplink accepting commands from `+34600000001`.
+    fn uplink() -> Uplink {
+        let logger = Logger::new(&env::temp_dir().join("Uplink"), "UplinkTest", "Uplink")
+            .unwrap();
+        Uplink::new(&[String::from("+34600000001")], logger)
+    }
+
+    #[test]
+    fn command_from_str() {
+        assert_eq!("POS".parse::<Command>().unwrap(), Command::Position);
+        assert_eq!(" status\n".parse::<Command>().unwrap(), Command::Status);
+        assert_eq!("Interval 30".parse::<Command>().unwrap(), Command::Interval(30));
+        assert_eq!("silence 2h".parse::<Command>().unwrap(),
+                   Command::Silence(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!("SILENCE 45".parse::<Command>().unwrap(),
+                   Command::Silence(Duration::from_secs(45 * 60)));
+
+        assert!("".parse::<Command>().is_err());
+        assert!("POS NOW".parse::<Command>().is_err());
+        assert!("INTERVAL".parse::<Command>().is_err());
+        assert!("INTERVAL 0".parse::<Command>().is_err());
+        assert!("INTERVAL 1441".parse::<Command>().is_err());
+        assert!("SILENCE 0M".parse::<Command>().is_err());
+        assert!("SILENCE 13H".parse::<Command>().is_err());
+        assert!("LAND".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(parse_duration("2H"), Some(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse_duration("30M"), Some(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("90S"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("15"), Some(Duration::from_secs(15 * 60)));
+
+        assert_eq!(parse_duration("H"), None);
+        assert_eq!(parse_duration("-1M"), None);
+        assert_eq!(parse_duration("2D"), None);
+        assert_eq!(parse_duration("18446744073709551615H"), None);
+    }
+
+    #[test]
+    fn allowed_commands() {
+        assert!(Command::Status.is_allowed(State::ShutDown));
+        assert!(Command::Status.is_allowed(State::SafeMode));
+        assert!(!Command::Position.is_allowed(State::Initializing));
+        assert!(Command::Position.is_allowed(State::GoingDown));
+        assert!(!Command::Interval(5).is_allowed(State::SafeMode));
+        assert!(Command::Interval(5).is_allowed(State::GoingUp));
+
+        let silence = Command::Silence(Duration::from_secs(60));
+        assert!(silence.is_allowed(State::GoingUp));
+        assert!(silence.is_allowed(State::Landed));
+        assert!(!silence.is_allowed(State::GoingDown));
+        assert!(!silence.is_allowed(State::SafeMode));
+    }
+
+    #[test]
+    fn whitelist() {
+        let mut emulator = Emulator::new();
+        emulator.receive_sms("+34600000009", "STATUS").receive_sms("600000001", "STATUS");
+        let mut gsm = Gsm::simulated(emulator, "");
+        let mut outbox = outbox("UplinkWhitelist");
+        let mut uplink = uplink();
+
+        uplink.process(&mut gsm, &mut outbox, State::Landed).unwrap();
+
+        // Only the whitelisted number, without international prefix, gets a reply.
+        assert_eq!(outbox.pending().len(), 1);
+        assert_eq!(outbox.pending()[0].get_number(), "600000001");
+        assert!(outbox.pending()[0]
+            .get_text()
+            .starts_with("State: Landed, main battery: "));
+        // Every message is deleted, accepted or not.
+        assert!(gsm.list_sms().unwrap().is_empty());
+    }
+
+    #[test]
+    fn forbidden_commands() {
+        let mut emulator = Emulator::new();
+        emulator.receive_sms("+34600000001", "SILENCE 1H")
+            .receive_sms("+34600000001", "INTERVAL 5")
+            .receive_sms("+34600000001", "HELLO");
+        let mut gsm = Gsm::simulated(emulator, "");
+        let mut outbox = outbox("UplinkForbidden");
+        let mut uplink = uplink();
+
+        uplink.process(&mut gsm, &mut outbox, State::GoingDown).unwrap();
+
+        let replies = outbox.pending().iter().map(|m| m.get_text()).collect::<Vec<_>>();
+        assert_eq!(replies,
+                   ["Command not allowed in state GoingDown",
+                    "Report interval set to 5 minutes",
+                    "Unknown command: HELLO"]);
+        assert!(outbox.pending().iter().all(|m| m.get_kind() == MessageKind::Other));
+        assert!(!uplink.is_silenced());
+        assert_eq!(uplink.get_report_interval(), Duration::from_secs(5 * 60));
+    }
+
+    #[test]
+    fn invalid_sms() {
+        let mut emulator = Emulator::new();
+        emulator.receive_sms("+34600000001", "POS");
+        emulator.script("AT+CMGL=4", &["+CMGL: 1,0,,20", "0011", "OK"]);
+        let mut gsm = Gsm::simulated(emulator, "");
+        let mut outbox = outbox("UplinkInvalid");
+        let mut uplink = uplink();
+
+        // The message that cannot be decoded is deleted without a reply.
+        uplink.process(&mut gsm, &mut outbox, State::Landed).unwrap();
+        assert!(outbox.pending().is_empty());
+        assert!(gsm.list_sms().unwrap().is_empty());
+    }
+}
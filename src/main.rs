@@ -3,9 +3,12 @@ extern crate time;
 extern crate log;
 extern crate fern;
 extern crate serial;
+extern crate libc;
 extern crate wiringpi;
 
 mod threads;
+mod config;
+mod clock;
 mod gsm;
 mod logger;
 mod utils;
@@ -18,14 +21,14 @@ use std::{fs, io, fmt};
 use std::io::{Read, Write};
 use std::sync::Mutex;
 
-use logic::*;
-
 const STATE_FILE: &'static str = "data/last_state.txt";
 
 #[derive(Debug)]
 pub enum Error {
     ParseStateError(ParseStateError),
     IOError(io::Error),
+    GsmError(gsm::GsmError),
+    ConfigError(config::ConfigError),
 }
 
 impl From<ParseStateError> for Error {
@@ -40,17 +43,36 @@ impl From<io::Error> for Error {
     }
 }
 
+impl From<gsm::GsmError> for Error {
+    fn from(e: gsm::GsmError) -> Error {
+        Error::GsmError(e)
+    }
+}
+
+impl From<config::ConfigError> for Error {
+    fn from(e: config::ConfigError) -> Error {
+        Error::ConfigError(e)
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.description())
+        match *self {
+            Error::ParseStateError(ref e) => write!(f, "{}", e),
+            Error::IOError(ref e) => write!(f, "{}", e),
+            Error::GsmError(ref e) => write!(f, "{}", e),
+            Error::ConfigError(ref e) => write!(f, "{}", e),
+        }
     }
 }
 
 impl StdError for Error {
     fn description(&self) -> &str {
-        match self {
-            &Error::ParseStateError(ref e) => e.description(),
-            &Error::IOError(ref e) => e.description(),
+        match *self {
+            Error::ParseStateError(_) => "invalid state",
+            Error::IOError(_) => "I/O error",
+            Error::GsmError(_) => "GSM error",
+            Error::ConfigError(_) => "invalid configuration",
         }
     }
 }
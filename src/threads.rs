@@ -1,11 +1,15 @@
 use State;
 
-use gsm::Gsm;
+use gsm::{Gsm, GsmError, Transport};
+use gsm::outbox::Outbox;
+use logic::uplink::Uplink;
+use logic::landing::{send_landing_report, escalate_landing_report, LANDING_ALARM_DELAY};
+use logic::telemetry::Telemetry;
 use logger::Logger;
 
 use std::thread;
 use std::sync::Mutex;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use log::LogLevel;
 
@@ -15,44 +19,225 @@ pub fn system(state: &Mutex<State>) {
     println!("State: '{:?}'", *state);
 }
 
-pub fn battery(state: &Mutex<State>, gsm: &Mutex<Gsm>) {
+pub fn battery<T: Transport>(state: &Mutex<State>, gsm: &Mutex<Gsm<T>>) {
     let mut logger = Logger::new("data/logs/GSM", "Battery", "Battery").unwrap();
+    let mut signal_logger = Logger::new("data/logs/GSM", "Signal", "Signal").unwrap();
+    let mut cell_logger = Logger::new("data/logs/GSM", "Cells", "Cells").unwrap();
 
     while {
         let state = state.lock().unwrap();
         *state != State::ShutDown
     } {
-        let mut gsm = gsm.lock().unwrap();
-        let (main_battery, gsm_battery) = if gsm.is_on() {
-            match gsm.get_battery_status() {
-                Err(e) => {
-                    error!("Error reading battery status! {:?}", e);
-                    thread::sleep(Duration::from_secs(3 * 30));
-                    continue;
-                }
-                Ok((main, gsm)) => (main, gsm),
-            }
-        } else {
+        // If the GSM is off it is only turned on every 15 minutes, to save battery. The lock is
+        // not held while sleeping, so that the uplink can turn it on in the meantime.
+        let is_on = gsm.lock().unwrap().is_on();
+        if !is_on {
             thread::sleep(Duration::from_secs(15 * 60));
+        }
+
+        let current_state = *state.lock().unwrap();
+        let batteries = read_batteries(&mut gsm.lock().unwrap(),
+                                       current_state,
+                                       &mut signal_logger,
+                                       &mut cell_logger);
+        match batteries {
+            Ok((main_battery, gsm_battery)) => {
+                logger.log(format!("[MAIN] {}", main_battery).as_ref(), LogLevel::Info);
+                logger.log(format!("[GSM] {}", gsm_battery).as_ref(), LogLevel::Info);
+            }
+            Err(e) => error!("Error reading battery status! {:?}", e),
+        }
+
+        thread::sleep(Duration::from_secs(3 * 30));
+    }
+}
+
+/// Reads the main and GSM battery charges, in that order, logging the signal quality and, during
+/// the descent, the cells around.
+///
+/// If the GSM is off, it is turned on for the reading, and turned off afterwards unless the given
+/// state needs it on.
+fn read_batteries<T: Transport>(gsm: &mut Gsm<T>,
+                                state: State,
+                                signal_logger: &mut Logger,
+                                cell_logger: &mut Logger)
+                                -> Result<(f64, f64), GsmError> {
+    let turned_on = !gsm.is_on();
+    if turned_on {
+        try!(gsm.turn_on());
+    }
+
+    let batteries = gsm.get_battery_status();
+    if batteries.is_ok() {
+        log_signal_quality(gsm, signal_logger);
+        if state == State::GoingDown {
+            log_cell_info(gsm, cell_logger);
+        }
+    }
+
+    if turned_on && !keeps_gsm_on(state) {
+        if let Err(e) = gsm.turn_off() {
+            error!("Error turning GSM off! {}", e);
+        }
+    }
+
+    batteries.map(|(gsm, main)| (main, gsm))
+}
+
+pub fn uplink<T: Transport>(state: &Mutex<State>,
+                            gsm: &Mutex<Gsm<T>>,
+                            outbox: &Mutex<Outbox>,
+                            whitelist: &[String]) {
+    let logger = gsm.lock().unwrap().open_logger("Uplink").unwrap();
+    let mut ground_link = GroundLink::new(Uplink::new(whitelist, logger), whitelist);
+
+    while {
+        let state = state.lock().unwrap();
+        *state != State::ShutDown
+    } {
+        let current_state = *state.lock().unwrap();
+        ground_link.run(&mut gsm.lock().unwrap(),
+                        &mut outbox.lock().unwrap(),
+                        current_state);
+
+        thread::sleep(Duration::from_secs(60));
+    }
+}
+
+/// Communication with the ground team: commands, reports, pending SMS and the landing alarm.
+struct GroundLink {
+    uplink: Uplink,
+    whitelist: Vec<String>,
+    landing_reported: bool,
+    landing_alarm: Option<Instant>,
+}
 
-            gsm.turn_on();
-            let (main_battery, gsm_battery) = match gsm.get_battery_status() {
-                Err(e) => {
-                    error!("Error reading battery status! {:?}", e);
-                    thread::sleep(Duration::from_secs(3 * 30));
-                    continue;
+impl GroundLink {
+    /// Creates the link with the given uplink, reporting the landing to the given numbers.
+    fn new(uplink: Uplink, whitelist: &[String]) -> GroundLink {
+        GroundLink {
+            uplink: uplink,
+            whitelist: whitelist.to_vec(),
+            landing_reported: false,
+            landing_alarm: None,
+        }
+    }
+
+    /// Runs an iteration of the uplink thread, turning the GSM on first if the state needs it.
+    fn run<T: Transport>(&mut self, gsm: &mut Gsm<T>, outbox: &mut Outbox, state: State) {
+        if !power_gsm(gsm, state) {
+            return;
+        }
+
+        if state == State::Landed && !self.landing_reported {
+            match send_landing_report(gsm, outbox, &self.whitelist) {
+                Ok(_) => {
+                    self.landing_reported = true;
+                    self.landing_alarm = Some(Instant::now() +
+                                              Duration::from_secs(LANDING_ALARM_DELAY));
                 }
-                Ok((main, gsm)) => (main, gsm),
+                Err(e) => error!("Error queueing the landing report! {}", e),
+            }
+        }
+        if let Err(e) = self.uplink.process(gsm, outbox, state) {
+            error!("Error processing uplink commands! {}", e);
+        }
+        if let Err(e) = self.uplink.report_position(gsm, outbox, state) {
+            error!("Error queueing the position report! {}", e);
+        }
+        if let Err(e) = outbox.flush(gsm) {
+            error!("Error sending pending SMS! {}", e);
+        }
+        gsm.update_deliveries();
+        if self.landing_alarm.map_or(false, |due| Instant::now() >= due) {
+            self.landing_alarm = if escalate_landing_report(gsm, outbox, &self.whitelist) {
+                None
+            } else {
+                Some(Instant::now() + Duration::from_secs(LANDING_ALARM_DELAY))
             };
-            gsm.turn_off();
+        }
+        if let Err(e) = gsm.handle_incoming_calls() {
+            error!("Error handling incoming calls! {}", e);
+        }
+    }
+}
 
-            (main_battery, gsm_battery)
-        };
+/// Checks if the GSM must be kept on in the given state: during the descent and once landed the
+/// landing report must get through, and the ground team must be able to reach the probe.
+fn keeps_gsm_on(state: State) -> bool {
+    matches!(state, State::GoingDown | State::Landed)
+}
 
-        logger.log(format!("[MAIN] {}", main_battery).as_ref(), LogLevel::Info);
-        logger.log(format!("[GSM] {}", gsm_battery).as_ref(), LogLevel::Info);
+/// Turns the GSM on if it is off and the given state needs it, returning whether it is on.
+fn power_gsm<T: Transport>(gsm: &mut Gsm<T>, state: State) -> bool {
+    if !gsm.is_on() && keeps_gsm_on(state) {
+        info!("Turning GSM on in state {:?}.", state);
+        if let Err(e) = gsm.turn_on() {
+            error!("Error turning GSM on! {}", e);
+        }
+    }
 
-        thread::sleep(Duration::from_secs(3 * 30));
+    gsm.is_on()
+}
+
+/// Uploads the telemetry to the ground server every `interval`, whenever GPRS is available.
+///
+/// The GSM is turned on first if the state needs it on, so that the descent and the landing site
+/// can be followed on the ground.
+pub fn telemetry<T: Transport>(state: &Mutex<State>,
+                               gsm: &Mutex<Gsm<T>>,
+                               url: &str,
+                               interval: Duration) {
+    let logger = gsm.lock().unwrap().open_logger("Telemetry").unwrap();
+    let mut telemetry = Telemetry::new(url, logger);
+
+    while {
+        let state = state.lock().unwrap();
+        *state != State::ShutDown
+    } {
+        let current_state = *state.lock().unwrap();
+        {
+            let mut gsm = gsm.lock().unwrap();
+            if power_gsm(&mut gsm, current_state) {
+                match gsm.has_gprs_connectivity() {
+                    Ok(true) => {
+                        if let Err(e) = telemetry.report(&mut gsm, current_state) {
+                            error!("Error uploading telemetry! {}", e);
+                        }
+                    }
+                    Ok(false) => debug!("No GPRS connectivity, telemetry not uploaded."),
+                    Err(e) => error!("Error checking GPRS connectivity! {}", e),
+                }
+            }
+        }
+
+        thread::sleep(interval);
+    }
+}
+
+/// Logs the GSM signal quality, to correlate it with the altitude after the flight.
+fn log_signal_quality<T: Transport>(gsm: &mut Gsm<T>, logger: &mut Logger) {
+    match gsm.signal_quality() {
+        Ok(quality) => {
+            let rssi = quality.get_rssi_dbm()
+                .map_or(String::from("unknown"), |rssi| format!("{} dBm", rssi));
+            let ber = quality.get_ber().map_or(String::from("unknown"), |ber| ber.to_string());
+            logger.log(&format!("[RSSI] {} [BER] {}", rssi, ber), LogLevel::Info);
+        }
+        Err(e) => error!("Error reading signal quality! {}", e),
+    }
+}
+
+/// Logs the serving and neighbour cells, to help finding the probe if it cannot be located.
+fn log_cell_info<T: Transport>(gsm: &mut Gsm<T>, logger: &mut Logger) {
+    match gsm.get_cell_info() {
+        Ok(cells) => {
+            for cell in cells {
+                let kind = if cell.is_serving() { "SERVING" } else { "NEIGHBOUR" };
+                logger.log(&format!("[{}] {}", kind, cell), LogLevel::Info);
+            }
+        }
+        Err(e) => error!("Error reading cell information! {}", e),
     }
 }
 
@@ -61,3 +246,90 @@ pub fn pictures(state: &Mutex<State>) {
     let state = state.lock().unwrap();
     println!("State: '{:?}'", *state);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use std::io::Read;
+
+    use State;
+    use logger::Logger;
+    use gsm::Gsm;
+    use gsm::emulator::Emulator;
+    use gsm::outbox::Outbox;
+    use logic::uplink::Uplink;
+    use super::*;
+
+    /// Creates a GSM module talking to a new emulator, turned off.
+    fn gsm_off(emulator: Emulator) -> Gsm<Emulator> {
+        let mut gsm = Gsm::simulated(emulator, "");
+        gsm.turn_off().unwrap();
+        gsm
+    }
+
+    /// Creates a test logger with the given name.
+    fn logger(name: &'static str) -> Logger {
+        Logger::new(&env::temp_dir().join(name), name, name).unwrap()
+    }
+
+    #[test]
+    fn battery_turns_gsm_on_only_for_the_reading() {
+        let mut gsm = gsm_off(Emulator::new());
+        let (mut signal_logger, mut cell_logger) = (logger("SignalTest"), logger("CellsTest"));
+
+        let (main, gsm_battery) =
+            read_batteries(&mut gsm, State::GoingUp, &mut signal_logger, &mut cell_logger)
+                .unwrap();
+        assert!(main > 0f64 && gsm_battery > 0f64);
+        assert!(!gsm.is_on());
+
+        // It is left on once the landing report may be needed.
+        read_batteries(&mut gsm, State::GoingDown, &mut signal_logger, &mut cell_logger)
+            .unwrap();
+        assert!(gsm.is_on());
+    }
+
+    #[test]
+    fn battery_logs_signal_quality() {
+        let mut gsm = Gsm::simulated(Emulator::new(), "");
+        let dir = env::temp_dir().join("SignalLogTest");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut signal_logger = Logger::new(&dir.join("log"), "Signal", "Signal").unwrap();
+
+        read_batteries(&mut gsm, State::GoingUp, &mut signal_logger, &mut logger("CellsTest"))
+            .unwrap();
+        let mut log = String::new();
+        for entry in fs::read_dir(&dir).unwrap() {
+            fs::File::open(entry.unwrap().path()).unwrap().read_to_string(&mut log).unwrap();
+        }
+        assert!(log.contains("[RSSI] -78 dBm [BER] 0"), "{}", log);
+    }
+
+    #[test]
+    fn uplink_turns_gsm_on_after_landing() {
+        let mut emulator = Emulator::new();
+        emulator.receive_sms("+34600000001", "STATUS");
+        let mut gsm = gsm_off(emulator);
+        let dir = env::temp_dir().join("GroundLinkTest");
+        let _ = fs::remove_dir_all(&dir);
+        let mut outbox = Outbox::open(&dir, logger("OutboxTest")).unwrap();
+        let whitelist = [String::from("+34600000001")];
+        let mut ground_link = GroundLink::new(Uplink::new(&whitelist, logger("UplinkTest")),
+                                              &whitelist);
+
+        // The GSM is left off while flying, as the battery thread leaves it.
+        ground_link.run(&mut gsm, &mut outbox, State::GoingUp);
+        assert!(!gsm.is_on());
+        assert!(!ground_link.landing_reported);
+
+        ground_link.run(&mut gsm, &mut outbox, State::Landed);
+        assert!(gsm.is_on());
+        assert!(ground_link.landing_reported);
+        assert!(ground_link.landing_alarm.is_some());
+        // The landing report and the reply to the command were sent, and the command deleted.
+        assert!(outbox.pending().is_empty());
+        assert!(gsm.get_delivery_state("+34600000001").is_some());
+        assert!(gsm.list_sms().unwrap().is_empty());
+    }
+}
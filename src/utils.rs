@@ -1,9 +1,11 @@
 use std::fs;
-use {log, fern, time};
+use {log, fern};
+
+use clock;
 
 pub fn init_logger() {
     let log_path = format!("data/logs/main/OpenStratos.{}.log",
-                           time::now_utc()
+                           clock::now_utc()
                                .strftime("%F.%H-%M-%S")
                                .unwrap());
 
@@ -11,7 +13,7 @@ pub fn init_logger() {
         format: Box::new(|msg: &str, level: &log::LogLevel, _location: &log::LogLocation| {
             format!("[OpenStratos][{}] - {} - {}",
                     level,
-                    time::now_utc().strftime("%D %T.%f").unwrap(),
+                    clock::now_utc().strftime("%D %T.%f").unwrap(),
                     msg)
         }),
         output: if cfg!(feature = "debug") {
@@ -29,6 +31,9 @@ pub fn init_logger() {
     if let Err(e) = fern::init_global_logger(logger_config, log::LogLevelFilter::Trace) {
         panic!("Failed to initialize global logger: {}", e);
     }
+    info!("Log started at {}, time source: {}.",
+          clock::now_utc().rfc3339(),
+          clock::source());
 }
 
 pub fn check_or_create(path: &str) {